@@ -0,0 +1,465 @@
+/*
+
+A batch is a list of operations applied to a project as a single automerge change, written as one JSON object per line:
+
+    {"op": "add", "ref": "$inbox", "content": "Inbox"}
+    {"op": "add", "parent": "$inbox", "content": "aGk=", "content_encoding": "base64", "content_type": "image/png"}
+    {"op": "update", "id": "7KQ2M9XA", "content": "new text", "class": null}
+    {"op": "move", "id": "7KQ2M9XA", "parent": "$inbox", "rank": 3}
+    {"op": "delete", "id": "B00ZZQ41"}
+
+Items are referenced by their exact id or by a placeholder starting with `$` that an earlier add in the same batch
+declared with `ref`. Fields that are left out are left unchanged, while a `null` class or parent clears it. Content is
+utf-8 text unless content_encoding is "base64", matching the json output of the CLI.
+
+Every operation goes through the same Project mutators as any other edit so the same validation applies, and if any
+operation fails the whole batch is rolled back and the error names the operation that failed.
+
+The http api and the rpc mode take a single edit as a json object of the same fields, with the op and id coming from
+the request itself. operation and update_operations turn those fields into operations and apply_edit applies them.
+Their parent can be any reference that au::path::resolve takes, which resolve_parent turns into an id beforehand.
+
+ */
+
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use automerge::{AutoCommit, ChangeHash};
+use base64::Engine;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use time::OffsetDateTime;
+
+use crate::error::AuError;
+use crate::item::{Item, ItemUpdate, Project};
+use crate::path::resolve;
+
+const PLACEHOLDER_PREFIX: char = '$';
+const CONTENT_TYPE_DEFAULT: &str = "text/plain";
+const ENCODING_UTF8: &str = "utf-8";
+const ENCODING_BASE64: &str = "base64";
+const FIELD_PARENT: &str = "parent";
+// MOVE_FIELDS are the fields of an edit that are applied as a move rather than an update.
+const MOVE_FIELDS: &[&str] = &[FIELD_PARENT, "rank"];
+// RESERVED_FIELDS are operation fields that come from the caller and may not appear among the fields of an edit.
+const RESERVED_FIELDS: &[&str] = &["op", "id", "ref"];
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Operation {
+    Add {
+        #[serde(rename = "ref")]
+        placeholder: Option<String>,
+        parent: Option<String>,
+        class: Option<String>,
+        content_type: Option<String>,
+        #[serde(default)]
+        content: String,
+        content_encoding: Option<String>,
+        rank: Option<i64>,
+    },
+    Update {
+        id: String,
+        #[serde(default, deserialize_with = "present")]
+        class: Option<Option<String>>,
+        content_type: Option<String>,
+        content: Option<String>,
+        content_encoding: Option<String>,
+    },
+    Move {
+        id: String,
+        #[serde(default, deserialize_with = "present")]
+        parent: Option<Option<String>>,
+        rank: Option<i64>,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+// BatchResult is the outcome of applying a batch.
+#[derive(Debug, Default)]
+pub struct BatchResult {
+    // change is the hash of the change holding the whole batch, or None if the batch changed nothing.
+    pub change: Option<ChangeHash>,
+    // placeholders maps each declared placeholder to the id generated for it.
+    pub placeholders: BTreeMap<String, Rc<str>>,
+}
+
+// present distinguishes a field set to null (Some(None)) from a field that was left out (None).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+// parse_ndjson reads one operation per line, skipping blank lines.
+pub fn parse_ndjson(reader: impl BufRead) -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+    let mut ops: Vec<Operation> = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let op = serde_json::from_str(line.as_str())
+            .map_err(|e| AuError::InvalidField(Box::from(format!("line {}", i + 1)), Box::from(e.to_string())))?;
+        ops.push(op);
+    }
+    Ok(ops)
+}
+
+// operation builds an operation from the fields of a single edit, as taken by the http api and the rpc mode, and the id
+// of the item it applies to.
+pub fn operation(op: &str, id: Option<&str>, mut fields: Map<String, Value>) -> Result<Operation, Box<dyn std::error::Error>> {
+    if let Some(name) = RESERVED_FIELDS.iter().find(|n| fields.contains_key(**n)) {
+        return Err(Box::new(AuError::InvalidField(
+            Box::from(*name),
+            Box::from("cannot be given with the edit"),
+        )));
+    }
+    fields.insert(String::from("op"), Value::from(op));
+    if let Some(id) = id {
+        fields.insert(String::from("id"), Value::from(id));
+    }
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+// resolve_parent replaces the parent reference in the fields of a single edit with the id of the item it names, or
+// with null if it names the top level.
+pub fn resolve_parent(project: &Project, fields: &mut Map<String, Value>) -> Result<(), Box<dyn std::error::Error>> {
+    let parent = match fields.get(FIELD_PARENT) {
+        Some(Value::String(reference)) => match resolve(project, None, reference)? {
+            Some(item) => Value::from(item.id.as_ref()),
+            None => Value::Null,
+        },
+        _ => return Ok(()),
+    };
+    fields.insert(String::from(FIELD_PARENT), parent);
+    Ok(())
+}
+
+// update_operations builds the operations for an edit that may change any field of the item. The parent and rank go
+// into a move and the rest into an update, which is kept even with no fields so that a missing item is still an error.
+pub fn update_operations(id: &str, mut fields: Map<String, Value>) -> Result<Vec<Operation>, Box<dyn std::error::Error>> {
+    let mut move_fields: Map<String, Value> = Map::new();
+    for name in MOVE_FIELDS {
+        if let Some(value) = fields.remove(*name) {
+            move_fields.insert(name.to_string(), value);
+        }
+    }
+    let mut ops: Vec<Operation> = Vec::new();
+    if !fields.is_empty() || move_fields.is_empty() {
+        ops.push(operation("update", Some(id), fields)?);
+    }
+    if !move_fields.is_empty() {
+        ops.push(operation("move", Some(id), move_fields)?);
+    }
+    Ok(ops)
+}
+
+// apply runs every operation as a single change with the given message. New item ids come from new_id.
+pub fn apply(
+    project: &mut Project,
+    doc: &mut AutoCommit,
+    ops: &[Operation],
+    message: &str,
+    mut new_id: impl FnMut() -> String,
+) -> Result<BatchResult, Box<dyn std::error::Error>> {
+    let mut placeholders: BTreeMap<String, Rc<str>> = BTreeMap::new();
+    let change = project.transact(doc, message, |p, d| {
+        for (i, op) in ops.iter().enumerate() {
+            apply_one(p, d, op, &mut placeholders, &mut new_id)
+                .map_err(|e| AuError::NestedError(Box::from(format!("operation {}", i + 1)), e))?;
+        }
+        Ok(())
+    })?;
+    Ok(BatchResult { change, placeholders })
+}
+
+// apply_edit is apply for operations that make up a single edit as far as the caller is concerned, such as one http
+// request or rpc call, so an error is returned without the operation that failed.
+pub fn apply_edit(
+    project: &mut Project,
+    doc: &mut AutoCommit,
+    ops: &[Operation],
+    message: &str,
+    new_id: impl FnMut() -> String,
+) -> Result<BatchResult, Box<dyn std::error::Error>> {
+    apply(project, doc, ops, message, new_id).map_err(|e| match e.downcast::<AuError>() {
+        Ok(err) => match *err {
+            AuError::NestedError(_, inner) => inner,
+            other => Box::new(other),
+        },
+        Err(e) => e,
+    })
+}
+
+fn apply_one(
+    project: &mut Project,
+    doc: &mut AutoCommit,
+    op: &Operation,
+    placeholders: &mut BTreeMap<String, Rc<str>>,
+    new_id: &mut impl FnMut() -> String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match op {
+        Operation::Add {
+            placeholder,
+            parent,
+            class,
+            content_type,
+            content,
+            content_encoding,
+            rank,
+        } => {
+            let parent = parent.as_deref().map(|p| lookup(project, placeholders, p)).transpose()?;
+            let item = Item {
+                id: Rc::from(new_id()),
+                at: OffsetDateTime::now_utc(),
+                class: class.as_deref().map(Rc::from),
+                content_type: Rc::from(content_type.as_deref().unwrap_or(CONTENT_TYPE_DEFAULT)),
+                content: Rc::from(decode_content(content, content_encoding.as_deref())?),
+                rank: rank.unwrap_or_else(|| project.bottom_rank(parent.as_deref())),
+                parent,
+            };
+            if let Some(name) = placeholder {
+                if !name.starts_with(PLACEHOLDER_PREFIX) {
+                    return Err(Box::new(AuError::InvalidField(
+                        Box::from("ref"),
+                        Box::from(format!("must start with {}", PLACEHOLDER_PREFIX)),
+                    )));
+                } else if placeholders.contains_key(name) {
+                    return Err(Box::new(AuError::InvalidField(
+                        Box::from("ref"),
+                        Box::from("duplicate placeholder"),
+                    )));
+                }
+                placeholders.insert(name.clone(), item.id.clone());
+            }
+            project.with_item(&item, doc)?;
+        }
+        Operation::Update {
+            id,
+            class,
+            content_type,
+            content,
+            content_encoding,
+        } => {
+            let id = lookup(project, placeholders, id)?;
+            let mut updates: Vec<ItemUpdate> = Vec::new();
+            if let Some(class) = class {
+                updates.push(ItemUpdate::Class(class.as_deref().map(Box::from)));
+            }
+            if content.is_some() || content_type.is_some() {
+                let item = project.get_item(&id).ok_or_else(|| AuError::NoSuchKey(Box::from(id.as_ref())))?;
+                let new_content = match content {
+                    Some(c) => decode_content(c, content_encoding.as_deref())?,
+                    None => item.content.to_vec(),
+                };
+                let new_type = content_type.as_deref().unwrap_or(item.content_type.as_ref());
+                updates.push(ItemUpdate::Content(Box::from(new_type), Box::from(new_content)));
+            }
+            project.with_updated_item(&id, &updates, doc)?;
+        }
+        Operation::Move { id, parent, rank } => {
+            let id = lookup(project, placeholders, id)?;
+            let mut updates: Vec<ItemUpdate> = Vec::new();
+            if let Some(parent) = parent {
+                let parent = parent.as_deref().map(|p| lookup(project, placeholders, p)).transpose()?;
+                updates.push(ItemUpdate::Parent(parent.as_deref().map(Box::from)));
+            }
+            if let Some(rank) = rank {
+                updates.push(ItemUpdate::Rank(*rank));
+            }
+            project.with_updated_item(&id, &updates, doc)?;
+        }
+        Operation::Delete { id } => {
+            let id = lookup(project, placeholders, id)?;
+            project.without_item(&id, doc)?;
+        }
+    }
+    Ok(())
+}
+
+// lookup turns a placeholder or id into the id of an existing item.
+fn lookup(project: &Project, placeholders: &BTreeMap<String, Rc<str>>, reference: &str) -> Result<Rc<str>, Box<dyn std::error::Error>> {
+    if reference.starts_with(PLACEHOLDER_PREFIX) {
+        placeholders
+            .get(reference)
+            .cloned()
+            .ok_or_else(|| Box::new(AuError::NoSuchKey(Box::from(reference))) as Box<dyn std::error::Error>)
+    } else {
+        project
+            .get_item(reference)
+            .map(|i| i.id.clone())
+            .ok_or_else(|| Box::new(AuError::NoSuchKey(Box::from(reference))) as Box<dyn std::error::Error>)
+    }
+}
+
+fn decode_content(content: &str, encoding: Option<&str>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match encoding.unwrap_or(ENCODING_UTF8) {
+        ENCODING_UTF8 => Ok(content.as_bytes().to_vec()),
+        ENCODING_BASE64 => Ok(base64::engine::general_purpose::STANDARD.decode(content)?),
+        other => Err(Box::new(AuError::InvalidField(
+            Box::from("content_encoding"),
+            Box::from(format!("unknown encoding {}", other)),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge::AutoCommit;
+
+    use serde_json::{json, Map, Value};
+
+    use crate::batch::{apply, apply_edit, operation, parse_ndjson, resolve_parent, update_operations, Operation};
+    use crate::item::{decode_project, init_project};
+
+    fn counter() -> impl FnMut() -> String {
+        let mut n = 0;
+        move || {
+            n += 1;
+            format!("ID{}", n)
+        }
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let input = "{\"op\": \"delete\", \"id\": \"A\"}\n\n{\"op\": \"move\", \"id\": \"B\", \"parent\": null}\n{\"op\": \"move\", \"id\": \"C\"}\n";
+        let ops = parse_ndjson(input.as_bytes()).unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                Operation::Delete { id: String::from("A") },
+                Operation::Move {
+                    id: String::from("B"),
+                    parent: Some(None),
+                    rank: None
+                },
+                Operation::Move {
+                    id: String::from("C"),
+                    parent: None,
+                    rank: None
+                },
+            ]
+        );
+        let err = parse_ndjson("{\"op\": \"delete\"}\n{\"op\": \"explode\"}".as_bytes())
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("'line 1': invalid: missing field `id`"), "{}", err);
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let input = "
+            {\"op\": \"add\", \"ref\": \"$inbox\", \"content\": \"Inbox\"}
+            {\"op\": \"add\", \"ref\": \"$a\", \"parent\": \"$inbox\", \"content\": \"a\", \"class\": \"todo\"}
+            {\"op\": \"add\", \"ref\": \"$b\", \"content\": \"AAEC\", \"content_encoding\": \"base64\", \"content_type\": \"application/octet-stream\"}
+            {\"op\": \"update\", \"id\": \"$a\", \"content\": \"a edited\", \"class\": null}
+            {\"op\": \"move\", \"id\": \"$b\", \"parent\": \"$inbox\", \"rank\": 4}
+            {\"op\": \"add\", \"ref\": \"$c\", \"content\": \"c\"}
+            {\"op\": \"delete\", \"id\": \"$c\"}
+        ";
+        let ops = parse_ndjson(input.as_bytes()).unwrap();
+        let changes_before = doc.get_changes(&[]).len();
+        let result = apply(&mut project, &mut doc, &ops, "reorganise", counter()).unwrap();
+        assert_eq!(doc.get_changes(&[]).len(), changes_before + 1);
+        assert_eq!(result.placeholders.get("$b").map(|s| s.as_ref()), Some("ID3"));
+
+        let project = decode_project(doc.document()).unwrap();
+        let children = project.list_children(Some("ID1"));
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].id.as_ref(), "ID3");
+        assert_eq!(children[0].content.as_ref(), &[0u8, 1, 2]);
+        assert_eq!(children[1].content.as_ref(), "a edited".as_bytes());
+        assert_eq!(children[1].class, None);
+        assert!(project.get_item("ID4").is_none());
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let heads = doc.get_heads();
+        let input = "
+            {\"op\": \"add\", \"ref\": \"$a\", \"content\": \"a\"}
+            {\"op\": \"add\", \"ref\": \"$b\", \"parent\": \"$a\", \"content\": \"b\"}
+            {\"op\": \"move\", \"id\": \"$a\", \"parent\": \"$b\"}
+        ";
+        let ops = parse_ndjson(input.as_bytes()).unwrap();
+        let err = apply(&mut project, &mut doc, &ops, "broken", counter()).err().unwrap();
+        assert_eq!(err.to_string(), "'operation 3': 'ID2': has a cycle");
+        assert_eq!(doc.get_heads(), heads);
+        assert!(project.list_items().is_empty());
+
+        let ops = parse_ndjson("{\"op\": \"delete\", \"id\": \"$missing\"}".as_bytes()).unwrap();
+        let err = apply(&mut project, &mut doc, &ops, "broken", counter()).err().unwrap();
+        assert_eq!(err.to_string(), "'operation 1': '$missing': no such key");
+    }
+
+    #[test]
+    fn test_edit_operations() {
+        let fields = |v: Value| -> Map<String, Value> { serde_json::from_value(v).unwrap() };
+        assert_eq!(
+            operation("move", Some("A"), fields(json!({"parent": null}))).unwrap(),
+            Operation::Move {
+                id: String::from("A"),
+                parent: Some(None),
+                rank: None
+            }
+        );
+        assert!(operation("add", None, fields(json!({"ref": "$a"}))).is_err());
+        assert!(operation("delete", Some("A"), fields(json!({"id": "B"}))).is_err());
+
+        let ops = update_operations("A", fields(json!({"class": "todo", "rank": 2}))).unwrap();
+        assert_eq!(
+            ops,
+            vec![
+                Operation::Update {
+                    id: String::from("A"),
+                    class: Some(Some(String::from("todo"))),
+                    content_type: None,
+                    content: None,
+                    content_encoding: None
+                },
+                Operation::Move {
+                    id: String::from("A"),
+                    parent: None,
+                    rank: Some(2)
+                },
+            ]
+        );
+        assert!(matches!(
+            update_operations("A", Map::new()).unwrap().as_slice(),
+            [Operation::Update { .. }]
+        ));
+        assert!(matches!(
+            update_operations("A", fields(json!({"parent": "B"}))).unwrap().as_slice(),
+            [Operation::Move { .. }]
+        ));
+
+        // a single edit fails without naming the operation
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let err = apply_edit(&mut project, &mut doc, &ops, "edit", counter()).err().unwrap();
+        assert_eq!(err.to_string(), "'A': no such key");
+
+        // parents can be given as any reference to an item
+        let ops = parse_ndjson("{\"op\": \"add\", \"content\": \"Inbox\"}".as_bytes()).unwrap();
+        apply(&mut project, &mut doc, &ops, "add", counter()).unwrap();
+        let resolved = |v: Value| -> Result<Map<String, Value>, String> {
+            let mut f = fields(v);
+            resolve_parent(&project, &mut f).map_err(|e| e.to_string())?;
+            Ok(f)
+        };
+        assert_eq!(resolved(json!({"parent": "inbox"})), Ok(fields(json!({"parent": "ID1"}))));
+        assert_eq!(resolved(json!({"parent": "/"})), Ok(fields(json!({"parent": null}))));
+        assert_eq!(resolved(json!({"rank": 1})), Ok(fields(json!({"rank": 1}))));
+        assert!(resolved(json!({"parent": "missing"})).is_err());
+    }
+}
@@ -10,6 +10,10 @@ pub enum AuError {
     InvalidField(Box<str>, Box<str>),
     #[error("'{0}': {1}")]
     InvalidOperation(Box<str>, Box<str>),
+    #[error("'{0}': has a cycle")]
+    Cycle(Box<str>),
+    #[error("'{0}': ambiguous, matches {1} keys")]
+    Ambiguous(Box<str>, usize),
     #[error("'{0}': {1}")]
     NestedError(Box<str>, Box<dyn std::error::Error>),
 }
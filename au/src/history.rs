@@ -0,0 +1,251 @@
+/*
+
+History reconstructs what happened to the items of a project from the automerge change graph. Each change is compared
+against the project at its own dependencies rather than at the change applied before it, so concurrent changes from
+different actors are each summarised against what that actor saw when making them.
+
+ */
+
+use std::rc::Rc;
+
+use automerge::{Automerge, ChangeHash};
+
+use crate::item::{decode_project_at, Item, Project};
+
+// ItemDiff is the difference of a single item between two versions of a project.
+pub enum ItemDiff {
+    Added(Rc<Item>),
+    Deleted(Rc<Item>),
+    Updated(Rc<Item>, Rc<Item>),
+}
+
+impl ItemDiff {
+    pub fn id(&self) -> &str {
+        match self {
+            ItemDiff::Added(i) | ItemDiff::Deleted(i) | ItemDiff::Updated(i, _) => i.id.as_ref(),
+        }
+    }
+}
+
+// ChangeSummary lists the ids of the items touched by a change. An item that was both moved and modified appears in
+// both moved and updated.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ChangeSummary {
+    pub added: Vec<Rc<str>>,
+    pub updated: Vec<Rc<str>>,
+    pub moved: Vec<Rc<str>>,
+    pub deleted: Vec<Rc<str>>,
+}
+
+impl ChangeSummary {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.moved.is_empty() && self.deleted.is_empty()
+    }
+}
+
+pub struct LogEntry {
+    pub hash: ChangeHash,
+    pub actor: Box<str>,
+    pub seq: u64,
+    // timestamp is in unix seconds as recorded by the author, it is not guaranteed to be ordered.
+    pub timestamp: i64,
+    pub message: Option<Box<str>>,
+    pub summary: ChangeSummary,
+}
+
+// is_moved is true if the item has a different position in the hierarchy.
+pub fn is_moved(before: &Item, after: &Item) -> bool {
+    before.parent != after.parent || before.rank != after.rank
+}
+
+// is_modified is true if anything other than the position of the item has changed.
+pub fn is_modified(before: &Item, after: &Item) -> bool {
+    before.class != after.class || before.content_type != after.content_type || before.content != after.content || before.at != after.at
+}
+
+// diff_projects returns the differences between two versions of a project ordered by id.
+pub fn diff_projects(before: &Project, after: &Project) -> Vec<ItemDiff> {
+    let mut out: Vec<ItemDiff> = Vec::new();
+    for item in before.list_items() {
+        match after.get_item(item.id.as_ref()) {
+            None => out.push(ItemDiff::Deleted(item)),
+            Some(other) => {
+                if is_moved(&item, &other) || is_modified(&item, &other) {
+                    out.push(ItemDiff::Updated(item, other))
+                }
+            }
+        }
+    }
+    for item in after.list_items() {
+        if before.get_item(item.id.as_ref()).is_none() {
+            out.push(ItemDiff::Added(item));
+        }
+    }
+    out.sort_by(|a, b| a.id().cmp(b.id()));
+    out
+}
+
+// summarise groups the differences into the ids that were added, updated, moved or deleted.
+pub fn summarise(diffs: &[ItemDiff]) -> ChangeSummary {
+    let mut summary = ChangeSummary::default();
+    for diff in diffs {
+        match diff {
+            ItemDiff::Added(i) => summary.added.push(i.id.clone()),
+            ItemDiff::Deleted(i) => summary.deleted.push(i.id.clone()),
+            ItemDiff::Updated(before, after) => {
+                if is_modified(before, after) {
+                    summary.updated.push(after.id.clone());
+                }
+                if is_moved(before, after) {
+                    summary.moved.push(after.id.clone());
+                }
+            }
+        }
+    }
+    summary
+}
+
+// diff_heads returns the differences between the project at two sets of heads.
+pub fn diff_heads(source: &Automerge, before: &[ChangeHash], after: &[ChangeHash]) -> Result<Vec<ItemDiff>, Box<dyn std::error::Error>> {
+    Ok(diff_projects(
+        &decode_project_at(source, before)?,
+        &decode_project_at(source, after)?,
+    ))
+}
+
+// log returns an entry for every change in the document, newest first in causal order, or only for the newest limit
+// changes. Only the changes that are returned are summarised.
+pub fn log(source: &Automerge, limit: Option<usize>) -> Result<Vec<LogEntry>, Box<dyn std::error::Error>> {
+    let mut out: Vec<LogEntry> = Vec::new();
+    for change in source.get_changes(&[]).into_iter().rev().take(limit.unwrap_or(usize::MAX)) {
+        let diffs = diff_heads(source, change.deps(), &[change.hash()])?;
+        out.push(LogEntry {
+            hash: change.hash(),
+            actor: Box::from(change.actor_id().to_hex_string()),
+            seq: change.seq(),
+            timestamp: change.timestamp(),
+            message: change.message().map(|m| Box::from(m.as_str())),
+            summary: summarise(&diffs),
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use automerge::AutoCommit;
+
+    use crate::history::{diff_heads, log, ChangeSummary, ItemDiff};
+    use crate::item::{init_project, Item, ItemUpdate};
+
+    fn new_item(id: &str, parent: Option<&str>) -> Item {
+        Item {
+            id: Rc::from(id),
+            content: Rc::from(format!("{} content", id).as_bytes()),
+            parent: parent.map(Rc::from),
+            ..Default::default()
+        }
+    }
+
+    fn ids(v: &[&str]) -> Vec<Rc<str>> {
+        v.iter().map(|s| Rc::from(*s)).collect()
+    }
+
+    #[test]
+    fn test_log() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        project
+            .transact(&mut doc, "add a", |p, d| p.with_item(&new_item("item-a", None), d).map(|_| ()))
+            .unwrap();
+        project
+            .transact(&mut doc, "add b", |p, d| p.with_item(&new_item("item-b", None), d).map(|_| ()))
+            .unwrap();
+        project
+            .transact(&mut doc, "move b", |p, d| {
+                p.with_updated_item("item-b", &[ItemUpdate::Parent(Some(Box::from("item-a")))], d)
+                    .map(|_| ())
+            })
+            .unwrap();
+        project
+            .transact(&mut doc, "edit a", |p, d| {
+                let content = ItemUpdate::Content(Box::from("text/plain"), Box::from("new".as_bytes()));
+                p.with_updated_item("item-a", &[content], d).map(|_| ())
+            })
+            .unwrap();
+        project
+            .transact(&mut doc, "remove b", |p, d| p.without_item("item-b", d).map(|_| ()))
+            .unwrap();
+
+        let entries = log(doc.document(), None).unwrap();
+        let summaries: Vec<&ChangeSummary> = entries.iter().map(|e| &e.summary).collect();
+        assert_eq!(
+            summaries,
+            vec![
+                &ChangeSummary {
+                    deleted: ids(&["item-b"]),
+                    ..Default::default()
+                },
+                &ChangeSummary {
+                    updated: ids(&["item-a"]),
+                    ..Default::default()
+                },
+                &ChangeSummary {
+                    moved: ids(&["item-b"]),
+                    ..Default::default()
+                },
+                &ChangeSummary {
+                    added: ids(&["item-b"]),
+                    ..Default::default()
+                },
+                &ChangeSummary {
+                    added: ids(&["item-a"]),
+                    ..Default::default()
+                },
+                // initialising the project touches no items
+                &ChangeSummary::default(),
+            ]
+        );
+        assert_eq!(entries[0].message.as_deref(), Some("remove b"));
+        assert!(entries[0].timestamp > 0);
+        assert_eq!(entries[0].actor.as_ref(), doc.get_actor().to_hex_string());
+        assert_eq!(entries[0].seq as usize, entries.len());
+
+        let newest = log(doc.document(), Some(2)).unwrap();
+        assert_eq!(
+            newest.iter().map(|e| e.hash).collect::<Vec<_>>(),
+            entries[..2].iter().map(|e| e.hash).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_diff_heads() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        project.with_item(&new_item("item-a", None), &mut doc).unwrap();
+        project.with_item(&new_item("item-b", None), &mut doc).unwrap();
+        let before = doc.get_heads();
+        project.with_item(&new_item("item-c", None), &mut doc).unwrap();
+        project.without_item("item-a", &mut doc).unwrap();
+        project.with_updated_item("item-b", &[ItemUpdate::Rank(3)], &mut doc).unwrap();
+        let after = doc.get_heads();
+
+        let diffs = diff_heads(doc.document(), &before, &after).unwrap();
+        let kinds: Vec<(&str, &str)> = diffs
+            .iter()
+            .map(|d| match d {
+                ItemDiff::Added(i) => ("added", i.id.as_ref()),
+                ItemDiff::Deleted(i) => ("deleted", i.id.as_ref()),
+                ItemDiff::Updated(_, i) => ("updated", i.id.as_ref()),
+            })
+            .collect();
+        assert_eq!(kinds, vec![("deleted", "item-a"), ("updated", "item-b"), ("added", "item-c")]);
+
+        // the empty heads are the empty project
+        assert_eq!(diff_heads(doc.document(), &[], &before).unwrap().len(), 2);
+        let missing = [automerge::ChangeHash([7; 32])];
+        assert!(diff_heads(doc.document(), &missing, &after).is_err());
+    }
+}
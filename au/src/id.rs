@@ -30,9 +30,9 @@ mod tests {
     #[test]
     fn test_gen() {
         let g = IdGen::default();
-        let mtng = rand::thread_rng();
-        assert!(g.gen(mtng).len() >= 8);
-        assert_ne!(g.gen(mtng), g.gen(mtng));
+        let mut mtng = rand::thread_rng();
+        assert!(g.gen(&mut mtng).len() >= 8);
+        assert_ne!(g.gen(&mut mtng), g.gen(&mut mtng));
     }
 
 }
@@ -4,9 +4,9 @@ use std::fmt::Debug;
 use std::iter;
 use std::rc::Rc;
 
-use automerge::transaction::Transactable;
+use automerge::transaction::{CommitOptions, Transactable};
 use automerge::ReadDoc;
-use automerge::{AutoCommit, Automerge, ObjType, ScalarValue, Value};
+use automerge::{AutoCommit, Automerge, ChangeHash, ObjType, ScalarValue, Value};
 use serde::{Deserialize, Serialize};
 use smol_str::SmolStr;
 use time::OffsetDateTime;
@@ -54,15 +54,17 @@ impl Item {
                         Some(s) => &as_str[..s],
                         None => as_str,
                     };
-                    return if first_line.len() <= width {
+                    // width counts characters rather than bytes so that multibyte text is never cut mid-character
+                    let end = |n: usize| first_line.char_indices().nth(n).map_or(first_line.len(), |(i, _)| i);
+                    return if first_line.chars().count() <= width {
                         Box::from(first_line)
                     } else if width < 3 {
-                        Box::from(&first_line[..width])
+                        Box::from(&first_line[..end(width)])
                     } else {
-                        let mut s = String::from(&first_line[..width-3]);
+                        let mut s = String::from(&first_line[..end(width - 3)]);
                         s.push_str("...");
                         Box::from(s.as_str())
-                    }
+                    };
                 }
             }
             Box::from(format!("(text {} file of {} bytes)", self.content_type, self.content.len()))
@@ -91,6 +93,9 @@ pub enum ItemUpdate {
     Rank(i64),
     Class(Option<Box<str>>),
     Content(Box<str>, Box<[u8]>),
+    // Splice deletes a number of characters at a character index of text content and inserts the text in their place.
+    // Characters are unicode scalar values, which is how automerge indexes text.
+    Splice(usize, usize, Box<str>),
 }
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
@@ -99,6 +104,35 @@ pub struct Project {
 }
 
 impl Project {
+    // transact runs several mutations as a single change committed with the message and the current time. Any earlier
+    // uncommitted mutations are committed first as their own change. If the mutations fail both the document and the
+    // project are returned to their state before the call. Returns the hash of the new change, or None if the mutations
+    // did nothing.
+    pub fn transact<F>(&mut self, doc: &mut AutoCommit, message: &str, f: F) -> Result<Option<ChangeHash>, Box<dyn std::error::Error>>
+    where
+        F: FnOnce(&mut Project, &mut AutoCommit) -> Result<(), Box<dyn std::error::Error>>,
+    {
+        doc.commit();
+        let heads = doc.get_heads();
+        let saved = self.clone();
+        if let Err(e) = f(self, doc) {
+            doc.rollback();
+            // the mutations may have closed the transaction themselves, so anything already committed is dropped by
+            // going back to the old heads under the same actor
+            if doc.get_heads() != heads {
+                let mut restored = doc.fork_at(&heads)?;
+                restored.set_actor(doc.get_actor().clone());
+                *doc = restored;
+            }
+            *self = saved;
+            return Err(e);
+        }
+        let options = CommitOptions::default()
+            .with_message(message)
+            .with_time(OffsetDateTime::now_utc().unix_timestamp());
+        Ok(doc.commit_with(options))
+    }
+
     pub fn with_item(&mut self, item: &Item, doc: &mut AutoCommit) -> Result<&mut Project, Box<dyn std::error::Error>> {
         if item.id.is_empty() {
             return Err(Box::new(AuError::InvalidField(Box::from(DOC_ITEM_ID_NODE), Box::from("empty"))));
@@ -115,7 +149,7 @@ impl Project {
                 )));
             }
         }
-        let items_node = match find_items_node(doc.document()) {
+        let items_node = match find_items_node(doc) {
             Ok(n) => n,
             Err(_) => doc.put_object(automerge::ROOT, DOC_ITEMS_NODE, ObjType::Map)?,
         };
@@ -155,7 +189,7 @@ impl Project {
         } else if self.has_children(Some(id)) {
             return Err(Box::new(AuError::InvalidOperation(Box::from(id), Box::from("has children"))));
         }
-        let items_node = match find_items_node(doc.document()) {
+        let items_node = match find_items_node(doc) {
             Ok(n) => n,
             Err(_) => doc.put_object(automerge::ROOT, DOC_ITEMS_NODE, ObjType::Map)?,
         };
@@ -177,11 +211,11 @@ impl Project {
             None => return Err(Box::new(AuError::NoSuchKey(Box::from(id.as_ref())))),
         };
 
-        let items_node = match find_items_node(doc.document()) {
+        let items_node = match find_items_node(doc) {
             Ok(n) => n,
             Err(_) => doc.put_object(automerge::ROOT, DOC_ITEMS_NODE, ObjType::Map)?,
         };
-        let item_node = match doc.document().get(items_node, id) {
+        let item_node = match doc.get(items_node, id) {
             Ok(Some((Value::Object(ObjType::Map), n))) => n,
             Ok(Some(_)) => return Err(Box::new(AuError::IncorrectType(Box::from(id), Box::from("map")))),
             Ok(None) => return Err(Box::new(AuError::NoSuchKey(Box::from(id)))),
@@ -202,7 +236,10 @@ impl Project {
                 // Updating the parent to a real value requires checking that the target exists
                 // and that there are no cycles and then performing the update
                 ItemUpdate::Parent(Some(ref new_parent)) => {
-                    // cycle detect
+                    // cycle detect, an item can't be its own parent either
+                    if new_parent.as_ref().eq(id) {
+                        return Err(Box::new(AuError::Cycle(new_parent.clone())));
+                    }
                     let mut current_item_id: Box<str> = new_parent.clone();
                     loop {
                         match self.children.get(current_item_id.as_ref()) {
@@ -213,7 +250,7 @@ impl Project {
                                     None => break,
                                     Some(p) => {
                                         if p.as_ref().eq(id) {
-                                            return Err(Box::new(AuError::InvalidOperation(new_parent.clone(), Box::from("has a cycle"))));
+                                            return Err(Box::new(AuError::Cycle(new_parent.clone())));
                                         }
                                         current_item_id = Box::from(p.as_ref())
                                     }
@@ -260,19 +297,27 @@ impl Project {
                             match doc.get(&item_node, DOC_ITEM_CONTENT_NODE) {
                                 Ok(Some((Value::Object(ObjType::Text), node))) => match doc.text(&node) {
                                     Ok(old_content_str) => {
-                                        // calculate the slice partition
-                                        let common_prefix_length = common_prefix(new_content_str.as_bytes(), old_content_str.as_bytes());
-                                        let common_suffix_length = common_suffix(
+                                        // calculate the slice partition, the common parts are measured in bytes but
+                                        // must end on character boundaries since automerge indexes by character
+                                        let mut common_prefix_length =
+                                            common_prefix(new_content_str.as_bytes(), old_content_str.as_bytes());
+                                        while !new_content_str.is_char_boundary(common_prefix_length) {
+                                            common_prefix_length -= 1;
+                                        }
+                                        let mut common_suffix_length = common_suffix(
                                             new_content_str[common_prefix_length..].as_bytes(),
                                             old_content_str[common_prefix_length..].as_bytes(),
                                         );
+                                        while !new_content_str.is_char_boundary(new_content_str.len() - common_suffix_length) {
+                                            common_suffix_length -= 1;
+                                        }
                                         if common_prefix_length > 0 || common_suffix_length > 0 {
-                                            let del_len = old_content_str.len() - common_prefix_length - common_suffix_length;
+                                            let old_end = old_content_str.len() - common_suffix_length;
                                             let new_end = new_content_str.len() - common_suffix_length;
                                             doc.splice_text(
                                                 node,
-                                                common_prefix_length,
-                                                del_len as isize,
+                                                new_content_str[..common_prefix_length].chars().count(),
+                                                old_content_str[common_prefix_length..old_end].chars().count() as isize,
                                                 &new_content_str[common_prefix_length..new_end],
                                             )?
                                         } else {
@@ -298,6 +343,30 @@ impl Project {
                         }
                     }
                 }
+                // Splicing edits text content in place so that concurrent edits from other actors merge by character
+                ItemUpdate::Splice(index, delete, insert) => {
+                    if !new_item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) {
+                        return Err(Box::new(AuError::InvalidOperation(Box::from(id), Box::from("content is not text"))));
+                    }
+                    let node = match doc.get(&item_node, DOC_ITEM_CONTENT_NODE)? {
+                        Some((Value::Object(ObjType::Text), node)) => node,
+                        // content stored some other way becomes a text node first
+                        _ => {
+                            let node = doc.put_object(&item_node, DOC_ITEM_CONTENT_NODE, ObjType::Text)?;
+                            doc.update_text(&node, std::str::from_utf8(new_item.content.as_ref())?)?;
+                            node
+                        }
+                    };
+                    let length = doc.text(&node)?.chars().count();
+                    if *index > length || *delete > length - *index {
+                        return Err(Box::new(AuError::InvalidField(
+                            Box::from(DOC_ITEM_CONTENT_NODE),
+                            Box::from(format!("splice of {} at {} is outside {} characters", delete, index, length)),
+                        )));
+                    }
+                    doc.splice_text(&node, *index, *delete as isize, insert)?;
+                    new_item.content = Rc::from(doc.text(&node)?.into_bytes());
+                }
             }
         }
         // insert the new child node
@@ -324,6 +393,37 @@ impl Project {
         return self.children.get(id).map(|t| t.clone());
     }
 
+    // resolve_id finds the item with exactly the given id or failing that the only item whose id starts with the given
+    // prefix, ignoring ascii case so that generated ids can be typed in lower case.
+    pub fn resolve_id(&self, prefix: &str) -> Result<Rc<Item>, Box<dyn std::error::Error>> {
+        if let Some(item) = self.get_item(prefix) {
+            return Ok(item);
+        }
+        let matches: Vec<&Rc<Item>> = self
+            .children
+            .iter()
+            .filter(|(k, _)| !prefix.is_empty() && k.get(..prefix.len()).is_some_and(|p| p.eq_ignore_ascii_case(prefix)))
+            .map(|(_, v)| v)
+            .collect();
+        match matches.as_slice() {
+            [] => Err(Box::new(AuError::NoSuchKey(Box::from(prefix)))),
+            [item] => Ok((*item).clone()),
+            _ => Err(Box::new(AuError::Ambiguous(Box::from(prefix), matches.len()))),
+        }
+    }
+
+    // bottom_rank is the rank that places a new item below all of the existing children of the parent.
+    pub fn bottom_rank(&self, parent: Option<&str>) -> i64 {
+        self.list_children(parent).last().map_or(0, |i| i.rank.saturating_sub(1))
+    }
+
+    // list_items returns every item in the project regardless of parent, ordered by id.
+    pub fn list_items(&self) -> Vec<Rc<Item>> {
+        let mut out: Vec<Rc<Item>> = self.children.values().cloned().collect();
+        out.sort_by(|a, b| a.id.cmp(&b.id));
+        out
+    }
+
     pub fn list_children(&self, parent: Option<&str>) -> Vec<Rc<Item>> {
         let mut out: Vec<Rc<Item>> = Vec::new();
         for (_, v) in self.children.iter() {
@@ -388,7 +488,9 @@ fn decode_item(source: &Automerge, items_node: &automerge::ObjId, k: &str) -> Re
     }
 }
 
-fn find_items_node(doc: &Automerge) -> Result<automerge::ObjId, Box<dyn std::error::Error>> {
+// find_items_node reads through the ReadDoc trait so that mutations can look it up without AutoCommit::document closing
+// the pending transaction.
+fn find_items_node<R: ReadDoc>(doc: &R) -> Result<automerge::ObjId, Box<dyn std::error::Error>> {
     match doc.get(automerge::ROOT, DOC_ITEMS_NODE) {
         Ok(Some((Value::Object(ObjType::Map), n))) => Ok(n),
         Ok(Some(_)) => return Err(Box::new(AuError::IncorrectType(Box::from(DOC_ITEMS_NODE), Box::from("map")))),
@@ -397,6 +499,19 @@ fn find_items_node(doc: &Automerge) -> Result<automerge::ObjId, Box<dyn std::err
     }
 }
 
+// init_project writes the empty items node into a new document. Documents that will be merged must share this node, so
+// it should be created once when the project is created rather than lazily by each writer.
+pub fn init_project(doc: &mut AutoCommit) -> Result<Project, Box<dyn std::error::Error>> {
+    let mut project = Project::default();
+    project.transact(doc, "initialise project", |_, d| {
+        if find_items_node(d).is_err() {
+            d.put_object(automerge::ROOT, DOC_ITEMS_NODE, ObjType::Map)?;
+        }
+        Ok(())
+    })?;
+    Ok(project)
+}
+
 pub fn decode_project(source: &Automerge) -> Result<Project, Box<dyn std::error::Error>> {
     let items_node = find_items_node(source)?;
     let mut out: HashMap<Box<str>, Rc<Item>> = HashMap::new();
@@ -413,6 +528,21 @@ pub fn decode_project(source: &Automerge) -> Result<Project, Box<dyn std::error:
     return Ok(Project { children: out });
 }
 
+// decode_project_at decodes the project as it was at the given heads. Before the project was initialised there is no
+// items node, which decodes as an empty project rather than an error.
+pub fn decode_project_at(source: &Automerge, heads: &[ChangeHash]) -> Result<Project, Box<dyn std::error::Error>> {
+    for head in heads {
+        if source.get_change_by_hash(head).is_none() {
+            return Err(Box::new(AuError::NoSuchKey(Box::from(head.to_string()))));
+        }
+    }
+    let forked = source.fork_at(heads)?;
+    if forked.get(automerge::ROOT, DOC_ITEMS_NODE)?.is_none() {
+        return Ok(Project::default());
+    }
+    decode_project(&forked)
+}
+
 fn common_prefix(a: &[u8], b: &[u8]) -> usize {
     let offset = iter::zip(a.chunks_exact(128), b.chunks_exact(128))
         .take_while(|(ac, bc)| ac == bc)
@@ -439,13 +569,15 @@ mod tests {
     use automerge::transaction::Transactable;
     use automerge::{AutoCommit, ObjType, ScalarValue};
 
-    use crate::item::{common_prefix, common_suffix, decode_item, decode_project, Item, ItemUpdate, Project, CONTENT_TYPE_DEFAULT};
+    use crate::item::{
+        common_prefix, common_suffix, decode_item, decode_project, init_project, Item, ItemUpdate, Project, CONTENT_TYPE_DEFAULT,
+    };
 
     #[test]
     fn test_decode_empty() {
         let mut doc = AutoCommit::new();
         doc.put_object(automerge::ROOT, "items", ObjType::Map).unwrap();
-        let res = decode_project(&doc.document()).expect("failed to decode");
+        let res = decode_project(doc.document()).expect("failed to decode");
         assert_eq!(res.children.len(), 0);
     }
 
@@ -548,14 +680,16 @@ mod tests {
     fn test_decode_project_some() {
         let mut doc = AutoCommit::new();
         let mut project = Project::default();
-        let mut item = Item::default();
-        item.id = Rc::from("some-id");
-        item.content_type = Rc::from("text/markdown");
-        item.content = Rc::from("blah blah".as_bytes());
+        let item = Item {
+            id: Rc::from("some-id"),
+            content_type: Rc::from("text/markdown"),
+            content: Rc::from("blah blah".as_bytes()),
+            ..Default::default()
+        };
         project.with_item(&item, &mut doc).unwrap();
         let project = decode_project(doc.document()).unwrap();
         assert_eq!(project.children.len(), 1);
-        assert!(project.children.get("some-id").is_some());
+        assert!(project.children.contains_key("some-id"));
         assert_eq!(project.list_children(None).len(), 1);
         assert!(project.has_children(None));
         assert_eq!(project.list_children(Some("foo")).len(), 0);
@@ -566,14 +700,20 @@ mod tests {
     fn test_project_with_item_tree() {
         let mut doc = AutoCommit::new();
         let mut project = Project::default();
-        let mut item_a = Item::default();
-        item_a.id = Rc::from("item-a");
-        let mut item_b = Item::default();
-        item_b.id = Rc::from("item-b");
-        item_b.parent = Some(Rc::from("item-a"));
-        let mut item_c = Item::default();
-        item_c.id = Rc::from("item-c");
-        item_c.parent = Some(Rc::from("item-b"));
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            ..Default::default()
+        };
+        let item_b = Item {
+            id: Rc::from("item-b"),
+            parent: Some(Rc::from("item-a")),
+            ..Default::default()
+        };
+        let item_c = Item {
+            id: Rc::from("item-c"),
+            parent: Some(Rc::from("item-b")),
+            ..Default::default()
+        };
         project
             .with_item(&item_a, &mut doc)
             .unwrap()
@@ -611,6 +751,12 @@ mod tests {
         assert_eq!(project.list_children(Some("item-a")).len(), 0);
         assert_eq!(project.list_children(Some("item-b")).len(), 1);
 
+        // cycles are rejected, including an item being its own parent
+        for parent in ["item-a", "item-b"] {
+            let res = project.with_updated_item("item-b", &[ItemUpdate::Parent(Some(Box::from(parent)))], &mut doc);
+            assert_eq!(res.err().unwrap().to_string(), format!("'{}': has a cycle", parent));
+        }
+
         // make sure a new document agrees
 
         project = decode_project(doc.document()).unwrap();
@@ -621,14 +767,83 @@ mod tests {
         assert_eq!(project.list_children(Some("item-b")).len(), 1);
     }
 
+    #[test]
+    fn test_resolve_id() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        for id in ["7KQ2M9XA", "7KQ5PP01", "7K", "B00"] {
+            let item = Item {
+                id: Rc::from(id),
+                ..Default::default()
+            };
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        assert_eq!(project.resolve_id("7KQ2").unwrap().id.as_ref(), "7KQ2M9XA");
+        assert_eq!(project.resolve_id("7kq5").unwrap().id.as_ref(), "7KQ5PP01");
+        // an exact match wins over longer ids sharing the prefix
+        assert_eq!(project.resolve_id("7K").unwrap().id.as_ref(), "7K");
+        assert_eq!(project.resolve_id("b").unwrap().id.as_ref(), "B00");
+        assert_eq!(
+            project.resolve_id("7KQ").err().unwrap().to_string(),
+            "'7KQ': ambiguous, matches 2 keys"
+        );
+        assert_eq!(project.resolve_id("X").err().unwrap().to_string(), "'X': no such key");
+        assert!(project.resolve_id("").is_err());
+    }
+
+    #[test]
+    fn test_transact() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            ..Default::default()
+        };
+        let item_b = Item {
+            id: Rc::from("item-b"),
+            parent: Some(Rc::from("item-a")),
+            ..Default::default()
+        };
+
+        let hash = project
+            .transact(&mut doc, "add two items", |p, d| {
+                p.with_item(&item_a, d)?.with_item(&item_b, d)?;
+                Ok(())
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(doc.get_heads(), vec![hash]);
+        let change = doc.get_change_by_hash(&hash).unwrap();
+        assert_eq!(change.message().map(|m| m.as_str()), Some("add two items"));
+        assert!(change.timestamp() > 0);
+        // the init change and the transaction
+        assert_eq!(doc.get_changes(&[]).len(), 2);
+
+        // a failure part way through leaves nothing behind, even if the transaction was closed in between
+        let res = project.transact(&mut doc, "broken", |p, d| {
+            p.with_updated_item("item-b", &[ItemUpdate::Rank(5)], d)?;
+            d.commit();
+            p.without_item("item-a", d)?;
+            Ok(())
+        });
+        assert!(res.is_err());
+        assert_eq!(doc.get_heads(), vec![hash]);
+        assert_eq!(project.get_item("item-b").unwrap().rank, 0);
+        assert_eq!(decode_project(doc.document()).unwrap().get_item("item-b").unwrap().rank, 0);
+
+        assert_eq!(project.transact(&mut doc, "nothing", |_, _| Ok(())).unwrap(), None);
+    }
+
     #[test]
     fn test_content_updates() {
         let mut doc = AutoCommit::new();
         let mut project = Project::default();
 
         // seed with an initial item
-        let mut item_a = Item::default();
-        item_a.id = Rc::from("item-a");
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            ..Default::default()
+        };
         project.with_item(&item_a, &mut doc).unwrap();
         doc.commit().unwrap();
 
@@ -700,6 +915,77 @@ mod tests {
         assert_eq!(changes.len(), 5);
     }
 
+    #[test]
+    fn test_summary_multibyte() {
+        let item = Item {
+            content: Rc::from("héllo wörld\nsecond line".as_bytes()),
+            ..Default::default()
+        };
+        assert_eq!(item.summary(20).as_ref(), "héllo wörld");
+        assert_eq!(item.summary(11).as_ref(), "héllo wörld");
+        assert_eq!(item.summary(10).as_ref(), "héllo w...");
+        assert_eq!(item.summary(2).as_ref(), "hé");
+    }
+
+    #[test]
+    fn test_content_updates_multibyte() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            content: Rc::from("héllo wörld".as_bytes()),
+            ..Default::default()
+        };
+        project.with_item(&item_a, &mut doc).unwrap();
+
+        // é and è share their first byte so the common prefix must step back to a character boundary
+        for content in ["hèllo wörld", "hèllo wörld!", "¡hèllo wörld!"] {
+            let update = ItemUpdate::Content(Box::from(CONTENT_TYPE_DEFAULT), Box::from(content.as_bytes()));
+            project.with_updated_item("item-a", &[update], &mut doc).unwrap();
+            let decoded = decode_project(doc.document()).unwrap();
+            assert_eq!(decoded.get_item("item-a").unwrap().content.as_ref(), content.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_splice_merges_concurrent_edits() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            content: Rc::from("héllo world".as_bytes()),
+            ..Default::default()
+        };
+        project.with_item(&item_a, &mut doc).unwrap();
+        doc.commit();
+
+        let mut doc_b = doc.fork();
+        let mut project_b = project.clone();
+        project
+            .with_updated_item("item-a", &[ItemUpdate::Splice(5, 0, Box::from(","))], &mut doc)
+            .unwrap();
+        assert_eq!(project.get_item("item-a").unwrap().content.as_ref(), "héllo, world".as_bytes());
+        project_b
+            .with_updated_item(
+                "item-a",
+                &[
+                    ItemUpdate::Splice(6, 5, Box::from("wörld")),
+                    ItemUpdate::Splice(11, 0, Box::from("!")),
+                ],
+                &mut doc_b,
+            )
+            .unwrap();
+        doc.merge(&mut doc_b).unwrap();
+        let merged = decode_project(doc.document()).unwrap();
+        assert_eq!(merged.get_item("item-a").unwrap().content.as_ref(), "héllo, wörld!".as_bytes());
+
+        let err = project
+            .with_updated_item("item-a", &[ItemUpdate::Splice(10, 5, Box::from(""))], &mut doc)
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "'content': invalid: splice of 5 at 10 is outside 13 characters");
+    }
+
     #[test]
     fn test_common_prefix() {
         assert_eq!(common_prefix("".as_ref(), "".as_ref()), 0);
@@ -1,4 +1,9 @@
 mod decode;
-mod error;
+pub mod batch;
+pub mod error;
+pub mod history;
 pub mod item;
 pub mod id;
+pub mod path;
+pub mod record;
+pub mod storage;
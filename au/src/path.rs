@@ -0,0 +1,259 @@
+/*
+
+Paths address items by the summaries of the items above them rather than by id, for example `/Projects/Q3 Launch/Tasks`.
+A path starting with `/` is resolved from the top level, any other path from the current item. `.` is the current item
+and `..` its parent, going above the top level stays at the top level. A `/` or `\` inside a summary is escaped with a
+backslash.
+
+Each segment is matched against the children of the item reached so far, in list_children order:
+
+ 1. children whose summary is exactly the segment
+ 2. otherwise children whose slug matches the slug of the segment, or whose id is the segment
+
+If the first rule that matches anything matches more than one child the path is ambiguous. The top level itself is
+represented as None.
+
+ */
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::error::AuError;
+use crate::item::{Item, Project};
+
+const PATH_SEPARATOR: char = '/';
+const PATH_ESCAPE: char = '\\';
+const PATH_CURRENT: &str = ".";
+const PATH_PARENT: &str = "..";
+
+// slug lower cases the text and joins its runs of letters and digits with dashes, so "Q3 Launch!" becomes "q3-launch".
+pub fn slug(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut gap = false;
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            if gap && !out.is_empty() {
+                out.push('-');
+            }
+            gap = false;
+            out.extend(c.to_lowercase());
+        } else {
+            gap = true;
+        }
+    }
+    out
+}
+
+// is_path is true if the reference can only be a path rather than an id or id prefix.
+pub fn is_path(reference: &str) -> bool {
+    reference.contains(PATH_SEPARATOR) || reference == PATH_CURRENT || reference == PATH_PARENT
+}
+
+// resolve finds an item by id, unique id prefix or path relative to the current item, which is how user input naming an
+// item should be interpreted. Anything that isn't obviously a path is tried as an id first.
+pub fn resolve(project: &Project, current: Option<&str>, reference: &str) -> Result<Option<Rc<Item>>, Box<dyn std::error::Error>> {
+    if is_path(reference) {
+        return resolve_path(project, current, reference);
+    }
+    match project.resolve_id(reference) {
+        Ok(item) => Ok(Some(item)),
+        Err(id_err) => match resolve_path(project, current, reference) {
+            Ok(item) => Ok(item),
+            Err(path_err) if matches!(path_err.downcast_ref::<AuError>(), Some(AuError::Ambiguous(_, _))) => Err(path_err),
+            Err(_) => Err(id_err),
+        },
+    }
+}
+
+// resolve_item is resolve for references that must name an item, a path to the top level is an error.
+pub fn resolve_item(project: &Project, current: Option<&str>, reference: &str) -> Result<Rc<Item>, Box<dyn std::error::Error>> {
+    resolve(project, current, reference)?.ok_or_else(|| {
+        Box::new(AuError::InvalidOperation(Box::from(reference), Box::from("is the top level"))) as Box<dyn std::error::Error>
+    })
+}
+
+// resolve_path follows the path from the current item, or from the top level if the path is absolute.
+pub fn resolve_path(project: &Project, current: Option<&str>, path: &str) -> Result<Option<Rc<Item>>, Box<dyn std::error::Error>> {
+    let mut at: Option<Rc<Item>> = match current {
+        Some(id) if !path.starts_with(PATH_SEPARATOR) => Some(project.get_item(id).ok_or_else(|| AuError::NoSuchKey(Box::from(id)))?),
+        _ => None,
+    };
+    for segment in split_path(path) {
+        at = match segment.as_str() {
+            "" | PATH_CURRENT => at,
+            PATH_PARENT => at.and_then(|i| i.parent.as_deref().and_then(|p| project.get_item(p))),
+            _ => Some(find_child(project, at.as_ref().map(|i| i.id.as_ref()), segment.as_str())?),
+        };
+    }
+    Ok(at)
+}
+
+// item_path returns the absolute path of the item. Segments use the summary unless another sibling shares it, in
+// which case the id is used so that the path always resolves back to the same item.
+pub fn item_path(project: &Project, id: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut segments: Vec<String> = Vec::new();
+    let mut seen: HashSet<Rc<str>> = HashSet::new();
+    let mut next = Some(project.get_item(id).ok_or_else(|| AuError::NoSuchKey(Box::from(id)))?);
+    while let Some(item) = next {
+        if !seen.insert(item.id.clone()) {
+            return Err(Box::new(AuError::Cycle(Box::from(item.id.as_ref()))));
+        }
+        let summary = full_summary(&item);
+        let shared = project
+            .list_children(item.parent.as_deref())
+            .iter()
+            .filter(|s| full_summary(s) == summary)
+            .count()
+            > 1;
+        segments.push(if shared || summary.is_empty() {
+            escape_segment(&item.id)
+        } else {
+            escape_segment(&summary)
+        });
+        next = item.parent.as_deref().and_then(|p| project.get_item(p));
+    }
+    segments.reverse();
+    Ok(format!("{}{}", PATH_SEPARATOR, segments.join(PATH_SEPARATOR.to_string().as_str())))
+}
+
+fn find_child(project: &Project, parent: Option<&str>, segment: &str) -> Result<Rc<Item>, Box<dyn std::error::Error>> {
+    let children = project.list_children(parent);
+    let exact: Vec<&Rc<Item>> = children.iter().filter(|c| full_summary(c) == segment).collect();
+    let candidates = if exact.is_empty() {
+        let segment_slug = slug(segment);
+        children
+            .iter()
+            .filter(|c| c.id.as_ref() == segment || (!segment_slug.is_empty() && slug(&full_summary(c)) == segment_slug))
+            .collect()
+    } else {
+        exact
+    };
+    match candidates.as_slice() {
+        [] => Err(Box::new(AuError::NoSuchKey(Box::from(segment)))),
+        [item] => Ok((*item).clone()),
+        _ => Err(Box::new(AuError::Ambiguous(Box::from(segment), candidates.len()))),
+    }
+}
+
+// full_summary is the untruncated first line of the item.
+fn full_summary(item: &Item) -> String {
+    item.summary(usize::MAX).to_string()
+}
+
+fn split_path(path: &str) -> Vec<String> {
+    let mut segments: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            PATH_ESCAPE => current.extend(chars.next()),
+            PATH_SEPARATOR => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+fn escape_segment(segment: &str) -> String {
+    let mut out = String::with_capacity(segment.len());
+    for c in segment.chars() {
+        if c == PATH_SEPARATOR || c == PATH_ESCAPE {
+            out.push(PATH_ESCAPE);
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use automerge::AutoCommit;
+
+    use crate::item::{init_project, Item, Project};
+    use crate::path::{item_path, resolve, resolve_item, resolve_path, slug, split_path};
+
+    fn build() -> Project {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        for (id, parent, content) in [
+            ("P1", None, "Projects"),
+            ("Q3", Some("P1"), "Q3 Launch\nnotes about the launch"),
+            ("T1", Some("Q3"), "Tasks"),
+            ("T2", Some("Q3"), "tasks"),
+            ("D1", Some("Q3"), "Done"),
+            ("D2", Some("Q3"), "Done"),
+            ("S1", Some("P1"), "a/b"),
+        ] {
+            let item = Item {
+                id: Rc::from(id),
+                parent: parent.map(Rc::from),
+                content: Rc::from(content.as_bytes()),
+                ..Default::default()
+            };
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        project
+    }
+
+    fn resolved(project: &Project, current: Option<&str>, path: &str) -> Option<String> {
+        resolve_path(project, current, path).unwrap().map(|i| i.id.to_string())
+    }
+
+    #[test]
+    fn test_slug() {
+        assert_eq!(slug("Q3 Launch!"), "q3-launch");
+        assert_eq!(slug("  --Hello,  World-- "), "hello-world");
+        assert_eq!(slug("!!"), "");
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("/a\\/b/c\\\\"), vec!["", "a/b", "c\\"]);
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let project = build();
+        assert_eq!(resolved(&project, None, "/Projects/Q3 Launch/Tasks").as_deref(), Some("T1"));
+        assert_eq!(resolved(&project, None, "projects/q3-launch/tasks").as_deref(), Some("T2"));
+        assert_eq!(resolved(&project, None, "/Projects/a\\/b").as_deref(), Some("S1"));
+        assert_eq!(resolved(&project, None, "/").as_deref(), None);
+        assert_eq!(resolved(&project, Some("T1"), "..").as_deref(), Some("Q3"));
+        assert_eq!(resolved(&project, Some("T1"), "../../a\\/b").as_deref(), Some("S1"));
+        assert_eq!(resolved(&project, Some("T1"), "../../../..").as_deref(), None);
+        assert_eq!(resolved(&project, Some("T1"), "/Projects").as_deref(), Some("P1"));
+        assert_eq!(resolved(&project, Some("Q3"), "./D2").as_deref(), Some("D2"));
+
+        let err = resolve_path(&project, None, "/Projects/Q3 Launch/Done").err().unwrap();
+        assert_eq!(err.to_string(), "'Done': ambiguous, matches 2 keys");
+        let err = resolve_path(&project, None, "/Projects/q3 launch/TASKS").err().unwrap();
+        assert_eq!(err.to_string(), "'TASKS': ambiguous, matches 2 keys");
+        let err = resolve_path(&project, None, "/Projects/Missing").err().unwrap();
+        assert_eq!(err.to_string(), "'Missing': no such key");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let project = build();
+        assert_eq!(resolve(&project, None, "q3").unwrap().unwrap().id.as_ref(), "Q3");
+        assert_eq!(resolve(&project, None, "Projects").unwrap().unwrap().id.as_ref(), "P1");
+        assert_eq!(resolve(&project, Some("Q3"), "Tasks").unwrap().unwrap().id.as_ref(), "T1");
+        assert!(resolve(&project, None, "nothing").is_err());
+        assert_eq!(resolve_item(&project, None, "/Projects").unwrap().id.as_ref(), "P1");
+        assert_eq!(resolve_item(&project, None, "/").err().unwrap().to_string(), "'/': is the top level");
+    }
+
+    #[test]
+    fn test_item_path() {
+        let project = build();
+        for id in ["P1", "Q3", "T1", "T2", "D1", "D2", "S1"] {
+            let path = item_path(&project, id).unwrap();
+            assert_eq!(resolved(&project, None, path.as_str()).as_deref(), Some(id), "{}", path);
+        }
+        assert_eq!(item_path(&project, "D2").unwrap(), "/Projects/Q3 Launch/D2");
+        assert_eq!(item_path(&project, "S1").unwrap(), "/Projects/a\\/b");
+    }
+}
@@ -0,0 +1,112 @@
+/*
+
+An ItemRecord is the serializable form of an item that every machine readable interface writes, so that the json output
+of the CLI, the rpc mode and the http api all agree on field names and encodings:
+
+    id, parent, rank, class, content_type, at, depth, content, content_encoding
+
+`at` is RFC3339. `content` is only present in records made with it, and holds the text when the content type is text
+and the content is valid utf-8 (content_encoding "utf-8") and base64 otherwise (content_encoding "base64"), the same
+encodings that au::batch reads. `depth` is only present in listings, where every record has it, counting from 0 for
+the children of the listed parent.
+
+ */
+
+use base64::Engine;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+
+use crate::item::Item;
+
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+const ENCODING_UTF8: &str = "utf-8";
+const ENCODING_BASE64: &str = "base64";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ItemRecord {
+    pub id: Box<str>,
+    pub parent: Option<Box<str>>,
+    pub rank: i64,
+    pub class: Option<Box<str>>,
+    pub content_type: Box<str>,
+    pub at: Box<str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<Box<str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<&'static str>,
+}
+
+impl ItemRecord {
+    // new is the record of an item without its content.
+    pub fn new(item: &Item) -> Result<ItemRecord, Box<dyn std::error::Error>> {
+        Ok(ItemRecord {
+            id: Box::from(item.id.as_ref()),
+            parent: item.parent.as_deref().map(Box::from),
+            rank: item.rank,
+            class: item.class.as_deref().map(Box::from),
+            content_type: Box::from(item.content_type.as_ref()),
+            at: Box::from(item.at.format(&Rfc3339)?),
+            depth: None,
+            content: None,
+            content_encoding: None,
+        })
+    }
+
+    // listing is the record of an item at a depth below the listed parent, without its content.
+    pub fn listing(item: &Item, depth: usize) -> Result<ItemRecord, Box<dyn std::error::Error>> {
+        let mut record = ItemRecord::new(item)?;
+        record.depth = Some(depth);
+        Ok(record)
+    }
+
+    // show is the record of an item with its content.
+    pub fn show(item: &Item) -> Result<ItemRecord, Box<dyn std::error::Error>> {
+        let mut record = ItemRecord::new(item)?;
+        match std::str::from_utf8(item.content.as_ref()) {
+            Ok(s) if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) => {
+                record.content = Some(Box::from(s));
+                record.content_encoding = Some(ENCODING_UTF8);
+            }
+            _ => {
+                record.content = Some(Box::from(base64::engine::general_purpose::STANDARD.encode(item.content.as_ref())));
+                record.content_encoding = Some(ENCODING_BASE64);
+            }
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::item::Item;
+    use crate::record::ItemRecord;
+
+    #[test]
+    fn test_item_record() {
+        let item = Item {
+            id: Rc::from("a"),
+            parent: Some(Rc::from("p")),
+            content: Rc::from("hello".as_bytes()),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&ItemRecord::new(&item).unwrap()).unwrap(),
+            r#"{"id":"a","parent":"p","rank":0,"class":null,"content_type":"text/plain","at":"1970-01-01T00:00:00Z"}"#
+        );
+        assert_eq!(ItemRecord::listing(&item, 2).unwrap().depth, Some(2));
+        let record = ItemRecord::show(&item).unwrap();
+        assert_eq!((record.content.as_deref(), record.content_encoding), (Some("hello"), Some("utf-8")));
+
+        let binary = Item {
+            content_type: Rc::from("application/octet-stream"),
+            content: Rc::from(vec![0u8, 1]),
+            ..item
+        };
+        let record = ItemRecord::show(&binary).unwrap();
+        assert_eq!((record.content.as_deref(), record.content_encoding), (Some("AAE="), Some("base64")));
+    }
+}
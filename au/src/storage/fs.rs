@@ -0,0 +1,348 @@
+/*
+
+The filesystem backend stores a project as a directory:
+
+    <dir>/snapshot.automerge     the last compacted snapshot, replaced atomically by rename
+    <dir>/changes/<hash>.change  one file per change appended since the snapshot
+    <dir>/lock                   advisory lock held while writing
+
+Change files are only ever created and never modified so any number of readers can list them at any time, and several
+processes can have the same project open. Each process appends its own changes and picks up the changes of others by
+merging them into its own document (see storage::refresh_project) so nothing is ever overwritten. The Watcher can be
+used to find out when that is worth doing.
+
+ */
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use automerge::{Change, ChangeHash};
+
+use crate::error::AuError;
+use crate::storage::{Backend, Lock};
+
+const SNAPSHOT_FILE: &str = "snapshot.automerge";
+const CHANGES_DIR: &str = "changes";
+const CHANGE_FILE_SUFFIX: &str = ".change";
+const LOCK_FILE: &str = "lock";
+pub(crate) const DEFAULT_LOCK_WAIT: Duration = Duration::from_secs(5);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct FsBackend {
+    path: PathBuf,
+    lock_wait: Duration,
+}
+
+impl FsBackend {
+    // init creates the directory layout at the path if it does not exist yet.
+    pub fn init(path: &Path) -> Result<FsBackend, Box<dyn std::error::Error>> {
+        fs::create_dir_all(path.join(CHANGES_DIR))?;
+        Ok(FsBackend {
+            path: path.to_path_buf(),
+            lock_wait: DEFAULT_LOCK_WAIT,
+        })
+    }
+
+    // open an existing project directory.
+    pub fn open(path: &Path) -> Result<FsBackend, Box<dyn std::error::Error>> {
+        if !path.join(CHANGES_DIR).is_dir() {
+            return Err(Box::new(AuError::InvalidOperation(
+                Box::from(path.to_string_lossy().as_ref()),
+                Box::from("not a project directory"),
+            )));
+        }
+        Ok(FsBackend {
+            path: path.to_path_buf(),
+            lock_wait: DEFAULT_LOCK_WAIT,
+        })
+    }
+
+    // with_lock_wait sets how long lock() waits for another process to release the lock before giving up.
+    pub fn with_lock_wait(mut self, lock_wait: Duration) -> FsBackend {
+        self.lock_wait = lock_wait;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    // watch starts a background thread that polls the project directory and sends on the returned Watcher whenever
+    // the snapshot or set of change files has changed, including changes made by this process.
+    pub fn watch(&self, interval: Duration) -> Watcher {
+        Watcher::new(self.path.as_path(), interval)
+    }
+}
+
+impl Backend for FsBackend {
+    fn load_snapshot(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        match fs::read(self.path.join(SNAPSHOT_FILE)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    fn append_changes(&mut self, changes: &[&Change]) -> Result<usize, Box<dyn std::error::Error>> {
+        write_change_files(&self.path.join(CHANGES_DIR), changes).map(|written| written.len())
+    }
+
+    fn list_changes(&self) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+        read_change_files(&self.path.join(CHANGES_DIR))
+    }
+
+    fn compact(&mut self, snapshot: &[u8], covered: &[ChangeHash]) -> Result<(), Box<dyn std::error::Error>> {
+        // write the snapshot to a temporary name first so that readers never see a partial file
+        let tmp = self.path.join(format!("{}.tmp", SNAPSHOT_FILE));
+        fs::write(&tmp, snapshot)?;
+        fs::rename(&tmp, self.path.join(SNAPSHOT_FILE))?;
+        for hash in covered {
+            match fs::remove_file(self.path.join(CHANGES_DIR).join(change_file_name(hash))) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(Box::new(e)),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<Lock, Box<dyn std::error::Error>> {
+        Ok(Lock::new(LockFile::acquire(&self.path.join(LOCK_FILE), self.lock_wait)?))
+    }
+}
+
+// LockFile is an exclusive advisory lock on a file held by the operating system. It is released when dropped, or
+// when the process exits, so a crashed writer never leaves a stale lock behind. The file itself is left in place.
+pub(crate) struct LockFile {
+    _file: fs::File,
+}
+
+impl LockFile {
+    // acquire waits up to the given duration for the lock before failing.
+    pub(crate) fn acquire(path: &Path, wait: Duration) -> Result<LockFile, Box<dyn std::error::Error>> {
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(path)?;
+        let deadline = Instant::now() + wait;
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(LockFile { _file: file }),
+                Err(fs::TryLockError::WouldBlock) if Instant::now() < deadline => thread::sleep(LOCK_RETRY_INTERVAL),
+                Err(fs::TryLockError::WouldBlock) => {
+                    return Err(Box::new(AuError::InvalidOperation(
+                        Box::from(path.to_string_lossy().as_ref()),
+                        Box::from("locked"),
+                    )))
+                }
+                Err(fs::TryLockError::Error(e)) => return Err(Box::new(e)),
+            }
+        }
+    }
+}
+
+// Watcher notifies about changes to a project directory made by any process. It polls rather than relying on platform
+// specific notification apis, the background thread stops when the Watcher is dropped.
+pub struct Watcher {
+    rx: Receiver<()>,
+    stop: Arc<AtomicBool>,
+}
+
+impl Watcher {
+    fn new(path: &Path, interval: Duration) -> Watcher {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let path = path.to_path_buf();
+        // take the first fingerprint before returning so that nothing written after this point can be missed
+        let mut last = fingerprint(&path);
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                thread::sleep(interval);
+                let next = fingerprint(&path);
+                if next != last {
+                    last = next;
+                    if tx.send(()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Watcher { rx, stop }
+    }
+
+    // changed returns true if anything has changed since the last call, without blocking.
+    pub fn changed(&self) -> bool {
+        let mut changed = false;
+        while self.rx.try_recv().is_ok() {
+            changed = true;
+        }
+        changed
+    }
+
+    // wait blocks until something changes or the timeout passes and returns whether anything changed.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        match self.rx.recv_timeout(timeout) {
+            Ok(()) => {
+                self.changed();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+// fingerprint summarises the state of the project directory: the snapshot modification time and the change file names.
+fn fingerprint(path: &Path) -> (Option<SystemTime>, Vec<String>) {
+    let snapshot = fs::metadata(path.join(SNAPSHOT_FILE)).and_then(|m| m.modified()).ok();
+    let mut names: Vec<String> = match fs::read_dir(path.join(CHANGES_DIR)) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.ends_with(CHANGE_FILE_SUFFIX) && !n.starts_with('.'))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    (snapshot, names)
+}
+
+pub(crate) fn change_file_name(hash: &ChangeHash) -> String {
+    format!("{}{}", hash, CHANGE_FILE_SUFFIX)
+}
+
+// write_change_files writes each change that does not already have a file and returns the names of the new files.
+pub(crate) fn write_change_files(dir: &Path, changes: &[&Change]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut written: Vec<String> = Vec::new();
+    for change in changes {
+        let file_name = change_file_name(&change.hash());
+        let path = dir.join(&file_name);
+        if path.exists() {
+            continue;
+        }
+        // as with the snapshot, write to a temporary name so that a concurrent reader never loads half a change
+        let tmp = dir.join(format!(".{}.tmp", file_name));
+        fs::write(&tmp, change.raw_bytes())?;
+        fs::rename(&tmp, &path)?;
+        written.push(file_name);
+    }
+    Ok(written)
+}
+
+// read_change_files loads every change file in the directory. Files that disappear while listing (because another
+// process compacted them into a snapshot) are skipped.
+pub(crate) fn read_change_files(dir: &Path) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+    let mut changes: Vec<Change> = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.ends_with(CHANGE_FILE_SUFFIX) || file_name.starts_with('.') {
+            continue;
+        }
+        let data = match fs::read(entry.path()) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(Box::new(e)),
+        };
+        let change = Change::from_bytes(data).map_err(|e| AuError::NestedError(Box::from(file_name.as_str()), Box::new(e)))?;
+        changes.push(change);
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::item::ItemUpdate;
+    use crate::storage::fs::FsBackend;
+    use crate::storage::tests::{exercise_backend, new_item};
+    use crate::storage::{compact_project, init_project, load_project, refresh_project, save_project, Backend};
+
+    #[test]
+    fn test_fs_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise_backend(&mut FsBackend::init(dir.path()).unwrap());
+        assert!(dir.path().join("snapshot.automerge").exists());
+        assert_eq!(std::fs::read_dir(dir.path().join("changes")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_fs_lock_contention() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = FsBackend::init(dir.path()).unwrap();
+        let b = FsBackend::open(dir.path()).unwrap().with_lock_wait(Duration::from_millis(50));
+        let lock = a.lock().unwrap();
+        assert_eq!(
+            b.lock().err().unwrap().to_string(),
+            format!("'{}': locked", dir.path().join("lock").display())
+        );
+        drop(lock);
+        assert!(b.lock().is_ok());
+    }
+
+    #[test]
+    fn test_fs_two_processes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend_a = FsBackend::init(dir.path()).unwrap();
+        let mut backend_b = FsBackend::open(dir.path()).unwrap();
+        let (mut doc_a, mut project_a) = init_project(&mut backend_a).unwrap();
+        let (mut doc_b, mut project_b) = load_project(&backend_b).unwrap();
+        let watcher = backend_b.watch(Duration::from_millis(10));
+
+        project_a.with_item(&new_item("item-a", None), &mut doc_a).unwrap();
+        save_project(&mut backend_a, &mut doc_a).unwrap();
+        assert!(watcher.wait(Duration::from_secs(5)));
+
+        // b has unsaved work of its own which must survive picking up a's changes
+        project_b.with_item(&new_item("item-b", None), &mut doc_b).unwrap();
+        assert!(refresh_project(&backend_b, &mut doc_b, &mut project_b).unwrap());
+        assert!(project_b.get_item("item-a").is_some());
+        assert!(project_b.get_item("item-b").is_some());
+        assert!(!refresh_project(&backend_b, &mut doc_b, &mut project_b).unwrap());
+
+        project_b.with_updated_item("item-a", &[ItemUpdate::Rank(2)], &mut doc_b).unwrap();
+        save_project(&mut backend_b, &mut doc_b).unwrap();
+        assert!(refresh_project(&backend_a, &mut doc_a, &mut project_a).unwrap());
+        assert_eq!(project_a.get_item("item-a").unwrap().rank, 2);
+        assert_eq!(project_a.list_items().len(), 2);
+        assert_eq!(doc_a.get_heads(), doc_b.get_heads());
+    }
+
+    #[test]
+    fn test_fs_two_compactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut backend_a = FsBackend::init(dir.path()).unwrap();
+        let mut backend_b = FsBackend::open(dir.path()).unwrap();
+        let (mut doc_a, mut project_a) = init_project(&mut backend_a).unwrap();
+        let (mut doc_b, mut project_b) = load_project(&backend_b).unwrap();
+
+        // b compacts without having seen a's compaction, which must not lose a's items
+        project_a.with_item(&new_item("item-a", None), &mut doc_a).unwrap();
+        compact_project(&mut backend_a, &mut doc_a, &mut project_a).unwrap();
+        project_b.with_item(&new_item("item-b", None), &mut doc_b).unwrap();
+        compact_project(&mut backend_b, &mut doc_b, &mut project_b).unwrap();
+        assert!(project_b.get_item("item-a").is_some());
+
+        let (_, project) = load_project(&backend_a).unwrap();
+        assert!(project.get_item("item-a").is_some());
+        assert!(project.get_item("item-b").is_some());
+        assert_eq!(std::fs::read_dir(dir.path().join("changes")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_fs_open_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(FsBackend::open(dir.path()).is_err());
+        FsBackend::init(dir.path()).unwrap();
+        assert!(FsBackend::open(dir.path()).is_ok());
+    }
+}
@@ -0,0 +1,324 @@
+/*
+
+The git store keeps the automerge changes of a project in a git working tree. Each change is written to its own file
+named after its change hash so two clones that have made independent changes will only ever add different files and a
+git merge between them can never conflict. The document is rebuilt by loading every change file, automerge takes care
+of ordering them by their dependencies.
+
+Compaction writes a snapshot file named after the document heads and removes the change files it covers. Two clones
+that compact concurrently produce differently named snapshots, so these are all loaded and merged together.
+
+Syncing is just a git pull followed by a git push against any remote, including a local bare repository.
+
+ */
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use automerge::{AutoCommit, Change, ChangeHash};
+
+use crate::error::AuError;
+use crate::storage::fs::{change_file_name, read_change_files, write_change_files, LockFile, DEFAULT_LOCK_WAIT};
+use crate::storage::{Backend, Lock};
+
+const CHANGES_DIR: &str = "changes";
+const SNAPSHOTS_DIR: &str = "snapshots";
+const SNAPSHOT_FILE_SUFFIX: &str = ".automerge";
+const LOCK_FILE: &str = "au.lock";
+const FALLBACK_USER_NAME: &str = "au";
+const FALLBACK_USER_EMAIL: &str = "au@localhost";
+
+pub struct GitStore {
+    path: PathBuf,
+}
+
+impl GitStore {
+    // init creates a new git repository at the path (or reuses an existing one) and prepares the changes directory.
+    pub fn init(path: &Path) -> Result<GitStore, Box<dyn std::error::Error>> {
+        fs::create_dir_all(path.join(CHANGES_DIR))?;
+        fs::create_dir_all(path.join(SNAPSHOTS_DIR))?;
+        let store = GitStore { path: path.to_path_buf() };
+        if !path.join(".git").exists() {
+            store.git(&["init", "--quiet"])?;
+        }
+        Ok(store)
+    }
+
+    // open an existing git store.
+    pub fn open(path: &Path) -> Result<GitStore, Box<dyn std::error::Error>> {
+        if !path.join(".git").exists() {
+            return Err(Box::new(AuError::InvalidOperation(
+                Box::from(path.to_string_lossy().as_ref()),
+                Box::from("not a git repository"),
+            )));
+        }
+        fs::create_dir_all(path.join(CHANGES_DIR))?;
+        fs::create_dir_all(path.join(SNAPSHOTS_DIR))?;
+        Ok(GitStore { path: path.to_path_buf() })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    // add_remote registers a named git remote such as a local bare repository.
+    pub fn add_remote(&self, name: &str, url: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.git(&["remote", "add", name, url])?;
+        Ok(())
+    }
+
+    // pull merges the current branch from the remote into the working tree. A remote that does not have the branch yet
+    // is treated as having nothing to pull.
+    pub fn pull(&self, remote: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let branch = self.current_branch()?;
+        if self.git(&["ls-remote", "--heads", remote, branch.as_str()])?.trim().is_empty() {
+            return Ok(());
+        }
+        self.git(&[
+            "pull",
+            "--quiet",
+            "--no-rebase",
+            "--no-edit",
+            "--allow-unrelated-histories",
+            remote,
+            branch.as_str(),
+        ])?;
+        Ok(())
+    }
+
+    // push the current branch to the same branch name on the remote.
+    pub fn push(&self, remote: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let branch = self.current_branch()?;
+        self.git(&["push", "--quiet", remote, format!("HEAD:refs/heads/{}", branch).as_str()])?;
+        Ok(())
+    }
+
+    // sync is a pull followed by a push so that both sides end up with the union of the changes.
+    pub fn sync(&self, remote: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.pull(remote)?;
+        self.push(remote)
+    }
+
+    fn snapshot_files(&self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+        let mut out: Vec<PathBuf> = Vec::new();
+        for entry in fs::read_dir(self.path.join(SNAPSHOTS_DIR))? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().ends_with(SNAPSHOT_FILE_SUFFIX) {
+                out.push(entry.path());
+            }
+        }
+        out.sort();
+        Ok(out)
+    }
+
+    // commit stages the change and snapshot directories and commits them if anything is staged.
+    fn commit(&self, message: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.git(&["add", "--all", CHANGES_DIR, SNAPSHOTS_DIR])?;
+        if self.git(&["diff", "--cached", "--name-only"])?.trim().is_empty() {
+            return Ok(());
+        }
+        self.git(&["commit", "--quiet", "--message", message])?;
+        Ok(())
+    }
+
+    fn current_branch(&self) -> Result<String, Box<dyn std::error::Error>> {
+        // symbolic-ref works even before the first commit exists unlike rev-parse
+        Ok(self.git(&["symbolic-ref", "--short", "HEAD"])?.trim().to_string())
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(&self.path);
+        // commits and merges need an identity, fall back to a fixed one if the user has not configured git
+        let has_identity = Command::new("git")
+            .current_dir(&self.path)
+            .args(["config", "user.email"])
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false);
+        if !has_identity {
+            cmd.args(["-c", format!("user.name={}", FALLBACK_USER_NAME).as_str()]);
+            cmd.args(["-c", format!("user.email={}", FALLBACK_USER_EMAIL).as_str()]);
+        }
+        let output = cmd.args(args).output()?;
+        if !output.status.success() {
+            return Err(Box::new(AuError::InvalidOperation(
+                Box::from(format!("git {}", args.join(" "))),
+                Box::from(String::from_utf8_lossy(&output.stderr).trim()),
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+// commit_message uses the message of a single change as the commit message so that the git log reads like the project
+// history, otherwise it counts the changes and lists any messages they carry.
+fn commit_message(changes: &[&Change]) -> String {
+    let messages: Vec<&str> = changes.iter().filter_map(|c| c.message()).map(|m| m.as_str()).collect();
+    if let [message] = messages.as_slice() {
+        if changes.len() == 1 {
+            return message.to_string();
+        }
+    }
+    let mut out = format!("Append {} changes", changes.len());
+    if !messages.is_empty() {
+        out.push('\n');
+        for message in messages {
+            out.push_str(format!("\n- {}", message).as_str());
+        }
+    }
+    out
+}
+
+impl Backend for GitStore {
+    fn load_snapshot(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let files = self.snapshot_files()?;
+        match files.len() {
+            0 => Ok(None),
+            1 => Ok(Some(fs::read(&files[0])?)),
+            _ => {
+                let mut merged = AutoCommit::new();
+                for file in files {
+                    merged.merge(&mut AutoCommit::load(&fs::read(file)?)?)?;
+                }
+                Ok(Some(merged.save()))
+            }
+        }
+    }
+
+    fn append_changes(&mut self, changes: &[&Change]) -> Result<usize, Box<dyn std::error::Error>> {
+        let written = write_change_files(&self.path.join(CHANGES_DIR), changes)?;
+        if !written.is_empty() {
+            let new_changes: Vec<&Change> = changes
+                .iter()
+                .filter(|c| written.contains(&change_file_name(&c.hash())))
+                .copied()
+                .collect();
+            self.commit(commit_message(&new_changes).as_str())?;
+        }
+        Ok(written.len())
+    }
+
+    fn list_changes(&self) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+        read_change_files(&self.path.join(CHANGES_DIR))
+    }
+
+    fn compact(&mut self, snapshot: &[u8], covered: &[ChangeHash]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut heads = AutoCommit::load(snapshot)?.get_heads();
+        heads.sort();
+        let name: Vec<String> = heads.iter().map(|h| h.to_string()[..16].to_string()).collect();
+        let previous = self.snapshot_files()?;
+        let target = self
+            .path
+            .join(SNAPSHOTS_DIR)
+            .join(format!("{}{}", name.join("-"), SNAPSHOT_FILE_SUFFIX));
+        fs::write(&target, snapshot)?;
+        for file in previous {
+            if file != target {
+                fs::remove_file(file)?;
+            }
+        }
+        for hash in covered {
+            match fs::remove_file(self.path.join(CHANGES_DIR).join(change_file_name(hash))) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(Box::new(e)),
+                _ => (),
+            }
+        }
+        self.commit("Compact changes into snapshot")
+    }
+
+    fn lock(&self) -> Result<Lock, Box<dyn std::error::Error>> {
+        Ok(Lock::new(LockFile::acquire(
+            &self.path.join(".git").join(LOCK_FILE),
+            DEFAULT_LOCK_WAIT,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+
+    use crate::item::ItemUpdate;
+    use crate::storage::git::GitStore;
+    use crate::storage::tests::{exercise_backend, new_item};
+    use crate::storage::{compact_project, init_project, load_document, load_project, save_project};
+
+    fn bare_remote(path: &Path) -> String {
+        let status = Command::new("git").args(["init", "--quiet", "--bare"]).arg(path).status().unwrap();
+        assert!(status.success());
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_git_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        exercise_backend(&mut GitStore::init(dir.path()).unwrap());
+        assert_eq!(std::fs::read_dir(dir.path().join("snapshots")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_commit_message_from_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = GitStore::init(dir.path()).unwrap();
+        let (mut doc, mut project) = init_project(&mut store).unwrap();
+        project
+            .transact(&mut doc, "add item-a", |p, d| p.with_item(&new_item("item-a", None), d).map(|_| ()))
+            .unwrap();
+        save_project(&mut store, &mut doc).unwrap();
+        assert_eq!(store.git(&["log", "-1", "--format=%s"]).unwrap().trim(), "add item-a");
+    }
+
+    #[test]
+    fn test_open_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(GitStore::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_sync_through_bare_remote() {
+        let remote_dir = tempfile::tempdir().unwrap();
+        let remote = bare_remote(remote_dir.path());
+
+        // the first clone creates the project and pushes it
+        let dir_a = tempfile::tempdir().unwrap();
+        let mut store_a = GitStore::init(dir_a.path()).unwrap();
+        store_a.add_remote("origin", remote.as_str()).unwrap();
+        let (mut doc_a, mut project_a) = init_project(&mut store_a).unwrap();
+        project_a.with_item(&new_item("item-a", None), &mut doc_a).unwrap();
+        save_project(&mut store_a, &mut doc_a).unwrap();
+        store_a.sync("origin").unwrap();
+
+        // the second clone pulls it down
+        let dir_b = tempfile::tempdir().unwrap();
+        let mut store_b = GitStore::init(dir_b.path()).unwrap();
+        store_b.add_remote("origin", remote.as_str()).unwrap();
+        store_b.pull("origin").unwrap();
+        let (mut doc_b, mut project_b) = load_project(&store_b).unwrap();
+        assert!(project_b.get_item("item-a").is_some());
+
+        // both make concurrent changes, and both compact their history
+        project_a.with_item(&new_item("item-from-a", None), &mut doc_a).unwrap();
+        compact_project(&mut store_a, &mut doc_a, &mut project_a).unwrap();
+        project_b.with_item(&new_item("item-from-b", None), &mut doc_b).unwrap();
+        project_b.with_updated_item("item-a", &[ItemUpdate::Rank(7)], &mut doc_b).unwrap();
+        compact_project(&mut store_b, &mut doc_b, &mut project_b).unwrap();
+        project_b.with_item(&new_item("item-after-compact", None), &mut doc_b).unwrap();
+        save_project(&mut store_b, &mut doc_b).unwrap();
+
+        // and the merges never conflict
+        store_a.sync("origin").unwrap();
+        store_b.sync("origin").unwrap();
+        store_a.pull("origin").unwrap();
+
+        let mut merged_a = load_document(&store_a).unwrap();
+        let mut merged_b = load_document(&store_b).unwrap();
+        assert_eq!(merged_a.get_heads(), merged_b.get_heads());
+        let (_, project) = load_project(&store_a).unwrap();
+        assert_eq!(project.list_children(None).len(), 4);
+        assert_eq!(project.get_item("item-a").unwrap().rank, 7);
+    }
+}
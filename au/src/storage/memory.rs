@@ -0,0 +1,86 @@
+use std::cell::Cell;
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use automerge::{Change, ChangeHash};
+
+use crate::error::AuError;
+use crate::storage::{Backend, Lock};
+
+// MemoryBackend keeps everything in memory, it is mostly useful for tests and for projects that should not outlive
+// the process.
+#[derive(Default)]
+pub struct MemoryBackend {
+    snapshot: Option<Vec<u8>>,
+    changes: BTreeMap<ChangeHash, Vec<u8>>,
+    locked: Rc<Cell<bool>>,
+}
+
+// FlagLock is an in-process lock over a shared flag which is cleared when dropped.
+pub(crate) struct FlagLock {
+    locked: Rc<Cell<bool>>,
+}
+
+impl FlagLock {
+    pub(crate) fn acquire(locked: &Rc<Cell<bool>>, name: &str) -> Result<FlagLock, Box<dyn std::error::Error>> {
+        if locked.replace(true) {
+            return Err(Box::new(AuError::InvalidOperation(Box::from(name), Box::from("locked"))));
+        }
+        Ok(FlagLock { locked: locked.clone() })
+    }
+}
+
+impl Drop for FlagLock {
+    fn drop(&mut self) {
+        self.locked.set(false);
+    }
+}
+
+impl Backend for MemoryBackend {
+    fn load_snapshot(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        Ok(self.snapshot.clone())
+    }
+
+    fn append_changes(&mut self, changes: &[&Change]) -> Result<usize, Box<dyn std::error::Error>> {
+        let mut written = 0;
+        for change in changes {
+            if let Entry::Vacant(e) = self.changes.entry(change.hash()) {
+                e.insert(change.raw_bytes().to_vec());
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    fn list_changes(&self) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+        let mut out: Vec<Change> = Vec::with_capacity(self.changes.len());
+        for data in self.changes.values() {
+            out.push(Change::from_bytes(data.clone())?);
+        }
+        Ok(out)
+    }
+
+    fn compact(&mut self, snapshot: &[u8], covered: &[ChangeHash]) -> Result<(), Box<dyn std::error::Error>> {
+        self.snapshot = Some(snapshot.to_vec());
+        for hash in covered {
+            self.changes.remove(hash);
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<Lock, Box<dyn std::error::Error>> {
+        Ok(Lock::new(FlagLock::acquire(&self.locked, "memory")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::memory::MemoryBackend;
+    use crate::storage::tests::exercise_backend;
+
+    #[test]
+    fn test_memory_backend() {
+        exercise_backend(&mut MemoryBackend::default());
+    }
+}
@@ -0,0 +1,174 @@
+/*
+
+Storage holds the ways a project document can be persisted and shared between processes and machines.
+
+A project is persisted as an optional compacted snapshot (a full automerge save) plus a set of individual automerge
+changes that have been appended since. Loading applies the changes on top of the snapshot, automerge ignores any that
+the snapshot already contains and orders the rest by their dependencies. Backends only need to store opaque blobs
+keyed by change hash so new backends can be added without knowing anything about items.
+
+ */
+
+use std::any::Any;
+
+use automerge::{AutoCommit, Change, ChangeHash};
+
+use crate::item::{decode_project, init_project as init_document, Project};
+
+pub mod fs;
+pub mod git;
+pub mod memory;
+pub mod sqlite;
+
+pub trait Backend {
+    // load_snapshot returns the bytes of the last compacted snapshot if there is one.
+    fn load_snapshot(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>>;
+    // append_changes stores each change that is not already stored and returns how many were new.
+    fn append_changes(&mut self, changes: &[&Change]) -> Result<usize, Box<dyn std::error::Error>>;
+    // list_changes returns every stored change that has been appended since the last compaction.
+    fn list_changes(&self) -> Result<Vec<Change>, Box<dyn std::error::Error>>;
+    // compact replaces the snapshot and discards the stored changes that the snapshot covers.
+    fn compact(&mut self, snapshot: &[u8], covered: &[ChangeHash]) -> Result<(), Box<dyn std::error::Error>>;
+    // lock takes the exclusive write lock for the store, it is held until the returned Lock is dropped.
+    fn lock(&self) -> Result<Lock, Box<dyn std::error::Error>>;
+}
+
+// Lock is an opaque guard for a backend write lock. Dropping it releases the lock.
+pub struct Lock {
+    _guard: Box<dyn Any>,
+}
+
+impl Lock {
+    pub fn new(guard: impl Any) -> Lock {
+        Lock { _guard: Box::new(guard) }
+    }
+}
+
+// load_document rebuilds the automerge document from the snapshot and changes in the backend.
+pub fn load_document(backend: &dyn Backend) -> Result<AutoCommit, Box<dyn std::error::Error>> {
+    // the changes are read before the snapshot so that a compaction happening in between can only move changes into
+    // the snapshot we are about to read rather than out of view
+    let changes = backend.list_changes()?;
+    let mut doc = match backend.load_snapshot()? {
+        Some(snapshot) => AutoCommit::load(&snapshot)?,
+        None => AutoCommit::new(),
+    };
+    doc.apply_changes(changes)?;
+    Ok(doc)
+}
+
+// load_project loads the document and decodes the project from it. A backend with nothing in it yet is an empty project.
+pub fn load_project(backend: &dyn Backend) -> Result<(AutoCommit, Project), Box<dyn std::error::Error>> {
+    let mut doc = load_document(backend)?;
+    if doc.get_heads().is_empty() {
+        return Ok((doc, Project::default()));
+    }
+    let project = decode_project(doc.document())?;
+    Ok((doc, project))
+}
+
+// init_project creates a new empty project in the backend. This must happen once before several processes or clones
+// open the project so that they all share the same root node.
+pub fn init_project(backend: &mut dyn Backend) -> Result<(AutoCommit, Project), Box<dyn std::error::Error>> {
+    let mut doc = load_document(backend)?;
+    let project = init_document(&mut doc)?;
+    save_project(backend, &mut doc)?;
+    Ok((doc, project))
+}
+
+// refresh_project merges any changes in the backend that the document does not have yet, such as those saved by
+// another process, into the document and redecodes the project if there were any. Local changes are kept, nothing is
+// overwritten. Returns whether anything new was merged.
+pub fn refresh_project(backend: &dyn Backend, doc: &mut AutoCommit, project: &mut Project) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut stored = load_document(backend)?;
+    let before = doc.get_heads();
+    if doc.merge(&mut stored)? == before {
+        return Ok(false);
+    }
+    *project = decode_project(doc.document())?;
+    Ok(true)
+}
+
+// save_project appends any changes in the document that the backend does not have yet.
+pub fn save_project(backend: &mut dyn Backend, doc: &mut AutoCommit) -> Result<usize, Box<dyn std::error::Error>> {
+    let _lock = backend.lock()?;
+    // changes already folded into the snapshot must not be appended again, otherwise compaction would never shrink
+    // the store. Heads we don't know about locally (from another process) can't be used to filter.
+    let mut covered: Vec<ChangeHash> = Vec::new();
+    if let Some(snapshot) = backend.load_snapshot()? {
+        for head in AutoCommit::load(&snapshot)?.get_heads() {
+            if doc.get_change_by_hash(&head).is_some() {
+                covered.push(head);
+            }
+        }
+    }
+    let changes = doc.get_changes(&covered);
+    backend.append_changes(&changes)
+}
+
+// compact_project saves the document and then replaces the stored history with a single snapshot of it. Anything
+// another process saved is merged in first, under the lock, so that the snapshot never drops changes it doesn't cover,
+// and the project is redecoded if that brought in anything new.
+pub fn compact_project(backend: &mut dyn Backend, doc: &mut AutoCommit, project: &mut Project) -> Result<(), Box<dyn std::error::Error>> {
+    let _lock = backend.lock()?;
+    refresh_project(backend, doc, project)?;
+    let covered: Vec<ChangeHash> = doc.get_changes(&[]).iter().map(|c| c.hash()).collect();
+    backend.append_changes(&doc.get_changes(&[]))?;
+    let snapshot = doc.save();
+    backend.compact(&snapshot, &covered)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::rc::Rc;
+
+    use crate::item::{Item, ItemUpdate};
+    use crate::storage::{compact_project, init_project, load_project, save_project, Backend};
+
+    pub(crate) fn new_item(id: &str, parent: Option<&str>) -> Item {
+        Item {
+            id: Rc::from(id),
+            parent: parent.map(Rc::from),
+            content: Rc::from(format!("{} content", id).as_bytes()),
+            ..Default::default()
+        }
+    }
+
+    // exercise_backend runs the same save, load, compact and lock checks against any backend.
+    pub(crate) fn exercise_backend(backend: &mut dyn Backend) {
+        let (_, project) = load_project(backend).unwrap();
+        assert!(project.list_items().is_empty());
+        let (mut doc, mut project) = init_project(backend).unwrap();
+
+        project.with_item(&new_item("item-a", None), &mut doc).unwrap();
+        project.with_item(&new_item("item-b", Some("item-a")), &mut doc).unwrap();
+        let saved = save_project(backend, &mut doc).unwrap();
+        assert!(saved > 0);
+        assert_eq!(save_project(backend, &mut doc).unwrap(), 0);
+
+        let (mut loaded_doc, loaded) = load_project(backend).unwrap();
+        assert_eq!(loaded_doc.get_heads(), doc.get_heads());
+        assert_eq!(loaded.list_children(Some("item-a")).len(), 1);
+
+        compact_project(backend, &mut doc, &mut project).unwrap();
+        assert!(backend.load_snapshot().unwrap().is_some());
+        project.with_updated_item("item-b", &[ItemUpdate::Rank(3)], &mut doc).unwrap();
+        save_project(backend, &mut doc).unwrap();
+
+        let (mut loaded_doc, loaded) = load_project(backend).unwrap();
+        assert_eq!(loaded_doc.get_heads(), doc.get_heads());
+        assert_eq!(loaded.get_item("item-b").unwrap().rank, 3);
+
+        let lock = backend.lock().unwrap();
+        drop(lock);
+        assert!(backend.lock().is_ok());
+    }
+
+    #[test]
+    fn test_load_empty_project() {
+        let backend = crate::storage::memory::MemoryBackend::default();
+        let (mut doc, project) = load_project(&backend).unwrap();
+        assert!(doc.get_heads().is_empty());
+        assert!(!project.has_children(None));
+    }
+}
@@ -0,0 +1,288 @@
+/*
+
+The sqlite store keeps the automerge changes of a project in a `changes` table and alongside them maintains a
+materialized `items` table so that external tools can query the current state of the project with plain SQL without
+understanding automerge.
+
+The items table is rewritten in the same transaction as new changes are appended, so as long as every Project mutation
+(or batch of mutations) is followed by a save it always reflects the current state.
+
+    snapshot(id INTEGER, data BLOB)
+    changes(hash TEXT PRIMARY KEY, actor TEXT, seq INTEGER, timestamp INTEGER, message TEXT, data BLOB)
+    items(id TEXT PRIMARY KEY, parent TEXT, rank INTEGER, class TEXT, content_type TEXT, at INTEGER, content)
+
+`at` is stored as unix milliseconds as it is in the document and the change `timestamp` as unix seconds as recorded by
+automerge. `content` is stored as TEXT for text content types and as a BLOB otherwise.
+
+ */
+
+use std::cell::Cell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use automerge::{AutoCommit, Change, ChangeHash};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+
+use crate::error::AuError;
+use crate::item::{decode_project, Project};
+use crate::storage::fs::{LockFile, DEFAULT_LOCK_WAIT};
+use crate::storage::memory::FlagLock;
+use crate::storage::{Backend, Lock};
+
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS snapshot (
+    id INTEGER PRIMARY KEY CHECK (id = 0),
+    data BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS changes (
+    hash TEXT PRIMARY KEY,
+    actor TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    message TEXT,
+    data BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS items (
+    id TEXT PRIMARY KEY,
+    parent TEXT,
+    rank INTEGER NOT NULL,
+    class TEXT,
+    content_type TEXT NOT NULL,
+    at INTEGER NOT NULL,
+    content
+);
+CREATE INDEX IF NOT EXISTS items_parent ON items (parent);
+";
+
+pub struct SqliteStore {
+    conn: Connection,
+    lock_path: Option<PathBuf>,
+    locked: Rc<Cell<bool>>,
+}
+
+impl SqliteStore {
+    // open the database at the path, creating it and the tables if they don't exist.
+    pub fn open(path: &Path) -> Result<SqliteStore, Box<dyn std::error::Error>> {
+        let mut lock_path = path.as_os_str().to_os_string();
+        lock_path.push(".lock");
+        SqliteStore::with_connection(Connection::open(path)?, Some(PathBuf::from(lock_path)))
+    }
+
+    pub fn open_in_memory() -> Result<SqliteStore, Box<dyn std::error::Error>> {
+        SqliteStore::with_connection(Connection::open_in_memory()?, None)
+    }
+
+    fn with_connection(conn: Connection, lock_path: Option<PathBuf>) -> Result<SqliteStore, Box<dyn std::error::Error>> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(SqliteStore {
+            conn,
+            lock_path,
+            locked: Rc::new(Cell::new(false)),
+        })
+    }
+
+    // connection gives access to the underlying database for queries against the materialized tables.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl Backend for SqliteStore {
+    fn load_snapshot(&self) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        read_snapshot(&self.conn)
+    }
+
+    fn append_changes(&mut self, changes: &[&Change]) -> Result<usize, Box<dyn std::error::Error>> {
+        // the write lock is taken up front so that the document read back below is exactly what the items table is
+        // built from, including changes appended through other connections since this one was opened
+        let tx = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut written = 0;
+        {
+            let mut insert_change =
+                tx.prepare("INSERT OR IGNORE INTO changes (hash, actor, seq, timestamp, message, data) VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for change in changes {
+                let inserted = insert_change.execute(params![
+                    change.hash().to_string(),
+                    change.actor_id().to_hex_string(),
+                    change.seq() as i64,
+                    change.timestamp(),
+                    change.message(),
+                    change.raw_bytes(),
+                ])?;
+                written += inserted;
+            }
+        }
+        if written > 0 {
+            let mut doc = read_document(&tx)?;
+            if !doc.get_heads().is_empty() {
+                materialize(&tx, &decode_project(doc.document())?)?;
+            }
+            tx.commit()?;
+        }
+        Ok(written)
+    }
+
+    fn list_changes(&self) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+        read_changes(&self.conn)
+    }
+
+    fn compact(&mut self, snapshot: &[u8], covered: &[ChangeHash]) -> Result<(), Box<dyn std::error::Error>> {
+        let tx = self.conn.transaction()?;
+        tx.execute("INSERT OR REPLACE INTO snapshot (id, data) VALUES (0, ?1)", params![snapshot])?;
+        {
+            let mut delete_change = tx.prepare("DELETE FROM changes WHERE hash = ?1")?;
+            for hash in covered {
+                delete_change.execute(params![hash.to_string()])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<Lock, Box<dyn std::error::Error>> {
+        match self.lock_path {
+            Some(ref path) => Ok(Lock::new(LockFile::acquire(path, DEFAULT_LOCK_WAIT)?)),
+            None => Ok(Lock::new(FlagLock::acquire(&self.locked, "sqlite")?)),
+        }
+    }
+}
+
+fn read_snapshot(conn: &Connection) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    Ok(conn
+        .query_row("SELECT data FROM snapshot WHERE id = 0", [], |r| r.get(0))
+        .optional()?)
+}
+
+fn read_changes(conn: &Connection) -> Result<Vec<Change>, Box<dyn std::error::Error>> {
+    let mut stmt = conn.prepare("SELECT hash, data FROM changes")?;
+    let mut rows = stmt.query([])?;
+    let mut changes: Vec<Change> = Vec::new();
+    while let Some(row) = rows.next()? {
+        let hash: String = row.get(0)?;
+        let data: Vec<u8> = row.get(1)?;
+        changes.push(Change::from_bytes(data).map_err(|e| AuError::NestedError(Box::from(hash), Box::new(e)))?);
+    }
+    Ok(changes)
+}
+
+// read_document builds the document from the snapshot and changes as the connection sees them, which inside a
+// transaction includes what it has written but not yet committed.
+fn read_document(conn: &Connection) -> Result<AutoCommit, Box<dyn std::error::Error>> {
+    let mut doc = match read_snapshot(conn)? {
+        Some(snapshot) => AutoCommit::load(&snapshot)?,
+        None => AutoCommit::new(),
+    };
+    doc.apply_changes(read_changes(conn)?)?;
+    Ok(doc)
+}
+
+// materialize rewrites the items table to match the project.
+fn materialize(tx: &Transaction, project: &Project) -> Result<(), Box<dyn std::error::Error>> {
+    tx.execute("DELETE FROM items", [])?;
+    let mut insert_item =
+        tx.prepare("INSERT INTO items (id, parent, rank, class, content_type, at, content) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?;
+    for item in project.list_items() {
+        let content = match std::str::from_utf8(item.content.as_ref()) {
+            Ok(s) if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) => SqlValue::Text(s.to_string()),
+            _ => SqlValue::Blob(item.content.to_vec()),
+        };
+        insert_item.execute(params![
+            item.id.as_ref(),
+            item.parent.as_deref(),
+            item.rank,
+            item.class.as_deref(),
+            item.content_type.as_ref(),
+            (item.at.unix_timestamp_nanos() / 1_000_000) as i64,
+            content,
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::item::ItemUpdate;
+    use crate::storage::sqlite::SqliteStore;
+    use crate::storage::tests::{exercise_backend, new_item};
+    use crate::storage::{compact_project, init_project, load_project, save_project};
+
+    fn query_i64(store: &SqliteStore, sql: &str) -> i64 {
+        store.connection().query_row(sql, [], |r| r.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_sqlite_backend() {
+        exercise_backend(&mut SqliteStore::open_in_memory().unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.sqlite");
+        exercise_backend(&mut SqliteStore::open(&path).unwrap());
+
+        // reopening rebuilds the same document
+        let store = SqliteStore::open(&path).unwrap();
+        let (_, project) = load_project(&store).unwrap();
+        assert_eq!(project.list_items().len(), 2);
+        assert_eq!(query_i64(&store, "SELECT COUNT(*) FROM items"), 2);
+    }
+
+    #[test]
+    fn test_two_handles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("project.sqlite");
+        let mut first = SqliteStore::open(&path).unwrap();
+        init_project(&mut first).unwrap();
+        let mut second = SqliteStore::open(&path).unwrap();
+        let (mut first_doc, mut first_project) = load_project(&first).unwrap();
+        let (mut second_doc, mut second_project) = load_project(&second).unwrap();
+
+        // each handle saves an item the other has not seen, and the items table keeps both
+        first_project.with_item(&new_item("item-a", None), &mut first_doc).unwrap();
+        save_project(&mut first, &mut first_doc).unwrap();
+        second_project.with_item(&new_item("item-b", None), &mut second_doc).unwrap();
+        save_project(&mut second, &mut second_doc).unwrap();
+        assert_eq!(query_i64(&first, "SELECT COUNT(*) FROM items"), 2);
+        assert_eq!(load_project(&first).unwrap().1.list_items().len(), 2);
+    }
+
+    #[test]
+    fn test_materialized_items() {
+        let mut store = SqliteStore::open_in_memory().unwrap();
+        let (mut doc, mut project) = load_project(&store).unwrap();
+        project.with_item(&new_item("item-a", None), &mut doc).unwrap();
+        project.with_item(&new_item("item-b", Some("item-a")), &mut doc).unwrap();
+        let mut binary = new_item("item-c", Some("item-a"));
+        binary.content_type = Rc::from("application/octet-stream");
+        project.with_item(&binary, &mut doc).unwrap();
+        save_project(&mut store, &mut doc).unwrap();
+
+        assert_eq!(query_i64(&store, "SELECT COUNT(*) FROM items WHERE parent = 'item-a'"), 2);
+        let content: String = store
+            .connection()
+            .query_row("SELECT content FROM items WHERE id = 'item-b'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(content, "item-b content");
+        let content_kind: String = store
+            .connection()
+            .query_row("SELECT typeof(content) FROM items WHERE id = 'item-c'", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(content_kind, "blob");
+
+        // updates and deletes are reflected after the next save, even across a compaction
+        compact_project(&mut store, &mut doc, &mut project).unwrap();
+        assert_eq!(query_i64(&store, "SELECT COUNT(*) FROM changes"), 0);
+        project
+            .with_updated_item("item-b", &[ItemUpdate::Parent(None), ItemUpdate::Rank(5)], &mut doc)
+            .unwrap();
+        project.without_item("item-c", &mut doc).unwrap();
+        save_project(&mut store, &mut doc).unwrap();
+
+        assert_eq!(query_i64(&store, "SELECT COUNT(*) FROM items"), 2);
+        assert_eq!(query_i64(&store, "SELECT COUNT(*) FROM items WHERE parent IS NULL"), 2);
+        assert_eq!(query_i64(&store, "SELECT rank FROM items WHERE id = 'item-b'"), 5);
+    }
+}
@@ -0,0 +1,596 @@
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use automerge::{AutoCommit, ChangeHash};
+use similar::TextDiff;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use au::batch::{apply as apply_batch, parse_ndjson};
+use au::error::AuError;
+use au::history::{diff_heads, is_moved, log as change_log, ItemDiff};
+use au::id::IdGen;
+use au::item::{Item, ItemUpdate, Project};
+use au::path::{resolve, resolve_item};
+use au::record::ItemRecord;
+use au::storage::{init_project, load_project, save_project, Backend};
+
+use crate::format::{write_diff, write_listing, write_log, write_record, DiffRecord, Format, LogRecord};
+
+const SUMMARY_WIDTH: usize = 80;
+const TREE_INDENT: usize = 2;
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+const CONTENT_TYPE_MARKDOWN: &str = "text/markdown";
+const DIFF_CONTEXT_LINES: usize = 3;
+
+// NewItem holds the fields of an item to be added, the rest are generated.
+pub struct NewItem {
+    pub content: Vec<u8>,
+    pub parent: Option<String>,
+    pub class: Option<String>,
+    pub content_type: String,
+    pub rank: Option<i64>,
+}
+
+pub fn init(backend: &mut dyn Backend, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, _) = load(backend)?;
+    if !doc.get_heads().is_empty() {
+        return Err(Box::from("project already initialised"));
+    }
+    init_project(backend)?;
+    writeln!(out, "initialised empty project")?;
+    Ok(())
+}
+
+pub fn add(backend: &mut dyn Backend, new_item: NewItem, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, mut project) = load(backend)?;
+    let parent = get_parent(&project, new_item.parent.as_deref())?;
+    // new items go to the bottom of their siblings unless a rank is given
+    let rank = new_item.rank.unwrap_or_else(|| project.bottom_rank(parent.as_deref()));
+    let item = Item {
+        id: Rc::from(IdGen::default().gen(rand::thread_rng())),
+        at: OffsetDateTime::now_utc(),
+        class: new_item.class.map(Rc::from),
+        content_type: Rc::from(new_item.content_type),
+        content: Rc::from(new_item.content),
+        rank,
+        parent,
+    };
+    project.transact(&mut doc, format!("add {}", item.id).as_str(), |p, d| {
+        p.with_item(&item, d).map(|_| ())
+    })?;
+    save_project(backend, &mut doc)?;
+    writeln!(out, "{}", item.id)?;
+    Ok(())
+}
+
+pub fn ls(
+    backend: &mut dyn Backend,
+    parent: Option<&str>,
+    tree: bool,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (_, project) = load(backend)?;
+    let parent = get_parent(&project, parent)?;
+    let parent = parent.as_deref();
+    let mut records: Vec<ItemRecord> = Vec::new();
+    let mut stack: Vec<(usize, Rc<Item>)> = project.list_children(parent).into_iter().rev().map(|i| (0, i)).collect();
+    while let Some((depth, item)) = stack.pop() {
+        if format == Format::Text {
+            writeln!(
+                out,
+                "{}{}  {}",
+                " ".repeat(depth * TREE_INDENT),
+                item.id,
+                item.summary(SUMMARY_WIDTH)
+            )?;
+        } else {
+            records.push(ItemRecord::listing(&item, depth)?);
+        }
+        if tree {
+            stack.extend(
+                project
+                    .list_children(Some(item.id.as_ref()))
+                    .into_iter()
+                    .rev()
+                    .map(|i| (depth + 1, i)),
+            );
+        }
+    }
+    if format != Format::Text {
+        write_listing(format, &records, out)?;
+    }
+    Ok(())
+}
+
+pub fn show(
+    backend: &mut dyn Backend,
+    id: &str,
+    content_only: bool,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (_, project) = load(backend)?;
+    let item = resolve_item(&project, None, id)?;
+    if content_only {
+        out.write_all(item.content.as_ref())?;
+        return Ok(());
+    }
+    if format != Format::Text {
+        return write_record(format, &ItemRecord::show(&item)?, out);
+    }
+    writeln!(out, "id:           {}", item.id)?;
+    writeln!(out, "parent:       {}", item.parent.as_deref().unwrap_or(""))?;
+    writeln!(out, "class:        {}", item.class.as_deref().unwrap_or(""))?;
+    writeln!(out, "content_type: {}", item.content_type)?;
+    writeln!(out, "rank:         {}", item.rank)?;
+    writeln!(out, "at:           {}", item.at.format(&Rfc3339)?)?;
+    writeln!(out)?;
+    match std::str::from_utf8(item.content.as_ref()) {
+        Ok(s) if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) => writeln!(out, "{}", s)?,
+        _ => writeln!(out, "{}", item.summary(SUMMARY_WIDTH))?,
+    }
+    Ok(())
+}
+
+pub fn mv(
+    backend: &mut dyn Backend,
+    id: &str,
+    parent: Option<&str>,
+    rank: Option<i64>,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, mut project) = load(backend)?;
+    let id = resolve_item(&project, None, id)?.id.clone();
+    let id = id.as_ref();
+    let parent = get_parent(&project, parent)?;
+    let mut updates = vec![ItemUpdate::Parent(parent.as_deref().map(Box::from))];
+    if let Some(r) = rank {
+        updates.push(ItemUpdate::Rank(r));
+    }
+    let message = match parent {
+        Some(p) => format!("move {} under {}", id, p),
+        None => format!("move {} to the top level", id),
+    };
+    project.transact(&mut doc, message.as_str(), |p, d| p.with_updated_item(id, &updates, d).map(|_| ()))?;
+    save_project(backend, &mut doc)?;
+    writeln!(out, "{}", id)?;
+    Ok(())
+}
+
+pub fn rm(backend: &mut dyn Backend, id: &str, recursive: bool, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, mut project) = load(backend)?;
+    let id = resolve_item(&project, None, id)?.id.clone();
+    let id = id.as_ref();
+    let targets = if recursive { subtree(&project, id) } else { vec![Box::from(id)] };
+    let message = match targets.len() {
+        1 => format!("remove {}", id),
+        n => format!("remove {} and {} descendants", id, n - 1),
+    };
+    project.transact(&mut doc, message.as_str(), |p, d| {
+        // children must go before their parents
+        for target in targets.iter().rev() {
+            p.without_item(target, d)?;
+        }
+        Ok(())
+    })?;
+    save_project(backend, &mut doc)?;
+    for target in targets {
+        writeln!(out, "{}", target)?;
+    }
+    Ok(())
+}
+
+pub fn edit(backend: &mut dyn Backend, id: &str, editor: &str, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, mut project) = load(backend)?;
+    let item = resolve_item(&project, None, id)?;
+    let id = item.id.as_ref();
+    if !item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) {
+        return Err(Box::from(format!("'{}': cannot edit binary {} content", id, item.content_type)));
+    }
+    let suffix = if item.content_type.starts_with(CONTENT_TYPE_MARKDOWN) {
+        ".md"
+    } else {
+        ".txt"
+    };
+    let file = tempfile::Builder::new().prefix("au-").suffix(suffix).tempfile()?;
+    fs::write(file.path(), item.content.as_ref())?;
+
+    // the editor may carry its own arguments, for example "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or("no editor configured")?;
+    let status = process::Command::new(program).args(parts).arg(file.path()).status()?;
+    if !status.success() {
+        return Err(Box::from(format!("editor exited with {}", status)));
+    }
+
+    let new_content = fs::read(file.path())?;
+    if new_content.as_slice() == item.content.as_ref() {
+        writeln!(out, "no changes")?;
+        return Ok(());
+    }
+    let update = ItemUpdate::Content(Box::from(item.content_type.as_ref()), Box::from(new_content));
+    project.transact(&mut doc, format!("edit {}", id).as_str(), |p, d| {
+        p.with_updated_item(id, &[update], d).map(|_| ())
+    })?;
+    save_project(backend, &mut doc)?;
+    writeln!(out, "{}", id)?;
+    Ok(())
+}
+
+pub fn log(backend: &mut dyn Backend, limit: Option<usize>, format: Format, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, _) = load(backend)?;
+    let mut records: Vec<LogRecord> = Vec::new();
+    for entry in change_log(doc.document(), limit)?.iter() {
+        records.push(LogRecord::new(entry)?);
+    }
+    if format != Format::Text {
+        return write_log(format, &records, out);
+    }
+    for record in records {
+        writeln!(out, "change {}", record.hash)?;
+        writeln!(out, "actor:   {}", record.actor)?;
+        writeln!(out, "date:    {}", record.timestamp)?;
+        if let Some(message) = record.message {
+            writeln!(out)?;
+            for line in message.lines() {
+                writeln!(out, "    {}", line)?;
+            }
+        }
+        writeln!(out)?;
+        for (label, ids) in [
+            ("added", &record.added),
+            ("updated", &record.updated),
+            ("moved", &record.moved),
+            ("deleted", &record.deleted),
+        ] {
+            if !ids.is_empty() {
+                writeln!(out, "    {}: {}", label, ids.join(", "))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// diff shows the item differences between two sets of heads, or between the given heads and the current state.
+pub fn diff(
+    backend: &mut dyn Backend,
+    before: &str,
+    after: Option<&str>,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut doc, _) = load(backend)?;
+    let before = parse_heads(before)?;
+    let after = match after {
+        Some(a) => parse_heads(a)?,
+        None => doc.get_heads(),
+    };
+    let diffs = diff_heads(doc.document(), &before, &after)?;
+    if format != Format::Text {
+        let records = diffs.iter().map(DiffRecord::new).collect::<Result<Vec<DiffRecord>, _>>()?;
+        return write_diff(format, &records, out);
+    }
+    for diff in diffs {
+        match diff {
+            ItemDiff::Added(item) => writeln!(out, "added    {}  {}", item.id, item.summary(SUMMARY_WIDTH))?,
+            ItemDiff::Deleted(item) => writeln!(out, "deleted  {}  {}", item.id, item.summary(SUMMARY_WIDTH))?,
+            ItemDiff::Updated(b, a) => {
+                writeln!(out, "updated  {}  {}", a.id, a.summary(SUMMARY_WIDTH))?;
+                write_item_changes(&b, &a, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// write_item_changes writes the fields that differ between two versions of an item, with a unified line diff for text.
+fn write_item_changes(before: &Item, after: &Item, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    if is_moved(before, after) {
+        writeln!(
+            out,
+            "    parent: {} -> {}",
+            before.parent.as_deref().unwrap_or("-"),
+            after.parent.as_deref().unwrap_or("-")
+        )?;
+        writeln!(out, "    rank: {} -> {}", before.rank, after.rank)?;
+    }
+    if before.class != after.class {
+        writeln!(
+            out,
+            "    class: {} -> {}",
+            before.class.as_deref().unwrap_or("-"),
+            after.class.as_deref().unwrap_or("-")
+        )?;
+    }
+    if before.content_type != after.content_type {
+        writeln!(out, "    content_type: {} -> {}", before.content_type, after.content_type)?;
+    }
+    if before.content == after.content {
+        return Ok(());
+    }
+    let is_text = |i: &Item| i.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX);
+    match (
+        std::str::from_utf8(before.content.as_ref()),
+        std::str::from_utf8(after.content.as_ref()),
+    ) {
+        (Ok(b), Ok(a)) if is_text(before) && is_text(after) => {
+            let text_diff = TextDiff::from_lines(b, a);
+            let mut unified = text_diff.unified_diff();
+            unified.context_radius(DIFF_CONTEXT_LINES).missing_newline_hint(false);
+            for hunk in unified.iter_hunks() {
+                for line in hunk.to_string().lines() {
+                    writeln!(out, "    {}", line)?;
+                }
+            }
+        }
+        _ => writeln!(out, "    content: {} bytes -> {} bytes", before.content.len(), after.content.len())?,
+    }
+    Ok(())
+}
+
+// apply runs a batch of operations read from the input as a single change, or only checks them when dry_run is set.
+// The ids generated for the placeholders of the batch are written out.
+pub fn apply(
+    backend: &mut dyn Backend,
+    input: impl BufRead,
+    message: &str,
+    dry_run: bool,
+    format: Format,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ops = parse_ndjson(input)?;
+    let (mut doc, mut project) = load(backend)?;
+    let id_gen = IdGen::default();
+    let new_id = || id_gen.gen(rand::thread_rng());
+    let result = if dry_run {
+        apply_batch(&mut project.clone(), &mut doc.fork(), &ops, message, new_id)?
+    } else {
+        let result = apply_batch(&mut project, &mut doc, &ops, message, new_id)?;
+        save_project(backend, &mut doc)?;
+        result
+    };
+    match format {
+        Format::Json | Format::Ndjson => writeln!(out, "{}", serde_json::to_string(&result.placeholders)?)?,
+        _ => {
+            for (placeholder, id) in result.placeholders {
+                writeln!(out, "{}\t{}", placeholder, id)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// parse_heads parses comma separated change hashes. The empty string is the empty project before any change.
+fn parse_heads(heads: &str) -> Result<Vec<ChangeHash>, Box<dyn std::error::Error>> {
+    heads
+        .split(',')
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .map(|h| {
+            ChangeHash::from_str(h).map_err(|_| {
+                Box::new(AuError::InvalidField(
+                    Box::from("heads"),
+                    Box::from(format!("'{}' is not a change hash", h)),
+                )) as Box<dyn std::error::Error>
+            })
+        })
+        .collect()
+}
+
+// CorruptStore marks a failure to decode what is stored, as opposed to an error about the command arguments.
+#[derive(Debug)]
+pub struct CorruptStore(pub Box<dyn std::error::Error>);
+
+impl std::fmt::Display for CorruptStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'project': {}", self.0)
+    }
+}
+
+impl std::error::Error for CorruptStore {}
+
+// load reads the project from the backend, anything other than an io failure means the store could not be decoded.
+pub fn load(backend: &dyn Backend) -> Result<(AutoCommit, Project), Box<dyn std::error::Error>> {
+    load_project(backend).map_err(|e| if e.is::<io::Error>() { e } else { Box::new(CorruptStore(e)) })
+}
+
+// get_parent resolves an optional parent reference where a path to the top level means no parent.
+fn get_parent(project: &Project, reference: Option<&str>) -> Result<Option<Rc<str>>, Box<dyn std::error::Error>> {
+    match reference {
+        Some(r) => Ok(resolve(project, None, r)?.map(|i| i.id.clone())),
+        None => Ok(None),
+    }
+}
+
+// subtree returns the id and all descendant ids of the item with parents always before their children.
+fn subtree(project: &Project, id: &str) -> Vec<Box<str>> {
+    let mut out: Vec<Box<str>> = vec![Box::from(id)];
+    let mut i = 0;
+    while i < out.len() {
+        for child in project.list_children(Some(out[i].as_ref())) {
+            out.push(Box::from(child.id.as_ref()));
+        }
+        i += 1;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use au::storage::load_project;
+    use au::storage::memory::MemoryBackend;
+
+    use crate::commands::{add, apply, diff, edit, init, log, ls, mv, rm, show, NewItem};
+    use crate::format::Format;
+
+    fn add_text(backend: &mut MemoryBackend, content: &str, parent: Option<&str>) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        let new_item = NewItem {
+            content: content.as_bytes().to_vec(),
+            parent: parent.map(String::from),
+            class: None,
+            content_type: String::from("text/plain"),
+            rank: None,
+        };
+        add(backend, new_item, &mut out).unwrap();
+        String::from_utf8(out).unwrap().trim().to_string()
+    }
+
+    fn output(f: impl FnOnce(&mut Vec<u8>)) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        f(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_init_twice() {
+        let mut backend = MemoryBackend::default();
+        init(&mut backend, &mut Vec::new()).unwrap();
+        assert_eq!(
+            init(&mut backend, &mut Vec::new()).err().unwrap().to_string(),
+            "project already initialised"
+        );
+    }
+
+    #[test]
+    fn test_add_ls_show() {
+        let mut backend = MemoryBackend::default();
+        init(&mut backend, &mut Vec::new()).unwrap();
+        let a = add_text(&mut backend, "first\nmore", None);
+        let b = add_text(&mut backend, "second", None);
+        let c = add_text(&mut backend, "child", Some(a.as_str()));
+
+        assert_eq!(
+            output(|o| ls(&mut backend, None, false, Format::Text, o).unwrap()),
+            format!("{}  first\n{}  second\n", a, b)
+        );
+        assert_eq!(
+            output(|o| ls(&mut backend, None, true, Format::Text, o).unwrap()),
+            format!("{}  first\n  {}  child\n{}  second\n", a, c, b)
+        );
+        assert_eq!(
+            output(|o| ls(&mut backend, Some(a.as_str()), false, Format::Text, o).unwrap()),
+            format!("{}  child\n", c)
+        );
+
+        let shown = output(|o| show(&mut backend, c.as_str(), false, Format::Text, o).unwrap());
+        assert!(shown.starts_with(format!("id:           {}\nparent:       {}\n", c, a).as_str()));
+        assert!(shown.ends_with("\n\nchild\n"));
+        assert_eq!(
+            output(|o| show(&mut backend, a.as_str(), true, Format::Text, o).unwrap()),
+            "first\nmore"
+        );
+        assert_eq!(
+            show(&mut backend, "missing", false, Format::Json, &mut Vec::new())
+                .err()
+                .unwrap()
+                .to_string(),
+            "'missing': no such key"
+        );
+        let json = output(|o| show(&mut backend, c.as_str(), false, Format::Json, o).unwrap());
+        assert!(json.starts_with(format!("{{\"id\":\"{}\",\"parent\":\"{}\"", c, a).as_str()));
+        let ndjson = output(|o| ls(&mut backend, None, true, Format::Ndjson, o).unwrap());
+        assert_eq!(ndjson.lines().count(), 3);
+
+        // items can also be named by path
+        assert_eq!(
+            output(|o| show(&mut backend, "/first/child", true, Format::Text, o).unwrap()),
+            "child"
+        );
+        assert_eq!(
+            output(|o| ls(&mut backend, Some("/"), false, Format::Text, o).unwrap())
+                .lines()
+                .count(),
+            2
+        );
+        mv(&mut backend, "first/child", Some("second"), None, &mut Vec::new()).unwrap();
+        assert_eq!(
+            output(|o| ls(&mut backend, Some("second"), false, Format::Text, o).unwrap()),
+            format!("{}  child\n", c)
+        );
+    }
+
+    #[test]
+    fn test_mv_rm() {
+        let mut backend = MemoryBackend::default();
+        init(&mut backend, &mut Vec::new()).unwrap();
+        let a = add_text(&mut backend, "a", None);
+        let b = add_text(&mut backend, "b", Some(a.as_str()));
+        let c = add_text(&mut backend, "c", Some(b.as_str()));
+
+        // moving an item under its own descendant is a cycle
+        assert!(mv(&mut backend, a.as_str(), Some(c.as_str()), None, &mut Vec::new()).is_err());
+        mv(&mut backend, c.as_str(), None, Some(10), &mut Vec::new()).unwrap();
+        let (_, project) = load_project(&backend).unwrap();
+        assert_eq!(project.list_children(None).first().unwrap().id.as_ref(), c.as_str());
+
+        assert!(rm(&mut backend, a.as_str(), false, &mut Vec::new()).is_err());
+        assert_eq!(
+            output(|o| rm(&mut backend, a.as_str(), true, o).unwrap()),
+            format!("{}\n{}\n", a, b)
+        );
+        let (_, project) = load_project(&backend).unwrap();
+        assert_eq!(project.list_items().len(), 1);
+    }
+
+    #[test]
+    fn test_log_diff() {
+        let mut backend = MemoryBackend::default();
+        init(&mut backend, &mut Vec::new()).unwrap();
+        let a = add_text(&mut backend, "one\ntwo\nthree\n", None);
+        let heads: Vec<String> = load_project(&backend)
+            .unwrap()
+            .0
+            .get_heads()
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let b = add_text(&mut backend, "b", Some(a.as_str()));
+        edit(&mut backend, a.as_str(), "sed -i s/two/2/", &mut Vec::new()).unwrap();
+
+        let logged = output(|o| log(&mut backend, Some(2), Format::Text, o).unwrap());
+        assert_eq!(logged.matches("change ").count(), 2);
+        assert!(logged.contains(format!("    edit {}\n\n    updated: {}\n", a, a).as_str()));
+        assert!(logged.contains(format!("    added: {}\n", b).as_str()));
+        let ndjson = output(|o| log(&mut backend, None, Format::Ndjson, o).unwrap());
+        assert_eq!(ndjson.lines().count(), 4);
+
+        let diffed = output(|o| diff(&mut backend, heads.join(",").as_str(), None, Format::Text, o).unwrap());
+        assert!(diffed.contains(format!("updated  {}  one\n", a).as_str()));
+        assert!(diffed.contains("    -two\n    +2\n"));
+        assert!(diffed.contains(format!("added    {}  b\n", b).as_str()));
+        let everything = output(|o| diff(&mut backend, "", None, Format::Tsv, o).unwrap());
+        assert_eq!(everything.lines().count(), 3);
+        assert!(diff(&mut backend, "nope", None, Format::Text, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut backend = MemoryBackend::default();
+        init(&mut backend, &mut Vec::new()).unwrap();
+        let a = add_text(&mut backend, "a", None);
+        let input = format!(
+            "{{\"op\": \"add\", \"ref\": \"$inbox\", \"content\": \"Inbox\"}}\n\
+             {{\"op\": \"move\", \"id\": \"{}\", \"parent\": \"$inbox\"}}\n",
+            a
+        );
+
+        let dry = output(|o| apply(&mut backend, input.as_bytes(), "dry", true, Format::Text, o).unwrap());
+        assert!(dry.starts_with("$inbox\t"));
+        assert_eq!(load_project(&backend).unwrap().1.list_items().len(), 1);
+
+        let json = output(|o| apply(&mut backend, input.as_bytes(), "organise", false, Format::Json, o).unwrap());
+        let placeholders: std::collections::BTreeMap<String, String> = serde_json::from_str(json.as_str()).unwrap();
+        let (_, project) = load_project(&backend).unwrap();
+        assert_eq!(
+            project.get_item(a.as_str()).unwrap().parent.as_deref(),
+            placeholders.get("$inbox").map(|s| s.as_str())
+        );
+    }
+}
@@ -0,0 +1,73 @@
+/*
+
+Dynamic shell completion. The shell calls back into `au` with COMPLETE set and the words typed so far, so the item ids
+offered come from the project as it is at the time of completion, with the item summary as the description.
+
+ */
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use clap_complete::CompletionCandidate;
+
+use au::storage::fs::FsBackend;
+use au::storage::load_project;
+
+const PROJECT_ENV: &str = "AU_PROJECT";
+const DEFAULT_PROJECT: &str = ".au";
+const COMPLETION_SUMMARY_WIDTH: usize = 60;
+
+// item_ids offers every item id in the project. Completion must never fail loudly, so any error offers nothing.
+pub fn item_ids() -> Vec<CompletionCandidate> {
+    let Ok(backend) = FsBackend::open(&project_path(std::env::args_os())) else {
+        return Vec::new();
+    };
+    let Ok((_, project)) = load_project(&backend) else {
+        return Vec::new();
+    };
+    project
+        .list_items()
+        .into_iter()
+        .map(|i| CompletionCandidate::new(i.id.as_ref()).help(Some(i.summary(COMPLETION_SUMMARY_WIDTH).to_string().into())))
+        .collect()
+}
+
+// project_path finds the project the command being completed refers to. The arguments have not been parsed yet, so
+// the project flag is picked out of the words directly before falling back to the environment and the default.
+fn project_path(args: impl Iterator<Item = OsString>) -> PathBuf {
+    let args: Vec<String> = args.map(|a| a.to_string_lossy().to_string()).collect();
+    for (i, arg) in args.iter().enumerate() {
+        if let Some(path) = arg.strip_prefix("--project=") {
+            return PathBuf::from(path);
+        } else if arg == "-p" || arg == "--project" {
+            if let Some(path) = args.get(i + 1) {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    std::env::var_os(PROJECT_ENV).map_or_else(|| PathBuf::from(DEFAULT_PROJECT), PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsString;
+    use std::path::PathBuf;
+
+    use crate::complete::project_path;
+
+    fn args(v: &[&str]) -> impl Iterator<Item = OsString> {
+        v.iter().map(OsString::from).collect::<Vec<OsString>>().into_iter()
+    }
+
+    #[test]
+    fn test_project_path() {
+        assert_eq!(
+            project_path(args(&["au", "--", "au", "-p", "/tmp/x", "show", "7K"])),
+            PathBuf::from("/tmp/x")
+        );
+        assert_eq!(
+            project_path(args(&["au", "--", "au", "show", "--project=other", "7K"])),
+            PathBuf::from("other")
+        );
+    }
+}
@@ -0,0 +1,291 @@
+/*
+
+Machine readable output for listing and show commands. Every format writes au::record::ItemRecord, which describes
+the fields and their encodings, listings use ItemRecord::listing and show uses ItemRecord::show.
+
+The log writes one record per change with the ids of the items it added, updated, moved and deleted, and diff writes
+one record per changed item with the show record of the item before and after.
+
+ */
+
+use std::io::Write;
+use std::rc::Rc;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use au::history::{ItemDiff, LogEntry};
+use au::record::ItemRecord;
+
+const TSV_LISTING_COLUMNS: &[&str] = &["id", "parent", "rank", "class", "content_type", "at", "depth"];
+const TSV_SHOW_COLUMNS: &[&str] = &["id", "parent", "rank", "class", "content_type", "at", "content", "content_encoding"];
+const TSV_LOG_COLUMNS: &[&str] = &[
+    "hash",
+    "actor",
+    "seq",
+    "timestamp",
+    "message",
+    "added",
+    "updated",
+    "moved",
+    "deleted",
+];
+const TSV_DIFF_COLUMNS: &[&str] = &["id", "change"];
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Format {
+    // human readable output
+    #[default]
+    Text,
+    // a single json document
+    Json,
+    // one json document per line
+    Ndjson,
+    // tab separated values with a header row
+    Tsv,
+}
+
+// item_tsv_fields is the row of an item record in the order of the listing or show columns.
+fn item_tsv_fields(record: &ItemRecord) -> Vec<String> {
+    let mut fields = vec![
+        record.id.to_string(),
+        record.parent.as_deref().unwrap_or("").to_string(),
+        record.rank.to_string(),
+        record.class.as_deref().unwrap_or("").to_string(),
+        record.content_type.to_string(),
+        record.at.to_string(),
+    ];
+    if let Some(depth) = record.depth {
+        fields.push(depth.to_string());
+    }
+    if let Some(ref content) = record.content {
+        fields.push(content.to_string());
+        fields.push(record.content_encoding.unwrap_or("").to_string());
+    }
+    fields
+}
+
+#[derive(Serialize)]
+pub struct LogRecord {
+    pub hash: Box<str>,
+    pub actor: Box<str>,
+    pub seq: u64,
+    pub timestamp: Box<str>,
+    pub message: Option<Box<str>>,
+    pub added: Vec<Rc<str>>,
+    pub updated: Vec<Rc<str>>,
+    pub moved: Vec<Rc<str>>,
+    pub deleted: Vec<Rc<str>>,
+}
+
+impl LogRecord {
+    pub fn new(entry: &LogEntry) -> Result<LogRecord, Box<dyn std::error::Error>> {
+        let timestamp = OffsetDateTime::from_unix_timestamp(entry.timestamp)?;
+        Ok(LogRecord {
+            hash: Box::from(entry.hash.to_string()),
+            actor: entry.actor.clone(),
+            seq: entry.seq,
+            timestamp: Box::from(timestamp.format(&Rfc3339)?),
+            message: entry.message.clone(),
+            added: entry.summary.added.clone(),
+            updated: entry.summary.updated.clone(),
+            moved: entry.summary.moved.clone(),
+            deleted: entry.summary.deleted.clone(),
+        })
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![
+            self.hash.to_string(),
+            self.actor.to_string(),
+            self.seq.to_string(),
+            self.timestamp.to_string(),
+            self.message.as_deref().unwrap_or("").to_string(),
+            self.added.join(","),
+            self.updated.join(","),
+            self.moved.join(","),
+            self.deleted.join(","),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct DiffRecord {
+    pub id: Box<str>,
+    pub change: &'static str,
+    pub before: Option<ItemRecord>,
+    pub after: Option<ItemRecord>,
+}
+
+impl DiffRecord {
+    pub fn new(diff: &ItemDiff) -> Result<DiffRecord, Box<dyn std::error::Error>> {
+        let (change, before, after) = match diff {
+            ItemDiff::Added(i) => ("added", None, Some(ItemRecord::show(i)?)),
+            ItemDiff::Deleted(i) => ("deleted", Some(ItemRecord::show(i)?), None),
+            ItemDiff::Updated(b, a) => ("updated", Some(ItemRecord::show(b)?), Some(ItemRecord::show(a)?)),
+        };
+        Ok(DiffRecord {
+            id: Box::from(diff.id()),
+            change,
+            before,
+            after,
+        })
+    }
+
+    fn tsv_fields(&self) -> Vec<String> {
+        vec![self.id.to_string(), self.change.to_string()]
+    }
+}
+
+// write_listing writes the records of a listing as a json array, json lines or tsv rows.
+pub fn write_listing(format: Format, records: &[ItemRecord], out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    write_rows(format, records, TSV_LISTING_COLUMNS, item_tsv_fields, out)
+}
+
+pub fn write_log(format: Format, records: &[LogRecord], out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    write_rows(format, records, TSV_LOG_COLUMNS, LogRecord::tsv_fields, out)
+}
+
+// write_diff writes the changed items, tsv only carries the id and the kind of change since the item fields before and
+// after don't fit in a row.
+pub fn write_diff(format: Format, records: &[DiffRecord], out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    write_rows(format, records, TSV_DIFF_COLUMNS, DiffRecord::tsv_fields, out)
+}
+
+fn write_rows<T: Serialize>(
+    format: Format,
+    records: &[T],
+    columns: &[&str],
+    tsv_fields: fn(&T) -> Vec<String>,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Json => writeln!(out, "{}", serde_json::to_string(records)?)?,
+        Format::Ndjson => {
+            for record in records {
+                writeln!(out, "{}", serde_json::to_string(record)?)?;
+            }
+        }
+        Format::Tsv => {
+            write_tsv_row(out, columns.iter().map(|c| c.to_string()).collect())?;
+            for record in records {
+                write_tsv_row(out, tsv_fields(record))?;
+            }
+        }
+        Format::Text => return Err(Box::from("text format must be written by the command")),
+    }
+    Ok(())
+}
+
+// write_record writes a single record, for json this is an object rather than an array.
+pub fn write_record(format: Format, record: &ItemRecord, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    match format {
+        Format::Json | Format::Ndjson => writeln!(out, "{}", serde_json::to_string(record)?)?,
+        Format::Tsv => {
+            write_tsv_row(out, TSV_SHOW_COLUMNS.iter().map(|c| c.to_string()).collect())?;
+            write_tsv_row(out, item_tsv_fields(record))?;
+        }
+        Format::Text => return Err(Box::from("text format must be written by the command")),
+    }
+    Ok(())
+}
+
+fn write_tsv_row(out: &mut dyn Write, fields: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let escaped: Vec<String> = fields.iter().map(|f| escape_tsv(f)).collect();
+    writeln!(out, "{}", escaped.join("\t"))?;
+    Ok(())
+}
+
+// escape_tsv escapes the characters that would otherwise break the row structure.
+fn escape_tsv(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use au::item::Item;
+    use au::record::ItemRecord;
+
+    use crate::format::{escape_tsv, write_listing, write_record, Format};
+
+    fn render(f: impl FnOnce(&mut Vec<u8>)) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        f(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_escape_tsv() {
+        assert_eq!(escape_tsv("a\tb\nc\\d"), "a\\tb\\nc\\\\d");
+    }
+
+    #[test]
+    fn test_show_formats() {
+        let item = Item {
+            id: Rc::from("item-a"),
+            content: Rc::from("hello\tworld".as_bytes()),
+            class: Some(Rc::from("todo")),
+            ..Default::default()
+        };
+        let record = ItemRecord::show(&item).unwrap();
+        assert_eq!(
+            render(|o| write_record(Format::Json, &record, o).unwrap()),
+            "{\"id\":\"item-a\",\"parent\":null,\"rank\":0,\"class\":\"todo\",\"content_type\":\"text/plain\",\
+             \"at\":\"1970-01-01T00:00:00Z\",\"content\":\"hello\\tworld\",\"content_encoding\":\"utf-8\"}\n"
+        );
+        assert_eq!(
+            render(|o| write_record(Format::Tsv, &record, o).unwrap()),
+            "id\tparent\trank\tclass\tcontent_type\tat\tcontent\tcontent_encoding\n\
+             item-a\t\t0\ttodo\ttext/plain\t1970-01-01T00:00:00Z\thello\\tworld\tutf-8\n"
+        );
+
+        let binary = Item {
+            id: Rc::from("item-b"),
+            content_type: Rc::from("application/octet-stream"),
+            content: Rc::from(vec![0u8, 1, 2]),
+            ..Default::default()
+        };
+        let record = ItemRecord::show(&binary).unwrap();
+        assert_eq!(record.content.as_deref(), Some("AAEC"));
+        assert_eq!(record.content_encoding, Some("base64"));
+    }
+
+    #[test]
+    fn test_listing_formats() {
+        let item = Item {
+            id: Rc::from("item-a"),
+            ..Default::default()
+        };
+        let child = Item {
+            id: Rc::from("item-b"),
+            parent: Some(Rc::from("item-a")),
+            ..Default::default()
+        };
+        let records = vec![ItemRecord::listing(&item, 0).unwrap(), ItemRecord::listing(&child, 1).unwrap()];
+        let ndjson = render(|o| write_listing(Format::Ndjson, &records, o).unwrap());
+        assert_eq!(ndjson.lines().count(), 2);
+        assert!(ndjson.lines().nth(1).unwrap().contains("\"parent\":\"item-a\""));
+        assert!(ndjson.lines().nth(1).unwrap().ends_with("\"depth\":1}"));
+        let json = render(|o| write_listing(Format::Json, &records, o).unwrap());
+        assert!(json.starts_with("[{\"id\":\"item-a\""));
+        let tsv = render(|o| write_listing(Format::Tsv, &records, o).unwrap());
+        assert_eq!(
+            tsv.lines().nth(2).unwrap(),
+            "item-b\titem-a\t0\t\ttext/plain\t1970-01-01T00:00:00Z\t1"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::{ArgValueCandidates, CompleteEnv};
+
+use au::error::AuError;
+use au::storage::fs::FsBackend;
+
+use crate::commands::{CorruptStore, NewItem};
+use crate::format::Format;
+
+mod commands;
+mod complete;
+mod format;
+mod rpc;
+
+const DEFAULT_EDITOR: &str = "vi";
+// WATCH_INTERVAL is how often rpc looks for changes saved by other processes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+// Exit codes let scripts tell failures apart without parsing the message. 2 is used by clap for usage errors.
+const EXIT_FAILURE: u8 = 1;
+const EXIT_NOT_FOUND: u8 = 3;
+const EXIT_CYCLE: u8 = 4;
+const EXIT_CORRUPT: u8 = 5;
+const EXIT_INVALID: u8 = 6;
+const EXIT_IO: u8 = 7;
+const EXIT_AMBIGUOUS: u8 = 8;
+
+#[derive(Parser)]
+#[command(
+    name = "au",
+    about = "Work with an au project from the command line",
+    after_help = "Items may be named by id, by any unique prefix of the id, or by a path of summaries or slugs such as \
+Projects/Q3 Launch/Tasks or /projects/q3-launch/tasks.
+
+Exit codes: 1 failure, 2 usage, 3 no such item, 4 cycle, 5 corrupt store, 6 invalid operation, 7 io error, \
+8 ambiguous id prefix
+
+Shell completion, including item ids, is enabled by adding one of these to the shell startup:
+  bash:  source <(COMPLETE=bash au)
+  zsh:   source <(COMPLETE=zsh au)
+  fish:  COMPLETE=fish au | source"
+)]
+struct Cli {
+    /// The project directory
+    #[arg(short, long, global = true, env = "AU_PROJECT", default_value = ".au")]
+    project: PathBuf,
+    /// The output format for ls, show, log and diff
+    #[arg(short, long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new empty project
+    Init,
+    /// Add an item, the content is read from stdin if not given
+    Add {
+        content: Option<String>,
+        /// The id of the parent item
+        #[arg(long, add = ArgValueCandidates::new(complete::item_ids))]
+        parent: Option<String>,
+        #[arg(long)]
+        class: Option<String>,
+        /// The content type of the item
+        #[arg(long = "type", default_value = "text/plain")]
+        content_type: String,
+        /// The rank among siblings, defaults to the bottom of the list
+        #[arg(long, allow_hyphen_values = true)]
+        rank: Option<i64>,
+    },
+    /// List the children of an item, or the top level items
+    Ls {
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        parent: Option<String>,
+        /// List all descendants as an indented tree
+        #[arg(short, long)]
+        tree: bool,
+    },
+    /// Show an item and its content
+    Show {
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        id: String,
+        /// Write only the raw content
+        #[arg(long)]
+        content: bool,
+    },
+    /// Move an item under a new parent, or to the top level if none is given
+    Mv {
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        id: String,
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        parent: Option<String>,
+        #[arg(long, allow_hyphen_values = true)]
+        rank: Option<i64>,
+    },
+    /// Remove an item
+    Rm {
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        id: String,
+        /// Also remove all descendants
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    /// Edit the content of a text item with $EDITOR
+    Edit {
+        #[arg(add = ArgValueCandidates::new(complete::item_ids))]
+        id: String,
+    },
+    /// Apply a file of NDJSON operations as a single change, reading stdin if no file is given
+    Apply {
+        file: Option<PathBuf>,
+        /// The message recorded with the change
+        #[arg(short, long, default_value = "apply batch")]
+        message: String,
+        /// Check the operations without saving them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show the change history, newest first
+    Log {
+        /// Show at most this many changes
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Show the item differences between two points in history
+    Diff {
+        /// Comma separated change hashes, empty for the empty project
+        before: String,
+        /// Comma separated change hashes, defaults to the current state
+        after: Option<String>,
+    },
+    /// Serve JSON-RPC 2.0 on stdin and stdout for editor integrations
+    Rpc,
+}
+
+fn main() -> ExitCode {
+    CompleteEnv::with_factory(Cli::command).complete();
+    let cli = Cli::parse();
+    let mut stdout = io::stdout().lock();
+    match run(cli, &mut stdout) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let _ = stdout.flush();
+            eprintln!("au: {}", e);
+            ExitCode::from(exit_code(e.as_ref()))
+        }
+    }
+}
+
+// exit_code classifies the error, looking through the context added by NestedError to the error that caused it.
+fn exit_code(e: &(dyn std::error::Error + 'static)) -> u8 {
+    if e.is::<CorruptStore>() {
+        return EXIT_CORRUPT;
+    }
+    if let Some(au_error) = e.downcast_ref::<AuError>() {
+        return match au_error {
+            AuError::NoSuchKey(_) => EXIT_NOT_FOUND,
+            AuError::Cycle(_) => EXIT_CYCLE,
+            AuError::Ambiguous(_, _) => EXIT_AMBIGUOUS,
+            AuError::IncorrectType(_, _) => EXIT_CORRUPT,
+            AuError::NestedError(_, inner) => exit_code(inner.as_ref()),
+            AuError::InvalidField(_, _) | AuError::InvalidOperation(_, _) => EXIT_INVALID,
+        };
+    }
+    if e.is::<automerge::AutomergeError>() {
+        EXIT_CORRUPT
+    } else if e.is::<io::Error>() {
+        EXIT_IO
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+fn run(cli: Cli, out: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+    // every command apart from init works on a project that already exists
+    let open = || FsBackend::open(&cli.project);
+    match cli.command {
+        Command::Init => commands::init(&mut FsBackend::init(&cli.project)?, out),
+        Command::Add {
+            content,
+            parent,
+            class,
+            content_type,
+            rank,
+        } => {
+            let backend = &mut open()?;
+            let content = match content {
+                Some(c) => c.into_bytes(),
+                None => {
+                    let mut buf: Vec<u8> = Vec::new();
+                    io::stdin().read_to_end(&mut buf)?;
+                    buf
+                }
+            };
+            let new_item = NewItem {
+                content,
+                parent,
+                class,
+                content_type,
+                rank,
+            };
+            commands::add(backend, new_item, out)
+        }
+        Command::Ls { parent, tree } => commands::ls(&mut open()?, parent.as_deref(), tree, cli.format, out),
+        Command::Show { id, content } => commands::show(&mut open()?, id.as_str(), content, cli.format, out),
+        Command::Mv { id, parent, rank } => commands::mv(&mut open()?, id.as_str(), parent.as_deref(), rank, out),
+        Command::Rm { id, recursive } => commands::rm(&mut open()?, id.as_str(), recursive, out),
+        Command::Edit { id } => {
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| String::from(DEFAULT_EDITOR));
+            commands::edit(&mut open()?, id.as_str(), editor.as_str(), out)
+        }
+        Command::Apply { file, message, dry_run } => {
+            let backend = &mut open()?;
+            match file {
+                Some(f) if f.as_os_str() != "-" => {
+                    let input = io::BufReader::new(std::fs::File::open(f)?);
+                    commands::apply(backend, input, message.as_str(), dry_run, cli.format, out)
+                }
+                _ => commands::apply(backend, io::stdin().lock(), message.as_str(), dry_run, cli.format, out),
+            }
+        }
+        Command::Log { limit } => commands::log(&mut open()?, limit, cli.format, out),
+        Command::Diff { before, after } => commands::diff(&mut open()?, before.as_str(), after.as_deref(), cli.format, out),
+        Command::Rpc => {
+            let backend = &mut open()?;
+            let watcher = backend.watch(WATCH_INTERVAL);
+            rpc::rpc(backend, || watcher.changed(), io::BufReader::new(io::stdin()), out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use au::error::AuError;
+
+    use crate::commands::CorruptStore;
+    use crate::{exit_code, EXIT_AMBIGUOUS, EXIT_CORRUPT, EXIT_CYCLE, EXIT_FAILURE, EXIT_INVALID, EXIT_IO, EXIT_NOT_FOUND};
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&AuError::NoSuchKey(Box::from("x"))), EXIT_NOT_FOUND);
+        assert_eq!(exit_code(&AuError::Cycle(Box::from("x"))), EXIT_CYCLE);
+        assert_eq!(
+            exit_code(&AuError::NestedError(
+                Box::from("operation 1"),
+                Box::new(AuError::Cycle(Box::from("x")))
+            )),
+            EXIT_CYCLE
+        );
+        assert_eq!(exit_code(&AuError::Ambiguous(Box::from("x"), 2)), EXIT_AMBIGUOUS);
+        assert_eq!(
+            exit_code(&CorruptStore(Box::new(AuError::NoSuchKey(Box::from("items"))))),
+            EXIT_CORRUPT
+        );
+        assert_eq!(
+            exit_code(&AuError::InvalidOperation(Box::from("x"), Box::from("has children"))),
+            EXIT_INVALID
+        );
+        assert_eq!(exit_code(&std::io::Error::from(std::io::ErrorKind::NotFound)), EXIT_IO);
+        let other: Box<dyn std::error::Error> = Box::from("other");
+        assert_eq!(exit_code(other.as_ref()), EXIT_FAILURE);
+    }
+}
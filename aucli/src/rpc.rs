@@ -0,0 +1,534 @@
+/*
+
+The rpc command lets editors and other tools drive a project over stdin and stdout with JSON-RPC 2.0. Messages are
+framed like the language server protocol, each one is preceded by a Content-Length header and a blank line:
+
+    Content-Length: 78\r\n
+    \r\n
+    {"jsonrpc": "2.0", "id": 1, "method": "getItem", "params": {"id": "7KQ2M9XA"}}
+
+The methods mirror the Project api. Items are the same records as the json output of show, or of ls for listings:
+
+    listChildren  {parent?}                                                         -> [item]
+    getItem       {id}                                                              -> item
+    addItem       {parent?, class?, content_type?, content?, content_encoding?, rank?} -> item
+    updateItem    {id, class?, content_type?, content?, content_encoding?, parent?, rank?} -> item
+    moveItem      {id, parent?, rank?}                                              -> item
+    removeItem    {id}                                                              -> null
+    spliceText    {id, edits: [{index, delete, insert}]}                            -> null
+
+The fields of addItem, updateItem and moveItem are those of the matching au::batch operation, so content is utf-8 text
+unless content_encoding is "base64", fields that are left out are unchanged and a null class or parent clears it. An
+id or parent can be anything the command line takes for an item, such as a unique id prefix or a path.
+
+spliceText applies its edits to text content in order, each against the text as left by the edits before it. index and
+delete count characters (unicode scalar values) so an editor working in utf-16 positions must convert them. The edits
+become automerge text splices, which means typing that happens concurrently elsewhere merges by character instead of
+one side overwriting the other.
+
+Changes saved to the project by other processes are merged in as they happen and announced with a notification that
+lists the ids of the items they touched:
+
+    {"jsonrpc": "2.0", "method": "didChange", "params": {"added": [...], "updated": [...], "moved": [...], "deleted": [...]}}
+
+Malformed messages get the standard JSON-RPC error codes, any other error gets -32000 minus the exit code the command
+line would give for it, for example -32003 for a missing item. The session ends at the end of the input or on an exit
+notification, and with an error on a message that can't be framed, such as one over MAX_MESSAGE_BYTES.
+
+ */
+
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use automerge::AutoCommit;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use au::batch::{apply_edit, operation, resolve_parent, update_operations, Operation};
+use au::error::AuError;
+use au::history::{diff_projects, summarise};
+use au::id::IdGen;
+use au::item::{Item, ItemUpdate, Project};
+use au::path::resolve_item;
+use au::record::ItemRecord;
+use au::storage::{refresh_project, save_project, Backend};
+
+use crate::commands::load;
+use crate::exit_code;
+
+const JSONRPC_VERSION: &str = "2.0";
+const HEADER_CONTENT_LENGTH: &str = "content-length";
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+// POLL_INTERVAL is how often changes by other processes are looked for while no messages arrive.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// MAX_MESSAGE_BYTES bounds the Content-Length of a message, a larger one is a protocol error that ends the session.
+const MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IdParams {
+    id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ListParams {
+    parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpliceParams {
+    id: String,
+    edits: Vec<Edit>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Edit {
+    index: usize,
+    #[serde(default)]
+    delete: usize,
+    #[serde(default)]
+    insert: String,
+}
+
+// UnknownMethod is the error for a method that does not exist.
+#[derive(Debug)]
+struct UnknownMethod(String);
+
+impl std::fmt::Display for UnknownMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}': no such method", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMethod {}
+
+// rpc serves messages from the input until it ends or the client sends exit. changed is polled while the input is idle
+// and says whether the backend may have been changed by another process.
+pub fn rpc(
+    backend: &mut dyn Backend,
+    changed: impl Fn() -> bool,
+    input: impl BufRead + Send + 'static,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut session = Session::open(backend)?;
+    // messages are read on their own thread so that remote changes can be announced while the client is quiet
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        let mut input = input;
+        loop {
+            let message = read_message(&mut input).map_err(|e| e.to_string());
+            let more = matches!(message, Ok(Some(_)));
+            if tx.send(message).is_err() || !more {
+                break;
+            }
+        }
+    });
+    while !session.exit {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(Some(message))) => {
+                for reply in session.handle(message.as_slice()) {
+                    write_message(out, &reply)?;
+                }
+            }
+            Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(Err(e)) => return Err(Box::from(e)),
+            // a failed refresh is reported with the reply to the next message
+            Err(RecvTimeoutError::Timeout) if changed() => {
+                if let Ok(Some(notification)) = session.poll() {
+                    write_message(out, &notification)?;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+        }
+    }
+    Ok(())
+}
+
+// read_message reads the next framed message body, or returns None at the end of the input. Headers other than
+// Content-Length are ignored.
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    let mut length: Option<usize> = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(Box::new(AuError::InvalidField(
+                    Box::from("message"),
+                    Box::from("ends in the headers"),
+                ))),
+            };
+        }
+        let header = line.trim_end_matches(['\r', '\n']);
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case(HEADER_CONTENT_LENGTH) {
+                length = Some(value.trim().parse()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| AuError::InvalidField(Box::from("message"), Box::from("no Content-Length header")))?;
+    if length > MAX_MESSAGE_BYTES {
+        return Err(Box::new(AuError::InvalidField(
+            Box::from("message"),
+            Box::from(format!("Content-Length over {} bytes", MAX_MESSAGE_BYTES)),
+        )));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+// write_message writes a message with its header and flushes it.
+pub fn write_message(out: &mut dyn Write, message: &Value) -> Result<(), Box<dyn std::error::Error>> {
+    let body = serde_json::to_vec(message)?;
+    write!(out, "Content-Length: {}\r\n\r\n", body.len())?;
+    out.write_all(body.as_slice())?;
+    out.flush()?;
+    Ok(())
+}
+
+// Session holds the project a client is working on.
+pub struct Session<'a> {
+    backend: &'a mut dyn Backend,
+    doc: AutoCommit,
+    project: Project,
+    id_gen: IdGen,
+    exit: bool,
+}
+
+impl<'a> Session<'a> {
+    pub fn open(backend: &'a mut dyn Backend) -> Result<Session<'a>, Box<dyn std::error::Error>> {
+        let (doc, project) = load(backend)?;
+        Ok(Session {
+            backend,
+            doc,
+            project,
+            id_gen: IdGen::default(),
+            exit: false,
+        })
+    }
+
+    // handle answers a single message. Any notification about remote changes comes before the response, and there is
+    // no response to a notification.
+    pub fn handle(&mut self, message: &[u8]) -> Vec<Value> {
+        let request: Value = match serde_json::from_slice(message) {
+            Ok(r) => r,
+            Err(e) => return vec![error_response(Value::Null, PARSE_ERROR, e.to_string().as_str())],
+        };
+        let id = request.get("id").cloned();
+        let (Some(JSONRPC_VERSION), Some(method)) = (
+            request.get("jsonrpc").and_then(Value::as_str),
+            request.get("method").and_then(Value::as_str),
+        ) else {
+            return vec![error_response(
+                id.unwrap_or(Value::Null),
+                INVALID_REQUEST,
+                "not a JSON-RPC 2.0 request",
+            )];
+        };
+        let params = request.get("params").cloned().unwrap_or_else(|| Value::Object(Map::new()));
+
+        let mut out: Vec<Value> = Vec::new();
+        let result = match self.poll() {
+            Ok(notification) => {
+                out.extend(notification);
+                self.call(method, params)
+            }
+            Err(e) => Err(e),
+        };
+        if let Some(id) = id {
+            out.push(match result {
+                Ok(value) => json!({ "jsonrpc": JSONRPC_VERSION, "id": id, "result": value }),
+                Err(e) => error_response(id, error_code(e.as_ref()), e.to_string().as_str()),
+            });
+        }
+        out
+    }
+
+    // poll merges changes saved to the backend by other processes and returns the notification announcing them.
+    pub fn poll(&mut self) -> Result<Option<Value>, Box<dyn std::error::Error>> {
+        let before = self.project.clone();
+        if !refresh_project(self.backend, &mut self.doc, &mut self.project)? {
+            return Ok(None);
+        }
+        let summary = summarise(&diff_projects(&before, &self.project));
+        if summary.is_empty() {
+            return Ok(None);
+        }
+        let params = json!({
+            "added": summary.added,
+            "updated": summary.updated,
+            "moved": summary.moved,
+            "deleted": summary.deleted,
+        });
+        Ok(Some(json!({ "jsonrpc": JSONRPC_VERSION, "method": "didChange", "params": params })))
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+        match method {
+            "listChildren" => {
+                let params: ListParams = serde_json::from_value(params)?;
+                let parent = params.parent.as_deref().map(|p| self.item(p)).transpose()?;
+                let records = self
+                    .project
+                    .list_children(parent.as_ref().map(|p| p.id.as_ref()))
+                    .iter()
+                    .map(|i| ItemRecord::listing(i, 0))
+                    .collect::<Result<Vec<ItemRecord>, _>>()?;
+                Ok(serde_json::to_value(records)?)
+            }
+            "getItem" => {
+                let params: IdParams = serde_json::from_value(params)?;
+                self.record(params.id.as_str())
+            }
+            "addItem" => {
+                let id = self.id_gen.gen(rand::thread_rng());
+                let mut fields: Map<String, Value> = serde_json::from_value(params)?;
+                resolve_parent(&self.project, &mut fields)?;
+                let op = operation("add", None, fields)?;
+                self.apply(&[op], format!("add {}", id).as_str(), || id.clone())?;
+                self.record(id.as_str())
+            }
+            "updateItem" => {
+                let mut fields: Map<String, Value> = serde_json::from_value(params)?;
+                let id = self.take_item(&mut fields)?;
+                let ops = update_operations(id.as_str(), fields)?;
+                self.apply(&ops, format!("edit {}", id).as_str(), String::new)?;
+                self.record(id.as_str())
+            }
+            "moveItem" => {
+                let mut fields: Map<String, Value> = serde_json::from_value(params)?;
+                let id = self.take_item(&mut fields)?;
+                let message = match fields.get("parent") {
+                    Some(Value::String(p)) => format!("move {} under {}", id, p),
+                    Some(Value::Null) => format!("move {} to the top level", id),
+                    _ => format!("move {}", id),
+                };
+                let op = operation("move", Some(id.as_str()), fields)?;
+                self.apply(&[op], message.as_str(), String::new)?;
+                self.record(id.as_str())
+            }
+            "removeItem" => {
+                let params: IdParams = serde_json::from_value(params)?;
+                let id = self.item(params.id.as_str())?.id.to_string();
+                self.apply(
+                    &[Operation::Delete { id: id.clone() }],
+                    format!("remove {}", id).as_str(),
+                    String::new,
+                )?;
+                Ok(Value::Null)
+            }
+            "spliceText" => {
+                let params: SpliceParams = serde_json::from_value(params)?;
+                let id = self.item(params.id.as_str())?.id.clone();
+                let updates: Vec<ItemUpdate> = params
+                    .edits
+                    .into_iter()
+                    .map(|e| ItemUpdate::Splice(e.index, e.delete, Box::from(e.insert)))
+                    .collect();
+                self.project.transact(&mut self.doc, format!("edit {}", id).as_str(), |p, d| {
+                    p.with_updated_item(id.as_ref(), &updates, d).map(|_| ())
+                })?;
+                save_project(self.backend, &mut self.doc)?;
+                Ok(Value::Null)
+            }
+            "exit" => {
+                self.exit = true;
+                Ok(Value::Null)
+            }
+            _ => Err(Box::new(UnknownMethod(method.to_string()))),
+        }
+    }
+
+    // apply runs the operations of a call as a single change and saves it.
+    fn apply(&mut self, ops: &[Operation], message: &str, new_id: impl FnMut() -> String) -> Result<(), Box<dyn std::error::Error>> {
+        apply_edit(&mut self.project, &mut self.doc, ops, message, new_id)?;
+        save_project(self.backend, &mut self.doc)?;
+        Ok(())
+    }
+
+    // item finds the item a call names by its id, a unique prefix of it, or its path.
+    fn item(&self, reference: &str) -> Result<Rc<Item>, Box<dyn std::error::Error>> {
+        resolve_item(&self.project, None, reference)
+    }
+
+    // take_item removes the item reference from the params of a call and resolves the parent among them, returning the
+    // id of the item.
+    fn take_item(&self, fields: &mut Map<String, Value>) -> Result<String, Box<dyn std::error::Error>> {
+        let id = self.item(take_id(fields)?.as_str())?.id.to_string();
+        resolve_parent(&self.project, fields)?;
+        Ok(id)
+    }
+
+    fn record(&self, id: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        Ok(serde_json::to_value(ItemRecord::show(self.item(id)?.as_ref())?)?)
+    }
+}
+
+// take_id removes the id from the params of a call.
+fn take_id(fields: &mut Map<String, Value>) -> Result<String, Box<dyn std::error::Error>> {
+    match fields.remove("id") {
+        Some(Value::String(id)) => Ok(id),
+        _ => Err(Box::new(AuError::InvalidField(Box::from("id"), Box::from("expected a string")))),
+    }
+}
+
+fn error_code(e: &(dyn std::error::Error + 'static)) -> i64 {
+    if e.is::<UnknownMethod>() {
+        METHOD_NOT_FOUND
+    } else if e.is::<serde_json::Error>() {
+        INVALID_PARAMS
+    } else {
+        SERVER_ERROR - i64::from(exit_code(e))
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": JSONRPC_VERSION, "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use serde_json::{json, Value};
+
+    use au::item::Item;
+    use au::storage::fs::FsBackend;
+    use au::storage::memory::MemoryBackend;
+    use au::storage::{init_project, load_project, save_project};
+
+    use crate::rpc::{read_message, rpc, write_message, Session};
+
+    fn call(session: &mut Session, id: i64, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        let mut replies = session.handle(request.to_string().as_bytes());
+        assert_eq!(replies.len(), 1, "{:?}", replies);
+        replies.remove(0)
+    }
+
+    fn frame(message: Value) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        write_message(&mut out, &message).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_read_message() {
+        let mut input = Cursor::new(b"Content-Type: application/json\r\ncontent-length: 2\r\n\r\n{}Content-Length: 3\r\n\r\n[1]".to_vec());
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), b"{}");
+        assert_eq!(read_message(&mut input).unwrap().unwrap(), b"[1]");
+        assert!(read_message(&mut input).unwrap().is_none());
+        assert!(read_message(&mut Cursor::new(b"X: 1\r\n\r\n{}".to_vec())).is_err());
+        let huge = format!("Content-Length: {}\r\n\r\n{{}}", usize::MAX);
+        assert!(read_message(&mut Cursor::new(huge.into_bytes())).is_err());
+    }
+
+    #[test]
+    fn test_session() {
+        let mut backend = MemoryBackend::default();
+        init_project(&mut backend).unwrap();
+        let mut session = Session::open(&mut backend).unwrap();
+
+        let inbox = call(&mut session, 1, "addItem", json!({ "content": "Inbox" }));
+        let inbox = inbox["result"]["id"].as_str().unwrap().to_string();
+        let note = call(&mut session, 2, "addItem", json!({ "parent": "inbox", "content": "héllo world" }));
+        let note = note["result"]["id"].as_str().unwrap().to_string();
+        let children = call(&mut session, 3, "listChildren", json!({ "parent": "/Inbox" }));
+        assert_eq!(children["result"][0]["id"].as_str(), Some(note.as_str()));
+
+        let edits = json!([{ "index": 5, "insert": "," }, { "index": 7, "delete": 5, "insert": "wörld!" }]);
+        let reply = call(&mut session, 4, "spliceText", json!({ "id": note, "edits": edits }));
+        assert_eq!(reply["result"], Value::Null);
+        let reply = call(&mut session, 5, "getItem", json!({ "id": note }));
+        assert_eq!(reply["result"]["content"], "héllo, wörld!");
+
+        let reply = call(
+            &mut session,
+            6,
+            "updateItem",
+            json!({ "id": note, "class": "task", "parent": null }),
+        );
+        assert_eq!(
+            (reply["result"]["class"].as_str(), &reply["result"]["parent"]),
+            (Some("task"), &Value::Null)
+        );
+        // items can be named the way the command line names them
+        let reply = call(&mut session, 7, "moveItem", json!({ "id": "inbox", "parent": note }));
+        assert_eq!(reply["result"]["parent"].as_str(), Some(note.as_str()));
+
+        // errors
+        assert_eq!(
+            call(&mut session, 8, "moveItem", json!({ "id": note, "parent": inbox }))["error"]["code"],
+            -32004
+        );
+        assert_eq!(call(&mut session, 9, "getItem", json!({ "id": "nope" }))["error"]["code"], -32003);
+        assert_eq!(call(&mut session, 10, "getItem", json!({}))["error"]["code"], -32602);
+        assert_eq!(call(&mut session, 11, "nothing", json!({}))["error"]["code"], -32601);
+        let reply = call(&mut session, 12, "spliceText", json!({ "id": note, "edits": [{ "index": 99 }] }));
+        assert_eq!(reply["error"]["code"], -32006);
+        assert_eq!(session.handle(b"{")[0]["error"]["code"], -32700);
+        assert_eq!(session.handle(b"{\"id\": 1}")[0]["error"]["code"], -32600);
+
+        // notifications are not answered
+        let notification = json!({ "jsonrpc": "2.0", "method": "removeItem", "params": { "id": inbox } });
+        assert!(session.handle(notification.to_string().as_bytes()).is_empty());
+        assert_eq!(call(&mut session, 13, "getItem", json!({ "id": inbox }))["error"]["code"], -32003);
+    }
+
+    #[test]
+    fn test_remote_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut local = FsBackend::init(dir.path()).unwrap();
+        init_project(&mut local).unwrap();
+        let mut session = Session::open(&mut local).unwrap();
+
+        let mut remote = FsBackend::open(dir.path()).unwrap();
+        let (mut doc, mut project) = load_project(&remote).unwrap();
+        let item = Item {
+            id: "REMOTE".into(),
+            ..Default::default()
+        };
+        project
+            .transact(&mut doc, "add REMOTE", |p, d| p.with_item(&item, d).map(|_| ()))
+            .unwrap();
+        save_project(&mut remote, &mut doc).unwrap();
+
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "getItem", "params": { "id": "REMOTE" } });
+        let replies = session.handle(request.to_string().as_bytes());
+        assert_eq!(replies[0]["method"], "didChange");
+        assert_eq!(replies[0]["params"]["added"], json!(["REMOTE"]));
+        assert_eq!(replies[1]["result"]["id"], "REMOTE");
+    }
+
+    #[test]
+    fn test_rpc() {
+        let mut backend = MemoryBackend::default();
+        init_project(&mut backend).unwrap();
+        let mut input = frame(json!({ "jsonrpc": "2.0", "id": 1, "method": "addItem", "params": { "content": "a" } }));
+        input.extend(frame(json!({ "jsonrpc": "2.0", "method": "exit" })));
+        input.extend(frame(json!({ "jsonrpc": "2.0", "id": 2, "method": "listChildren" })));
+        let mut out: Vec<u8> = Vec::new();
+        rpc(&mut backend, || false, Cursor::new(input), &mut out).unwrap();
+
+        let mut output = Cursor::new(out);
+        let reply: Value = serde_json::from_slice(&read_message(&mut output).unwrap().unwrap()).unwrap();
+        assert_eq!((reply["id"].as_i64(), reply["result"]["content"].as_str()), (Some(1), Some("a")));
+        // nothing is answered after exit
+        assert!(read_message(&mut output).unwrap().is_none());
+    }
+}
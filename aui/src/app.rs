@@ -1,4 +1,3 @@
-
 /*
 
 main screen shows a tree centered on a particular item
@@ -7,45 +6,1393 @@ main screen shows a tree centered on a particular item
 - right moves into the node to the first child
 - enter opens the item and shows all text to scroll through if it is text
 - escape leaves
-- shift enter adds a new item below the current
-- alt enter adds a new item into the current at the bottom
+- shift enter (or a) adds a new item below the current
+- alt enter (or A) adds a new item into the current at the bottom
+- e edits the content of the current item, ctrl-s saves and escape cancels
+- shift up and down (or K and J) move the current item among its siblings
+- tab (or >) indents the current item under the sibling above, shift tab (or <) outdents it next to its parent
+- x cuts the current item along with everything under it and p pastes it below the current item
+- / searches the whole project and g goes to an item by id, id prefix or path
+- o shows the whole hierarchy as an outline where right and left expand and collapse items, space flips one, E and C
+  expand and collapse everything and enter goes back to the tree at the selected item
+- [ and ] narrow and widen the tree beside the preview of the selected item, which is hidden on narrow terminals
+
+the mouse selects by clicking, opens by double clicking, scrolls whichever pane it is over and drags the divider between
+the panes to resize them
+
+a project opened from a directory is saved after every change, and changes other processes save to it show up as they
+arrive with the same items still selected
+
+these are the default bindings, the config can pick the vim or emacs presets or bind each action to other keys
 
  */
 
+use std::collections::HashSet;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use au::item::{Item, Project};
+use au::path::resolve;
 use automerge::AutoCommit;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::layout::{Margin, Rect};
 use ratatui::widgets::ListState;
-use au::item::{Item, Project};
 
+use crate::config::{Action, Config, Context};
+use crate::editor::Editor;
+use crate::ops;
+use crate::outline::{outline_rows, OutlineRow};
+use crate::search::search;
+use crate::store::Store;
+
+// DOUBLE_CLICK is the longest time between two clicks on the same row for them to open it.
+const DOUBLE_CLICK: Duration = Duration::from_millis(500);
+// SCROLL_LINES is how far one step of the mouse wheel scrolls.
+const SCROLL_LINES: usize = 3;
+// the split is the percentage of the width given to the tree, the rest goes to the preview
+const SPLIT_DEFAULT: u16 = 50;
+const SPLIT_MIN: u16 = 20;
+const SPLIT_MAX: u16 = 80;
+const SPLIT_STEP: u16 = 5;
+
+#[derive(Default)]
 pub struct TreeContext {
+    // parents is the path from the top of the hierarchy down to the item whose children are shown, empty at the top.
     pub parents: Vec<Rc<Item>>,
+    // children are the items being shown, in list_children order.
     pub children: Vec<Rc<Item>>,
     pub list_state: ListState,
 }
 
+impl TreeContext {
+    // new shows the children of the last of the parents with the first of them selected.
+    pub fn new(project: &Project, parents: Vec<Rc<Item>>) -> TreeContext {
+        let mut ctx = TreeContext {
+            parents,
+            children: vec![],
+            list_state: Default::default(),
+        };
+        ctx.reload(project);
+        ctx
+    }
+
+    pub fn parent_id(&self) -> Option<&str> {
+        self.parents.last().map(|p| p.id.as_ref())
+    }
+
+    pub fn selected(&self) -> Option<&Rc<Item>> {
+        self.list_state.selected().and_then(|i| self.children.get(i))
+    }
+
+    // reload lists the children again after the project changes, keeping the same item selected where it still exists.
+    pub fn reload(&mut self, project: &Project) {
+        let selected_id = self.selected().map(|i| i.id.clone());
+        let index = self.list_state.selected().unwrap_or(0);
+        self.children = project.list_children(self.parent_id());
+        let index = selected_id
+            .and_then(|id| self.children.iter().position(|c| c.id == id))
+            .unwrap_or(index.min(self.children.len().saturating_sub(1)));
+        self.list_state.select(if self.children.is_empty() { None } else { Some(index) });
+    }
+
+    // select_id selects the child with the id, if it is one of the children.
+    pub fn select_id(&mut self, id: &str) {
+        if let Some(index) = self.children.iter().position(|c| c.id.as_ref() == id) {
+            self.list_state.select(Some(index));
+        }
+    }
+
+    // reveal shows the item among its siblings with the parents rebuilt from its ancestors.
+    pub fn reveal(&mut self, project: &Project, id: &str) {
+        let Some(item) = project.get_item(id) else { return };
+        let mut parents: Vec<Rc<Item>> = Vec::new();
+        let mut next = item.parent.as_deref().and_then(|p| project.get_item(p));
+        while let Some(parent) = next {
+            // a cycle from a concurrent move would otherwise never end
+            if parents.iter().any(|p| p.id == parent.id) {
+                break;
+            }
+            next = parent.parent.as_deref().and_then(|p| project.get_item(p));
+            parents.push(parent);
+        }
+        parents.reverse();
+        self.parents = parents;
+        self.list_state = Default::default();
+        self.reload(project);
+        self.select_id(id);
+    }
+
+    // refresh follows changes made elsewhere. The selected item is revealed wherever it has moved to, and when it has
+    // gone the nearest of the parents that is still there is shown instead.
+    pub fn refresh(&mut self, project: &Project) {
+        let offset = self.list_state.offset();
+        let parents: Vec<Rc<str>> = self.parents.iter().map(|p| p.id.clone()).collect();
+        match self.selected().map(|s| s.id.clone()).filter(|id| project.get_item(id).is_some()) {
+            Some(id) => self.reveal(project, &id),
+            None => match self.parents.iter().rev().find(|p| project.get_item(&p.id).is_some()) {
+                Some(parent) => {
+                    let id = parent.id.clone();
+                    self.reveal(project, &id);
+                    self.right(project);
+                }
+                None => {
+                    self.parents.clear();
+                    self.reload(project);
+                }
+            },
+        }
+        // stay scrolled where we were when still showing the same level
+        if self.parents.iter().map(|p| &p.id).eq(parents.iter()) {
+            *self.list_state.offset_mut() = offset;
+        }
+    }
+
+    pub fn up(&mut self) {
+        if let Some(i) = self.list_state.selected() {
+            self.list_state.select(Some(i.saturating_sub(1)));
+        }
+    }
+
+    pub fn down(&mut self) {
+        if let Some(i) = self.list_state.selected() {
+            self.list_state.select(Some((i + 1).min(self.children.len() - 1)));
+        }
+    }
+
+    // left moves out to the parent, selecting it among its own siblings.
+    pub fn left(&mut self, project: &Project) {
+        if let Some(parent) = self.parents.pop() {
+            self.children = project.list_children(self.parent_id());
+            self.list_state
+                .select(Some(self.children.iter().position(|c| c.id == parent.id).unwrap_or(0)));
+        }
+    }
+
+    // right moves into the selected item, selecting its first child. Items without children are left alone.
+    pub fn right(&mut self, project: &Project) {
+        if let Some(item) = self.selected().cloned() {
+            if project.has_children(Some(item.id.as_ref())) {
+                self.parents.push(item);
+                self.list_state = Default::default();
+                self.reload(project);
+            }
+        }
+    }
+}
+
+pub struct DetailContext {
+    // tree is where to return to when leaving the detail view.
+    pub tree: TreeContext,
+    // id is the item being viewed, it is looked up on each draw so that the view follows changes to the project.
+    pub id: Box<str>,
+    // scroll is the first content line shown, the ui clamps it to the content once the wrapped length is known.
+    pub scroll: usize,
+    // page is the number of content lines that were visible on the last draw.
+    pub page: usize,
+}
+
+impl DetailContext {
+    fn scroll_by(&mut self, lines: isize) {
+        self.scroll = self.scroll.saturating_add_signed(lines);
+    }
+
+    fn page_lines(&self) -> isize {
+        self.page.max(1) as isize
+    }
+}
+
+pub enum EditTarget {
+    // Below adds a new item directly below the sibling with the id.
+    Below(Box<str>),
+    // Into adds a new item at the bottom of the children of the parent.
+    Into(Option<Box<str>>),
+    // Content replaces the content of the item with the id.
+    Content(Box<str>),
+}
+
+pub struct EditContext {
+    // tree is where to return to when the edit is saved or cancelled.
+    pub tree: TreeContext,
+    pub target: EditTarget,
+    pub editor: Editor,
+}
+
+// Shift is a change to where the selected item sits in the hierarchy.
+enum Shift {
+    Up,
+    Down,
+    Indent,
+    Outdent,
+}
+
+pub struct SearchContext {
+    // tree is where to return to when the search is cancelled.
+    pub tree: TreeContext,
+    pub query: String,
+    // results are the matches for the query, best first.
+    pub results: Vec<Rc<Item>>,
+    pub list_state: ListState,
+}
+
+pub struct GotoContext {
+    // tree is where to return to when going nowhere.
+    pub tree: TreeContext,
+    // input is an id, id prefix or path to resolve from the level being shown.
+    pub input: String,
+}
+
+// Panes are where the ui last drew the tree and the preview, so that mouse events can be mapped back to them.
+#[derive(Default, Clone, Copy)]
+pub struct Panes {
+    pub list: Rect,
+    // preview is None when the terminal is too narrow to show it.
+    pub preview: Option<Rect>,
+}
+
+impl Panes {
+    // on_divider is true on the borders either side of the line between the tree and the preview.
+    fn on_divider(&self, column: u16, row: u16) -> bool {
+        self.preview
+            .is_some_and(|p| (column == p.x || column + 1 == p.x) && row >= p.y && row < p.bottom())
+    }
+
+    // split_at is the split that puts the divider at the column.
+    fn split_at(&self, column: u16) -> u16 {
+        let width = self.list.width + self.preview.map_or(0, |p| p.width);
+        let split = column.saturating_sub(self.list.x) as u32 * 100 / width.max(1) as u32;
+        (split as u16).clamp(SPLIT_MIN, SPLIT_MAX)
+    }
+
+    // list_index is the index of the list item on the row, counting from the first item scrolled into view.
+    fn list_index(&self, offset: usize, column: u16, row: u16) -> Option<usize> {
+        // the list is drawn inside a border
+        let inner = self.list.inner(&Margin::new(1, 1));
+        contains(inner, column, row).then(|| offset + (row - inner.y) as usize)
+    }
+}
+
+pub struct OutlineContext {
+    // tree is where to return to when the outline is closed without selecting anything.
+    pub tree: TreeContext,
+    // rows are the items in the outline, in the order they are shown.
+    pub rows: Vec<OutlineRow>,
+    pub list_state: ListState,
+}
+
+impl OutlineContext {
+    pub fn selected(&self) -> Option<&Rc<Item>> {
+        self.list_state.selected().and_then(|i| self.rows.get(i)).map(|r| &r.item)
+    }
+
+    // reload lists the rows again after the project or what is expanded changes, keeping the same item selected. When
+    // the selected item has been hidden its nearest ancestor that is still shown is selected instead.
+    pub fn reload(&mut self, project: &Project, expanded: &HashSet<Rc<str>>) {
+        let mut selected = self.selected().cloned();
+        let index = self.list_state.selected().unwrap_or(0);
+        self.rows = outline_rows(project, expanded);
+        let mut visited: HashSet<Rc<str>> = HashSet::new();
+        let mut found = None;
+        while let Some(item) = selected {
+            if !visited.insert(item.id.clone()) {
+                break;
+            }
+            found = self.rows.iter().position(|r| r.item.id == item.id);
+            if found.is_some() {
+                break;
+            }
+            selected = item.parent.as_deref().and_then(|p| project.get_item(p));
+        }
+        let index = found.unwrap_or(index.min(self.rows.len().saturating_sub(1)));
+        self.list_state.select(if self.rows.is_empty() { None } else { Some(index) });
+    }
+
+    pub fn select_id(&mut self, id: &str) {
+        if let Some(index) = self.rows.iter().position(|r| r.item.id.as_ref() == id) {
+            self.list_state.select(Some(index));
+        }
+    }
+
+    fn up(&mut self) {
+        if let Some(i) = self.list_state.selected() {
+            self.list_state.select(Some(i.saturating_sub(1)));
+        }
+    }
+
+    fn down(&mut self) {
+        if let Some(i) = self.list_state.selected() {
+            self.list_state.select(Some((i + 1).min(self.rows.len() - 1)));
+        }
+    }
+
+    // parent selects the row of the parent of the selected item.
+    fn parent(&mut self) {
+        if let Some(parent) = self.selected().and_then(|s| s.parent.clone()) {
+            self.select_id(&parent);
+        }
+    }
+}
+
 pub enum Mode {
     // Tree mode is the main view of the hierarchy
     Tree(TreeContext),
     // Detail mode is viewing the current item in detail
-    Detail(Box<str>),
+    Detail(DetailContext),
+    // Edit mode is writing the content of a new or existing item
+    Edit(EditContext),
+    // Search mode is finding an item anywhere in the project to jump to
+    Search(SearchContext),
+    // Goto mode is typing the id or path of an item to jump to
+    Goto(GotoContext),
+    // Outline mode is the whole hierarchy at once with items expanded or collapsed
+    Outline(OutlineContext),
+}
+
+impl Mode {
+    // tree is the tree being shown or the one to return to.
+    fn tree(&mut self) -> &mut TreeContext {
+        match self {
+            Mode::Tree(tree) => tree,
+            Mode::Detail(ctx) => &mut ctx.tree,
+            Mode::Edit(ctx) => &mut ctx.tree,
+            Mode::Search(ctx) => &mut ctx.tree,
+            Mode::Goto(ctx) => &mut ctx.tree,
+            Mode::Outline(ctx) => &mut ctx.tree,
+        }
+    }
 }
 
 pub struct App {
     pub doc: automerge::AutoCommit,
     pub project: Project,
     pub mode: Mode,
+    // message is shown in the footer until the next key press, it is how errors are reported.
+    pub message: Option<Box<str>>,
+    // cut is the item waiting to be pasted somewhere else.
+    pub cut: Option<Rc<str>>,
+    pub config: Config,
+    // expanded are the items expanded in the outline.
+    pub expanded: HashSet<Rc<str>>,
+    // store is where the project is saved, there is none for a project that only lives in memory.
+    pub store: Option<Store>,
+    // split is the percentage of the width given to the tree when the preview is shown beside it.
+    pub split: u16,
+    pub panes: Panes,
+    // preview_scroll is the first line of the preview shown. It is for the item preview_id, and the ui starts it over
+    // when the selection moves to another item.
+    pub preview_scroll: usize,
+    pub preview_id: Option<Rc<str>>,
+    // last_click is when and on which row the last left click was, to recognise a second click on the same row.
+    last_click: Option<(Instant, u16)>,
+    // dragging is set while the divider between the panes is held down.
+    dragging: bool,
 }
 
 impl App {
     pub fn new() -> App {
+        App::with_project(AutoCommit::new(), Project::default())
+    }
+
+    // with_project starts at the top of the hierarchy of an already decoded project.
+    pub fn with_project(doc: AutoCommit, project: Project) -> App {
+        let mode = Mode::Tree(TreeContext::new(&project, vec![]));
         App {
-            doc: AutoCommit::new(),
-            project: Project::default(),
-            mode: Mode::Tree(TreeContext{
-                parents: vec![],
-                children: vec![],
+            doc,
+            project,
+            mode,
+            message: None,
+            cut: None,
+            config: Config::default(),
+            expanded: HashSet::new(),
+            store: None,
+            split: SPLIT_DEFAULT,
+            panes: Panes::default(),
+            preview_scroll: 0,
+            preview_id: None,
+            last_click: None,
+            dragging: false,
+        }
+    }
+
+    // with_config uses the key bindings and theme of the config.
+    pub fn with_config(mut self, config: Config) -> App {
+        self.config = config;
+        self
+    }
+
+    // with_store saves every change to the store and refreshes from it.
+    pub fn with_store(mut self, store: Store) -> App {
+        self.store = Some(store);
+        self
+    }
+
+    // on_event handles a terminal event that happened at the time now and returns true when the app should quit. It is
+    // all the event loop needs, so that tests can replay events without a terminal. Any change the event made is saved
+    // straight away.
+    pub fn on_event(&mut self, event: Event, now: Instant) -> bool {
+        let quit = match event {
+            // some terminals also report releases and repeats, only act once per press
+            Event::Key(key) if key.kind == KeyEventKind::Press => self.on_key(key),
+            Event::Mouse(mouse) => {
+                self.on_mouse(mouse, now);
+                false
+            }
+            _ => false,
+        };
+        // a failure shows in the footer and is tried again after the next event
+        let _ = self.save();
+        quit
+    }
+
+    // save saves any unsaved changes to the store.
+    pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match &mut self.store {
+            Some(store) => store.save(&mut self.doc),
+            None => Ok(()),
+        }
+    }
+
+    // refresh merges changes saved to the store by other processes and updates what is shown to match, keeping the
+    // same items selected.
+    pub fn refresh(&mut self) {
+        let Some(store) = &mut self.store else { return };
+        match store.refresh(&mut self.doc, &mut self.project) {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(e) => {
+                self.message = Some(Box::from(format!("cannot refresh: {}", e)));
+                return;
+            }
+        }
+        self.mode.tree().refresh(&self.project);
+        if let Mode::Outline(ctx) = &mut self.mode {
+            ctx.reload(&self.project, &self.expanded);
+        }
+        if let Mode::Search(ctx) = &mut self.mode {
+            let selected = ctx.list_state.selected().and_then(|i| ctx.results.get(i)).map(|i| i.id.clone());
+            ctx.results = search(&self.project, &ctx.query);
+            let index = selected.and_then(|id| ctx.results.iter().position(|r| r.id == id)).unwrap_or(0);
+            ctx.list_state.select(if ctx.results.is_empty() { None } else { Some(index) });
+        }
+        if self.cut.as_ref().is_some_and(|id| self.project.get_item(id).is_none()) {
+            self.cut = None;
+        }
+    }
+
+    // on_key handles a key press and returns true when the app should quit.
+    pub fn on_key(&mut self, key: KeyEvent) -> bool {
+        self.message = None;
+        // the prompts take typed text so their keys are not bound through the keymap
+        let context = match self.mode {
+            Mode::Tree(_) => Some(Context::Tree),
+            Mode::Detail(_) => Some(Context::Detail),
+            Mode::Edit(_) => Some(Context::Edit),
+            Mode::Outline(_) => Some(Context::Outline),
+            Mode::Search(_) | Mode::Goto(_) => None,
+        };
+        let action = context.and_then(|c| self.config.keymap.action(c, &key));
+        match &mut self.mode {
+            Mode::Tree(ctx) => match action {
+                Some(Action::Quit) => return true,
+                Some(Action::Up) => ctx.up(),
+                Some(Action::Down) => ctx.down(),
+                Some(Action::Parent) => ctx.left(&self.project),
+                Some(Action::Child) => ctx.right(&self.project),
+                Some(Action::Open) => self.open_detail(),
+                Some(Action::AddBelow) => self.open_add(false),
+                Some(Action::AddChild) => self.open_add(true),
+                Some(Action::Edit) => self.open_edit(),
+                Some(Action::MoveUp) => self.shift(Shift::Up),
+                Some(Action::MoveDown) => self.shift(Shift::Down),
+                Some(Action::Indent) => self.shift(Shift::Indent),
+                Some(Action::Outdent) => self.shift(Shift::Outdent),
+                Some(Action::Cut) => self.cut(),
+                Some(Action::Paste) => self.paste(),
+                Some(Action::Search) => self.open_search(),
+                Some(Action::Goto) => self.open_goto(),
+                Some(Action::ShrinkTree) => self.split = self.split.saturating_sub(SPLIT_STEP).max(SPLIT_MIN),
+                Some(Action::GrowTree) => self.split = (self.split + SPLIT_STEP).min(SPLIT_MAX),
+                Some(Action::Outline) => self.open_outline(),
+                _ => (),
+            },
+            Mode::Outline(ctx) => match action {
+                Some(Action::Previous) => ctx.up(),
+                Some(Action::Next) => ctx.down(),
+                // collapsing an item that is already collapsed and expanding one that is already expanded move out to
+                // its parent and in to its first child, so the arrow keys alone can walk the whole outline
+                Some(Action::Collapse) => match ctx.list_state.selected().and_then(|i| ctx.rows.get(i)) {
+                    Some(row) if row.expanded => self.expand(Some(false)),
+                    _ => ctx.parent(),
+                },
+                Some(Action::Expand) => match ctx.list_state.selected().and_then(|i| ctx.rows.get(i)) {
+                    Some(row) if row.expanded => ctx.down(),
+                    _ => self.expand(Some(true)),
+                },
+                Some(Action::Toggle) => self.expand(None),
+                Some(Action::ExpandAll) => self.expand_all(true),
+                Some(Action::CollapseAll) => self.expand_all(false),
+                Some(Action::Close) => self.close_outline(),
+                _ => (),
+            },
+            Mode::Detail(ctx) => match action {
+                Some(Action::Back) => self.close_detail(),
+                Some(Action::ScrollUp) => ctx.scroll_by(-1),
+                Some(Action::ScrollDown) => ctx.scroll_by(1),
+                Some(Action::PageUp) => ctx.scroll_by(-ctx.page_lines()),
+                Some(Action::PageDown) => ctx.scroll_by(ctx.page_lines()),
+                Some(Action::Top) => ctx.scroll = 0,
+                Some(Action::Bottom) => ctx.scroll = usize::MAX,
+                _ => (),
+            },
+            Mode::Edit(ctx) => match action {
+                Some(Action::Cancel) => self.close_edit(None),
+                Some(Action::Save) => self.save_edit(),
+                _ => {
+                    ctx.editor.on_key(key);
+                }
+            },
+            Mode::Search(ctx) => match key.code {
+                KeyCode::Esc => self.close_prompt(None),
+                KeyCode::Enter => {
+                    let id = ctx.list_state.selected().and_then(|i| ctx.results.get(i)).map(|i| i.id.clone());
+                    self.close_prompt(id.as_deref());
+                }
+                KeyCode::Up => ctx.list_state.select(ctx.list_state.selected().map(|i| i.saturating_sub(1))),
+                KeyCode::Down => ctx
+                    .list_state
+                    .select(ctx.list_state.selected().map(|i| (i + 1).min(ctx.results.len() - 1))),
+                _ => {
+                    if edit_input(&mut ctx.query, &key) {
+                        ctx.results = search(&self.project, &ctx.query);
+                        ctx.list_state.select(if ctx.results.is_empty() { None } else { Some(0) });
+                    }
+                }
+            },
+            Mode::Goto(ctx) => match key.code {
+                KeyCode::Esc => self.close_prompt(None),
+                KeyCode::Enter => self.goto(),
+                _ => {
+                    edit_input(&mut ctx.input, &key);
+                }
+            },
+        }
+        false
+    }
+
+    // on_mouse handles a mouse event that happened at the time now, which tells a double click from two single ones.
+    pub fn on_mouse(&mut self, event: MouseEvent, now: Instant) {
+        match &mut self.mode {
+            Mode::Tree(_) | Mode::Outline(_) => self.on_list_mouse(event, now),
+            Mode::Detail(ctx) => match event.kind {
+                MouseEventKind::ScrollUp => ctx.scroll_by(-(SCROLL_LINES as isize)),
+                MouseEventKind::ScrollDown => ctx.scroll_by(SCROLL_LINES as isize),
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    // on_list_mouse handles the mouse over the tree or the outline, which are laid out the same way. A double click
+    // opens an item in the tree and expands or collapses it in the outline.
+    fn on_list_mouse(&mut self, event: MouseEvent, now: Instant) {
+        let (column, row) = (event.column, event.row);
+        let (list_state, len) = match &mut self.mode {
+            Mode::Tree(ctx) => (&mut ctx.list_state, ctx.children.len()),
+            Mode::Outline(ctx) => (&mut ctx.list_state, ctx.rows.len()),
+            _ => return,
+        };
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.message = None;
+                if self.panes.on_divider(column, row) {
+                    self.dragging = true;
+                    return;
+                }
+                let Some(index) = self.panes.list_index(list_state.offset(), column, row) else {
+                    return;
+                };
+                if index >= len {
+                    return;
+                }
+                let double = list_state.selected() == Some(index)
+                    && self
+                        .last_click
+                        .is_some_and(|(at, last_row)| last_row == row && now.duration_since(at) <= DOUBLE_CLICK);
+                list_state.select(Some(index));
+                if !double {
+                    self.last_click = Some((now, row));
+                    return;
+                }
+                self.last_click = None;
+                match self.mode {
+                    Mode::Outline(_) => self.expand(None),
+                    _ => self.open_detail(),
+                }
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging => self.split = self.panes.split_at(column),
+            MouseEventKind::Up(MouseButton::Left) => self.dragging = false,
+            MouseEventKind::ScrollUp | MouseEventKind::ScrollDown => {
+                let down = event.kind == MouseEventKind::ScrollDown;
+                if contains(self.panes.list, column, row) {
+                    list_state.select(
+                        list_state
+                            .selected()
+                            .map(|i| if down { (i + 1).min(len - 1) } else { i.saturating_sub(1) }),
+                    );
+                } else if self.panes.preview.is_some_and(|p| contains(p, column, row)) {
+                    // the ui clamps the scroll to the content
+                    self.preview_scroll = if down {
+                        self.preview_scroll.saturating_add(SCROLL_LINES)
+                    } else {
+                        self.preview_scroll.saturating_sub(SCROLL_LINES)
+                    };
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // open_outline shows the whole hierarchy as an outline, expanding the way down to the selected item so that it
+    // stays in view.
+    fn open_outline(&mut self) {
+        let Mode::Tree(tree) = &mut self.mode else { return };
+        self.expanded.extend(tree.parents.iter().map(|p| p.id.clone()));
+        let selected = tree.selected().map(|s| s.id.clone());
+        let mut ctx = OutlineContext {
+            tree: std::mem::take(tree),
+            rows: vec![],
+            list_state: Default::default(),
+        };
+        ctx.reload(&self.project, &self.expanded);
+        if let Some(id) = selected {
+            ctx.select_id(&id);
+        }
+        self.mode = Mode::Outline(ctx);
+    }
+
+    // close_outline goes back to the tree, showing the item that was selected in the outline among its siblings.
+    fn close_outline(&mut self) {
+        let Mode::Outline(ctx) = &mut self.mode else { return };
+        let mut tree = std::mem::take(&mut ctx.tree);
+        match ctx.selected() {
+            Some(item) => tree.reveal(&self.project, &item.id),
+            None => tree.reload(&self.project),
+        }
+        self.mode = Mode::Tree(tree);
+    }
+
+    // expand expands or collapses the selected item in the outline, or flips it when expand is None. Items without
+    // children have nothing to expand.
+    fn expand(&mut self, expand: Option<bool>) {
+        let Mode::Outline(ctx) = &mut self.mode else { return };
+        let Some(row) = ctx.list_state.selected().and_then(|i| ctx.rows.get(i)) else {
+            return;
+        };
+        if row.children == 0 {
+            return;
+        }
+        let id = row.item.id.clone();
+        if expand.unwrap_or(!row.expanded) {
+            self.expanded.insert(id);
+        } else {
+            self.expanded.remove(&id);
+        }
+        ctx.reload(&self.project, &self.expanded);
+    }
+
+    // expand_all expands every item that has children, or collapses everything.
+    fn expand_all(&mut self, expand: bool) {
+        let Mode::Outline(ctx) = &mut self.mode else { return };
+        self.expanded = if expand {
+            self.project
+                .list_items()
+                .into_iter()
+                .filter(|i| self.project.has_children(Some(&i.id)))
+                .map(|i| i.id.clone())
+                .collect()
+        } else {
+            HashSet::new()
+        };
+        ctx.reload(&self.project, &self.expanded);
+    }
+
+    fn open_search(&mut self) {
+        if let Mode::Tree(tree) = &mut self.mode {
+            self.mode = Mode::Search(SearchContext {
+                tree: std::mem::take(tree),
+                query: String::new(),
+                results: vec![],
                 list_state: Default::default(),
-            }),
+            });
+        }
+    }
+
+    fn open_goto(&mut self) {
+        if let Mode::Tree(tree) = &mut self.mode {
+            self.mode = Mode::Goto(GotoContext {
+                tree: std::mem::take(tree),
+                input: String::new(),
+            });
+        }
+    }
+
+    // goto jumps to the item the input resolves to, keeping the prompt open with the error when it does not resolve.
+    fn goto(&mut self) {
+        let Mode::Goto(ctx) = &mut self.mode else { return };
+        match resolve(&self.project, ctx.tree.parent_id(), ctx.input.trim()) {
+            Ok(Some(item)) => self.close_prompt(Some(&item.id)),
+            // the path led to the top level, which has no item to select
+            Ok(None) => {
+                ctx.tree = TreeContext::new(&self.project, vec![]);
+                self.close_prompt(None);
+            }
+            Err(e) => self.message = Some(Box::from(e.to_string())),
+        }
+    }
+
+    // close_prompt goes back to the tree, revealing the item wherever it is when there is one.
+    fn close_prompt(&mut self, id: Option<&str>) {
+        let tree = match &mut self.mode {
+            Mode::Search(ctx) => &mut ctx.tree,
+            Mode::Goto(ctx) => &mut ctx.tree,
+            _ => return,
+        };
+        let mut tree = std::mem::take(tree);
+        match id {
+            Some(id) => tree.reveal(&self.project, id),
+            None => tree.reload(&self.project),
+        }
+        self.mode = Mode::Tree(tree);
+    }
+
+    // shift moves the selected item and keeps it selected wherever it ends up.
+    fn shift(&mut self, shift: Shift) {
+        let Mode::Tree(tree) = &mut self.mode else { return };
+        let (Some(item), Some(index)) = (tree.selected().cloned(), tree.list_state.selected()) else {
+            return;
+        };
+        let parent: Option<Rc<str>> = tree.parents.last().map(|p| p.id.clone());
+        let (parent, index) = match shift {
+            Shift::Up if index > 0 => (parent, index - 1),
+            Shift::Down if index + 1 < tree.children.len() => (parent, index + 1),
+            Shift::Indent if index > 0 => {
+                let above = tree.children[index - 1].id.clone();
+                let bottom = self.project.list_children(Some(&above)).len();
+                (Some(above), bottom)
+            }
+            Shift::Outdent if !tree.parents.is_empty() => {
+                let old_parent = &tree.parents[tree.parents.len() - 1];
+                let grandparent = old_parent.parent.clone();
+                let siblings = self.project.list_children(grandparent.as_deref());
+                (
+                    grandparent,
+                    siblings
+                        .iter()
+                        .position(|s| s.id == old_parent.id)
+                        .map_or(siblings.len(), |i| i + 1),
+                )
+            }
+            _ => return,
+        };
+        match ops::move_to(&mut self.doc, &mut self.project, &item.id, parent.as_deref(), index) {
+            Ok(()) => tree.reveal(&self.project, &item.id),
+            Err(e) => self.message = Some(Box::from(format!("cannot move {}: {}", item.id, e))),
+        }
+    }
+
+    // cut marks the selected item to be moved by the next paste.
+    fn cut(&mut self) {
+        if let Mode::Tree(tree) = &self.mode {
+            if let Some(item) = tree.selected() {
+                self.cut = Some(item.id.clone());
+                self.message = Some(Box::from("cut, p pastes below the selected item"));
+            }
+        }
+    }
+
+    // paste moves the cut item and everything under it to just below the selected item, or into the level being shown
+    // when it is empty.
+    fn paste(&mut self) {
+        let Mode::Tree(tree) = &mut self.mode else { return };
+        let Some(id) = self.cut.clone() else {
+            self.message = Some(Box::from("nothing has been cut"));
+            return;
+        };
+        let index = tree.list_state.selected().map_or(0, |i| i + 1);
+        // the cut item may be in this list above the selected item, and the index does not count it
+        let index = match tree.children.iter().position(|c| c.id == id) {
+            Some(i) if i < index => index - 1,
+            _ => index,
+        };
+        let parent: Option<Rc<str>> = tree.parents.last().map(|p| p.id.clone());
+        match ops::move_to(&mut self.doc, &mut self.project, &id, parent.as_deref(), index) {
+            Ok(()) => {
+                self.cut = None;
+                tree.reveal(&self.project, &id);
+            }
+            Err(e) => self.message = Some(Box::from(format!("cannot paste {}: {}", id, e))),
+        }
+    }
+
+    // open_add starts writing a new item, either directly below the selected item or as the last of its children.
+    // With nothing selected the item goes into the level being shown.
+    fn open_add(&mut self, into: bool) {
+        if let Mode::Tree(tree) = &mut self.mode {
+            let target = match (tree.selected(), into) {
+                (Some(item), false) => EditTarget::Below(Box::from(item.id.as_ref())),
+                (Some(item), true) => EditTarget::Into(Some(Box::from(item.id.as_ref()))),
+                (None, _) => EditTarget::Into(tree.parent_id().map(Box::from)),
+            };
+            self.mode = Mode::Edit(EditContext {
+                tree: std::mem::take(tree),
+                target,
+                editor: Editor::default(),
+            });
+        }
+    }
+
+    // open_edit starts editing the content of the selected item, which has to be text.
+    fn open_edit(&mut self) {
+        if let Mode::Tree(tree) = &mut self.mode {
+            let Some(item) = tree.selected() else { return };
+            let text = match std::str::from_utf8(item.content.as_ref()) {
+                Ok(s) if item.content_type.starts_with("text/") => s,
+                _ => {
+                    self.message = Some(Box::from("only text content can be edited"));
+                    return;
+                }
+            };
+            let editor = Editor::new(text);
+            let target = EditTarget::Content(Box::from(item.id.as_ref()));
+            self.mode = Mode::Edit(EditContext {
+                tree: std::mem::take(tree),
+                target,
+                editor,
+            });
+        }
+    }
+
+    // save_edit saves the text to the project and goes back to the tree with the item selected. On failure the editor
+    // stays open with the error in the footer so that nothing typed is lost.
+    fn save_edit(&mut self) {
+        let Mode::Edit(ctx) = &mut self.mode else { return };
+        let text = ctx.editor.text();
+        let is_new = !matches!(ctx.target, EditTarget::Content(_));
+        if is_new && text.trim().is_empty() {
+            self.message = Some(Box::from("nothing to add"));
+            return;
         }
+        let result = match &ctx.target {
+            EditTarget::Below(id) => match self.project.get_item(id) {
+                Some(sibling) => ops::add_below(&mut self.doc, &mut self.project, &sibling, &text),
+                None => Err(Box::from(format!("item {} no longer exists", id))),
+            },
+            EditTarget::Into(parent) => ops::add_into(&mut self.doc, &mut self.project, parent.as_deref(), &text),
+            EditTarget::Content(id) => ops::set_content(&mut self.doc, &mut self.project, id, &text).map(|_| Rc::from(id.as_ref())),
+        };
+        match result {
+            Ok(id) => self.close_edit(Some(&id)),
+            Err(e) => self.message = Some(Box::from(e.to_string())),
+        }
+    }
+
+    // close_edit goes back to the tree, showing the saved item if there is one. An item added into the selected item
+    // is shown among its new siblings.
+    fn close_edit(&mut self, saved: Option<&str>) {
+        if let Mode::Edit(ctx) = &mut self.mode {
+            let mut tree = std::mem::take(&mut ctx.tree);
+            if let (EditTarget::Into(Some(parent)), Some(_)) = (&ctx.target, saved) {
+                if tree.parent_id() != Some(parent.as_ref()) {
+                    if let Some(parent) = self.project.get_item(parent) {
+                        tree.parents.push(parent);
+                        tree.list_state = Default::default();
+                    }
+                }
+            }
+            tree.reload(&self.project);
+            if let Some(id) = saved {
+                tree.select_id(id);
+            }
+            self.mode = Mode::Tree(tree);
+        }
+    }
+
+    // open_detail shows the selected item in detail, remembering the tree to return to.
+    fn open_detail(&mut self) {
+        if let Mode::Tree(tree) = &mut self.mode {
+            if let Some(id) = tree.selected().map(|i| Box::from(i.id.as_ref())) {
+                let tree = std::mem::take(tree);
+                self.mode = Mode::Detail(DetailContext {
+                    tree,
+                    id,
+                    scroll: 0,
+                    page: 0,
+                });
+            }
+        }
+    }
+
+    // close_detail goes back to the tree, which may have changed while the item was open.
+    fn close_detail(&mut self) {
+        if let Mode::Detail(ctx) = &mut self.mode {
+            let mut tree = std::mem::take(&mut ctx.tree);
+            tree.reload(&self.project);
+            self.mode = Mode::Tree(tree);
+        }
+    }
+}
+
+fn contains(area: Rect, column: u16, row: u16) -> bool {
+    column >= area.x && column < area.right() && row >= area.y && row < area.bottom()
+}
+
+// edit_input applies a key to a single line of typed text and returns true if the text changed.
+fn edit_input(input: &mut String, key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char(c) if !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+            input.push(c);
+            true
+        }
+        KeyCode::Backspace => input.pop().is_some(),
+        _ => false,
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use au::item::init_project;
+    use au::storage::fs::FsBackend;
+    use au::storage::{load_project, refresh_project, save_project};
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use time::UtcOffset;
+
+    use super::*;
+
+    fn new_app() -> App {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        for (id, parent, rank) in [
+            ("a", None, 2),
+            ("b", None, 1),
+            ("a1", Some("a"), 2),
+            ("a2", Some("a"), 1),
+            ("a2x", Some("a2"), 0),
+        ] {
+            let item = Item {
+                id: Rc::from(id),
+                content: Rc::from(format!("item {}", id).as_bytes()),
+                rank,
+                parent: parent.map(Rc::from),
+                ..Default::default()
+            };
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        App::with_project(doc, project)
+    }
+
+    fn press(app: &mut App, code: KeyCode) -> bool {
+        app.on_key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    fn selected(app: &App) -> (Vec<&str>, &str) {
+        match &app.mode {
+            Mode::Tree(ctx) => (
+                ctx.parents.iter().map(|p| p.id.as_ref()).collect(),
+                ctx.selected().unwrap().id.as_ref(),
+            ),
+            _ => panic!("not in tree mode"),
+        }
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut app = new_app();
+        assert_eq!(selected(&app), (vec![], "a"));
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), (vec![], "a"));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(selected(&app), (vec![], "b"));
+
+        // b has no children so right stays put
+        press(&mut app, KeyCode::Right);
+        assert_eq!(selected(&app), (vec![], "b"));
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Right);
+        assert_eq!(selected(&app), (vec!["a"], "a1"));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Right);
+        assert_eq!(selected(&app), (vec!["a", "a2"], "a2x"));
+
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), (vec!["a"], "a2"));
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), (vec![], "a"));
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), (vec![], "a"));
+    }
+
+    #[test]
+    fn test_quit() {
+        let mut app = new_app();
+        assert!(!press(&mut app, KeyCode::Char('x')));
+        assert!(press(&mut app, KeyCode::Char('q')));
+        assert!(press(&mut app, KeyCode::Esc));
+    }
+
+    #[test]
+    fn test_empty_project() {
+        let mut app = App::new();
+        for code in [KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right] {
+            assert!(!press(&mut app, code));
+        }
+        match &app.mode {
+            Mode::Tree(ctx) => assert!(ctx.selected().is_none()),
+            _ => panic!("not in tree mode"),
+        }
+    }
+
+    #[test]
+    fn test_detail() {
+        let mut app = new_app();
+        press(&mut app, KeyCode::Down);
+        assert!(!press(&mut app, KeyCode::Enter));
+        match &mut app.mode {
+            Mode::Detail(ctx) => {
+                assert_eq!(ctx.id.as_ref(), "b");
+                ctx.page = 10;
+            }
+            _ => panic!("not in detail mode"),
+        }
+        press(&mut app, KeyCode::PageDown);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        match &app.mode {
+            Mode::Detail(ctx) => assert_eq!(ctx.scroll, 9),
+            _ => panic!("not in detail mode"),
+        }
+
+        // escape goes back to the tree with the same item selected rather than quitting
+        assert!(!press(&mut app, KeyCode::Esc));
+        assert_eq!(selected(&app), (vec![], "b"));
+    }
+
+    fn type_text(app: &mut App, text: &str) {
+        for c in text.chars() {
+            press(app, if c == '\n' { KeyCode::Enter } else { KeyCode::Char(c) });
+        }
+    }
+
+    fn save(app: &mut App) {
+        app.on_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL));
+    }
+
+    fn summaries(app: &App) -> Vec<String> {
+        match &app.mode {
+            Mode::Tree(ctx) => ctx.children.iter().map(|c| c.summary(usize::MAX).to_string()).collect(),
+            _ => panic!("not in tree mode"),
+        }
+    }
+
+    #[test]
+    fn test_add_and_edit() {
+        let mut app = new_app();
+
+        // shift enter adds directly below the selected item and selects the new one
+        app.on_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::SHIFT));
+        type_text(&mut app, "new\nsecond line");
+        save(&mut app);
+        assert_eq!(summaries(&app), vec!["item a", "new", "item b"]);
+        assert_eq!(
+            app.project.get_item(selected(&app).1).unwrap().content.as_ref(),
+            b"new\nsecond line"
+        );
+
+        // alt enter adds as the last child and moves into the parent to show it
+        press(&mut app, KeyCode::Up);
+        app.on_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT));
+        type_text(&mut app, "child");
+        save(&mut app);
+        assert_eq!(summaries(&app), vec!["item a1", "item a2", "child"]);
+        assert_eq!(selected(&app).0, vec!["a"]);
+
+        press(&mut app, KeyCode::Char('e'));
+        press(&mut app, KeyCode::Backspace);
+        type_text(&mut app, "dren");
+        save(&mut app);
+        assert_eq!(summaries(&app), vec!["item a1", "item a2", "children"]);
+
+        // escape throws the edit away and an empty new item is not added
+        press(&mut app, KeyCode::Char('e'));
+        type_text(&mut app, " discarded");
+        press(&mut app, KeyCode::Esc);
+        press(&mut app, KeyCode::Char('a'));
+        save(&mut app);
+        assert!(app.message.is_some());
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(summaries(&app), vec!["item a1", "item a2", "children"]);
+    }
+
+    #[test]
+    fn test_restructure() {
+        let mut app = new_app();
+        let shift = |code| KeyEvent::new(code, KeyModifiers::SHIFT);
+
+        app.on_key(shift(KeyCode::Down));
+        assert_eq!(summaries(&app), vec!["item b", "item a"]);
+        assert_eq!(selected(&app), (vec![], "a"));
+        app.on_key(shift(KeyCode::Down));
+        assert_eq!(summaries(&app), vec!["item b", "item a"]);
+        press(&mut app, KeyCode::Char('K'));
+        assert_eq!(summaries(&app), vec!["item a", "item b"]);
+
+        // b goes to the bottom of a's children and the view follows it
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Tab);
+        assert_eq!(selected(&app), (vec!["a"], "b"));
+        assert_eq!(summaries(&app), vec!["item a1", "item a2", "item b"]);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::BackTab);
+        assert_eq!(selected(&app), (vec![], "a2"));
+        assert_eq!(summaries(&app), vec!["item a", "item a2"]);
+
+        // a cannot be pasted under itself, the error is reported and nothing changes
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Char('x'));
+        press(&mut app, KeyCode::Right);
+        press(&mut app, KeyCode::Char('p'));
+        assert!(app.message.as_deref().unwrap().contains("cycle"));
+        assert_eq!(selected(&app), (vec!["a"], "a1"));
+
+        press(&mut app, KeyCode::Left);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Right);
+        press(&mut app, KeyCode::Char('p'));
+        assert_eq!(selected(&app), (vec!["a2"], "a"));
+        assert_eq!(summaries(&app), vec!["item a2x", "item a"]);
+        assert!(app.cut.is_none());
+    }
+
+    #[test]
+    fn test_config_keymap() {
+        let config = Config::parse("preset = \"vim\"\n[keys]\nquit = [\"ctrl-c\"]").unwrap();
+        let mut app = new_app().with_config(config);
+        press(&mut app, KeyCode::Char('j'));
+        press(&mut app, KeyCode::Char('k'));
+        press(&mut app, KeyCode::Char('l'));
+        assert_eq!(selected(&app), (vec!["a"], "a1"));
+        assert!(!press(&mut app, KeyCode::Char('q')));
+        assert!(app.on_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut app = new_app();
+        press(&mut app, KeyCode::Char('/'));
+        type_text(&mut app, "itm a2");
+        match &app.mode {
+            Mode::Search(ctx) => {
+                let ids: Vec<&str> = ctx.results.iter().map(|r| r.id.as_ref()).collect();
+                assert_eq!(ids[..2], ["a2", "a2x"]);
+            }
+            _ => panic!("not in search mode"),
+        }
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), (vec!["a", "a2"], "a2x"));
+
+        // escape leaves the tree as it was
+        press(&mut app, KeyCode::Char('/'));
+        type_text(&mut app, "b");
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(selected(&app), (vec!["a", "a2"], "a2x"));
+    }
+
+    #[test]
+    fn test_goto() {
+        let mut app = new_app();
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "a2x");
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), (vec!["a", "a2"], "a2x"));
+
+        // paths resolve from the level being shown
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "../item a1");
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), (vec!["a"], "a1"));
+
+        // a bad reference keeps the prompt open with the error
+        press(&mut app, KeyCode::Char('g'));
+        type_text(&mut app, "zzz");
+        press(&mut app, KeyCode::Enter);
+        assert!(app.message.is_some());
+        assert!(matches!(app.mode, Mode::Goto(_)));
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Backspace);
+        press(&mut app, KeyCode::Backspace);
+        type_text(&mut app, "/");
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), (vec![], "a"));
+    }
+
+    fn mouse(app: &mut App, kind: MouseEventKind, column: u16, row: u16, now: Instant) {
+        let event = MouseEvent {
+            kind,
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        };
+        app.on_mouse(event, now);
+    }
+
+    #[test]
+    fn test_mouse() {
+        let mut app = new_app();
+        // as the ui lays out a 100 column terminal, with the list rows from 4 down
+        app.panes = Panes {
+            list: Rect::new(0, 3, 50, 20),
+            preview: Some(Rect::new(50, 3, 50, 20)),
+        };
+        let start = Instant::now();
+        let click = MouseEventKind::Down(MouseButton::Left);
+        mouse(&mut app, click, 10, 5, start);
+        assert_eq!(selected(&app), (vec![], "b"));
+        // the border and rows past the end of the list select nothing
+        mouse(&mut app, click, 10, 3, start);
+        mouse(&mut app, click, 10, 8, start);
+        assert_eq!(selected(&app), (vec![], "b"));
+
+        // a second click on the same row opens the item, but not when it comes too late
+        mouse(&mut app, click, 10, 4, start);
+        mouse(&mut app, click, 10, 4, start + DOUBLE_CLICK * 2);
+        assert_eq!(selected(&app), (vec![], "a"));
+        let later = start + DOUBLE_CLICK * 2 + Duration::from_millis(100);
+        mouse(&mut app, click, 10, 4, later);
+        assert!(matches!(&app.mode, Mode::Detail(ctx) if ctx.id.as_ref() == "a"));
+        mouse(&mut app, MouseEventKind::ScrollDown, 10, 10, later);
+        assert!(matches!(&app.mode, Mode::Detail(ctx) if ctx.scroll == SCROLL_LINES));
+        press(&mut app, KeyCode::Esc);
+
+        // the wheel moves the selection over the list and scrolls the preview over the preview
+        mouse(&mut app, MouseEventKind::ScrollDown, 10, 10, later);
+        assert_eq!(selected(&app), (vec![], "b"));
+        mouse(&mut app, MouseEventKind::ScrollDown, 60, 10, later);
+        assert_eq!(selected(&app), (vec![], "b"));
+        assert_eq!(app.preview_scroll, SCROLL_LINES);
+
+        // dragging the divider resizes the panes within limits
+        mouse(&mut app, click, 50, 10, later);
+        mouse(&mut app, MouseEventKind::Drag(MouseButton::Left), 30, 10, later);
+        assert_eq!(app.split, 30);
+        mouse(&mut app, MouseEventKind::Drag(MouseButton::Left), 99, 10, later);
+        assert_eq!(app.split, SPLIT_MAX);
+        mouse(&mut app, MouseEventKind::Up(MouseButton::Left), 99, 10, later);
+        mouse(&mut app, MouseEventKind::Drag(MouseButton::Left), 30, 10, later);
+        assert_eq!(app.split, SPLIT_MAX);
+        press(&mut app, KeyCode::Char('['));
+        assert_eq!(app.split, SPLIT_MAX - SPLIT_STEP);
+    }
+
+    #[test]
+    fn test_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut other = FsBackend::init(dir.path()).unwrap();
+        let (mut other_doc, mut other_project) = au::storage::init_project(&mut other).unwrap();
+        let a = ops::add_into(&mut other_doc, &mut other_project, None, "a").unwrap();
+        let b = ops::add_into(&mut other_doc, &mut other_project, None, "b").unwrap();
+        save_project(&mut other, &mut other_doc).unwrap();
+
+        let backend = FsBackend::open(dir.path()).unwrap();
+        let (store, doc, project) = Store::open(Box::new(backend), "p", UtcOffset::UTC).unwrap();
+        let mut app = App::with_project(doc, project).with_store(store);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(selected(&app), (vec![], b.as_ref()));
+
+        // b moving elsewhere takes the selection with it, and changes made here are saved as they happen
+        ops::move_to(&mut other_doc, &mut other_project, &b, Some(&a), 0).unwrap();
+        save_project(&mut other, &mut other_doc).unwrap();
+        app.refresh();
+        assert_eq!(selected(&app), (vec![a.as_ref()], b.as_ref()));
+        for key in [KeyCode::Char('a'), KeyCode::Char('c')] {
+            app.on_event(Event::Key(KeyEvent::new(key, KeyModifiers::NONE)), Instant::now());
+        }
+        app.on_event(Event::Key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL)), Instant::now());
+        let (_, saved) = load_project(&other).unwrap();
+        assert_eq!(saved.list_children(Some(&a)).len(), 2);
+
+        // the parents are rebuilt when the level being shown moves
+        refresh_project(&other, &mut other_doc, &mut other_project).unwrap();
+        let d = ops::add_into(&mut other_doc, &mut other_project, None, "d").unwrap();
+        ops::move_to(&mut other_doc, &mut other_project, &a, Some(&d), 0).unwrap();
+        save_project(&mut other, &mut other_doc).unwrap();
+        press(&mut app, KeyCode::Up);
+        app.refresh();
+        assert_eq!(selected(&app), (vec![d.as_ref(), a.as_ref()], b.as_ref()));
+    }
+
+    fn outline_rows(app: &App) -> (Vec<String>, &str) {
+        match &app.mode {
+            Mode::Outline(ctx) => (
+                ctx.rows
+                    .iter()
+                    .map(|r| format!("{}{}{}", "  ".repeat(r.depth), r.item.id, if r.expanded { "-" } else { "" }))
+                    .collect(),
+                ctx.selected().unwrap().id.as_ref(),
+            ),
+            _ => panic!("not in outline mode"),
+        }
+    }
+
+    #[test]
+    fn test_outline() {
+        let mut app = new_app();
+        press(&mut app, KeyCode::Right);
+        press(&mut app, KeyCode::Down);
+        // the outline opens with the way down to the selected item expanded
+        press(&mut app, KeyCode::Char('o'));
+        assert_eq!(
+            outline_rows(&app),
+            (vec!["a-".into(), "  a1".into(), "  a2".into(), "b".into()], "a2")
+        );
+
+        // right expands and then moves in, left collapses and then moves out
+        press(&mut app, KeyCode::Right);
+        assert_eq!(outline_rows(&app).0, vec!["a-", "  a1", "  a2-", "    a2x", "b"]);
+        press(&mut app, KeyCode::Right);
+        assert_eq!(outline_rows(&app).1, "a2x");
+        press(&mut app, KeyCode::Left);
+        press(&mut app, KeyCode::Left);
+        press(&mut app, KeyCode::Left);
+        assert_eq!(
+            outline_rows(&app),
+            (vec!["a-".into(), "  a1".into(), "  a2".into(), "b".into()], "a")
+        );
+
+        // a2 stays expanded while a is collapsed
+        for code in [
+            KeyCode::Down,
+            KeyCode::Down,
+            KeyCode::Right,
+            KeyCode::Up,
+            KeyCode::Up,
+            KeyCode::Char(' '),
+        ] {
+            press(&mut app, code);
+        }
+        assert_eq!(outline_rows(&app), (vec!["a".into(), "b".into()], "a"));
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(outline_rows(&app).0, vec!["a-", "  a1", "  a2-", "    a2x", "b"]);
+        press(&mut app, KeyCode::Char('C'));
+        assert_eq!(outline_rows(&app), (vec!["a".into(), "b".into()], "a"));
+        assert!(app.expanded.is_empty());
+
+        // enter goes back to the tree at the selected item
+        press(&mut app, KeyCode::Char('E'));
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), (vec!["a", "a2"], "a2x"));
+    }
+}
@@ -0,0 +1,706 @@
+/*
+
+config holds the key bindings and theme of the tui, read from a toml file. A preset gives the starting bindings and the
+keys table replaces the bindings of individual actions, so a config only has to mention what it changes:
+
+preset = "vim"
+
+[keys]
+quit = ["ctrl-c"]
+paste = ["p", "ctrl-v"]
+
+[theme]
+border = { fg = "blue" }
+highlight = { fg = "black", bg = "yellow", modifiers = ["bold"] }
+highlight_symbol = "> "
+
+[classes.todo]
+icon = "[ ]"
+fg = "yellow"
+
+Key chords are a key name with optional ctrl-, alt- and shift- prefixes. Letters are matched by case so "K" is shift k.
+A chord in the keys table can only be given to one action of each mode.
+Colors are anything ratatui understands: names like "lightblue", indexes like "208", or "#rrggbb".
+
+ */
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+use au::error::AuError;
+
+// CONFIG_ENV names a config file to use instead of the one in the user's config directory.
+pub const CONFIG_ENV: &str = "AUI_CONFIG";
+const CONFIG_FILE: &str = "au/aui.toml";
+const DEFAULT_HIGHLIGHT_SYMBOL: &str = ">>";
+
+// Context is the mode an action applies in, the same key can mean different things in each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    Tree,
+    Detail,
+    Edit,
+    Outline,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    // tree actions
+    Up,
+    Down,
+    Parent,
+    Child,
+    Open,
+    AddBelow,
+    AddChild,
+    Edit,
+    MoveUp,
+    MoveDown,
+    Indent,
+    Outdent,
+    Cut,
+    Paste,
+    Search,
+    Goto,
+    ShrinkTree,
+    GrowTree,
+    Outline,
+    Quit,
+    // detail actions
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Top,
+    Bottom,
+    Back,
+    // edit actions
+    Save,
+    Cancel,
+    // outline actions
+    Previous,
+    Next,
+    Collapse,
+    Expand,
+    Toggle,
+    ExpandAll,
+    CollapseAll,
+    Close,
+}
+
+impl Action {
+    pub fn context(&self) -> Context {
+        match self {
+            Action::ScrollUp | Action::ScrollDown | Action::PageUp | Action::PageDown | Action::Top | Action::Bottom | Action::Back => {
+                Context::Detail
+            }
+            Action::Save | Action::Cancel => Context::Edit,
+            Action::Previous
+            | Action::Next
+            | Action::Collapse
+            | Action::Expand
+            | Action::Toggle
+            | Action::ExpandAll
+            | Action::CollapseAll
+            | Action::Close => Context::Outline,
+            _ => Context::Tree,
+        }
+    }
+}
+
+// KeyChord is a key with the modifiers that have to be held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    // matches ignores shift on characters and back tab, where it is already part of the key.
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        let mut modifiers = key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        if matches!(key.code, KeyCode::Char(_) | KeyCode::BackTab) {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        self.code == key.code && self.modifiers == modifiers
+    }
+}
+
+impl std::fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (modifier, prefix) in [
+            (KeyModifiers::CONTROL, "ctrl-"),
+            (KeyModifiers::ALT, "alt-"),
+            (KeyModifiers::SHIFT, "shift-"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                f.write_str(prefix)?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => f.write_str("space"),
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::PageUp => f.write_str("pgup"),
+            KeyCode::PageDown => f.write_str("pgdn"),
+            code => f.write_str(format!("{:?}", code).to_ascii_lowercase().as_str()),
+        }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AuError::InvalidField(Box::from("key"), Box::from(format!("'{}' is not a key chord", s)));
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        // a lone "-" is the minus key rather than an empty modifier
+        while let Some((prefix, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match prefix.to_ascii_lowercase().as_str() {
+                "ctrl" | "c" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "m" => KeyModifiers::ALT,
+                "shift" | "s" => KeyModifiers::SHIFT,
+                _ => return Err(Box::new(invalid())),
+            };
+            rest = key;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" | "ins" => KeyCode::Insert,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pgup" | "pageup" => KeyCode::PageUp,
+                "pgdn" | "pagedown" => KeyCode::PageDown,
+                "space" => KeyCode::Char(' '),
+                f if f.starts_with('f') => KeyCode::F(f[1..].parse().map_err(|_| invalid())?),
+                _ => return Err(Box::new(invalid())),
+            },
+        };
+        // shift is written into the character itself, and back tab is always shifted
+        if code == KeyCode::BackTab {
+            modifiers.remove(KeyModifiers::SHIFT);
+        }
+        if let KeyCode::Char(c) = code {
+            if modifiers.contains(KeyModifiers::SHIFT) {
+                modifiers.remove(KeyModifiers::SHIFT);
+                return Ok(KeyChord {
+                    code: KeyCode::Char(c.to_ascii_uppercase()),
+                    modifiers,
+                });
+            }
+        }
+        Ok(KeyChord { code, modifiers })
+    }
+}
+
+pub struct Keymap {
+    bindings: Vec<(KeyChord, Action)>,
+}
+
+impl Keymap {
+    // preset returns the bindings of a named preset: default, vim or emacs.
+    pub fn preset(name: &str) -> Result<Keymap, Box<dyn std::error::Error>> {
+        let table: &[(Action, &[&str])] = match name {
+            "default" => DEFAULT_KEYS,
+            "vim" => VIM_KEYS,
+            "emacs" => EMACS_KEYS,
+            _ => {
+                return Err(Box::new(AuError::InvalidField(
+                    Box::from("preset"),
+                    Box::from(format!("unknown preset '{}'", name)),
+                )))
+            }
+        };
+        let mut keymap = Keymap { bindings: vec![] };
+        for (action, chords) in table {
+            keymap.bind(*action, chords)?;
+        }
+        Ok(keymap)
+    }
+
+    // bind replaces the chords of an action, taking them away from any other action in the same context.
+    pub fn bind<S: AsRef<str>>(&mut self, action: Action, chords: &[S]) -> Result<(), Box<dyn std::error::Error>> {
+        let chords = chords.iter().map(|c| c.as_ref().parse()).collect::<Result<Vec<KeyChord>, _>>()?;
+        self.bindings
+            .retain(|(c, a)| *a != action && !(a.context() == action.context() && chords.contains(c)));
+        self.bindings.extend(chords.into_iter().map(|c| (c, action)));
+        Ok(())
+    }
+
+    // chord is the first key bound to the action, which is the one shown in help.
+    pub fn chord(&self, action: Action) -> Option<KeyChord> {
+        self.bindings.iter().find(|(_, a)| *a == action).map(|(chord, _)| *chord)
+    }
+
+    // action finds what the key does in the context.
+    pub fn action(&self, context: Context, key: &KeyEvent) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|(chord, action)| action.context() == context && chord.matches(key))
+            .map(|(_, action)| *action)
+    }
+}
+
+const DEFAULT_KEYS: &[(Action, &[&str])] = &[
+    (Action::Up, &["up"]),
+    (Action::Down, &["down"]),
+    (Action::Parent, &["left"]),
+    (Action::Child, &["right"]),
+    (Action::Open, &["enter"]),
+    // shift and alt enter are only told apart from enter by terminals that report modifiers, so a and A do the same
+    (Action::AddBelow, &["shift-enter", "a"]),
+    (Action::AddChild, &["alt-enter", "A"]),
+    (Action::Edit, &["e"]),
+    (Action::MoveUp, &["shift-up", "K"]),
+    (Action::MoveDown, &["shift-down", "J"]),
+    (Action::Indent, &["tab", ">"]),
+    (Action::Outdent, &["backtab", "<"]),
+    (Action::Cut, &["x"]),
+    (Action::Paste, &["p"]),
+    (Action::Search, &["/"]),
+    (Action::Goto, &["g"]),
+    (Action::ShrinkTree, &["["]),
+    (Action::GrowTree, &["]"]),
+    (Action::Outline, &["o"]),
+    (Action::Quit, &["esc", "q"]),
+    (Action::ScrollUp, &["up", "k"]),
+    (Action::ScrollDown, &["down", "j"]),
+    (Action::PageUp, &["pgup"]),
+    (Action::PageDown, &["pgdn", "space"]),
+    (Action::Top, &["home"]),
+    (Action::Bottom, &["end"]),
+    (Action::Back, &["esc", "q", "left", "backspace"]),
+    (Action::Save, &["ctrl-s"]),
+    (Action::Cancel, &["esc"]),
+    (Action::Previous, &["up"]),
+    (Action::Next, &["down"]),
+    (Action::Collapse, &["left"]),
+    (Action::Expand, &["right"]),
+    (Action::Toggle, &["space"]),
+    (Action::ExpandAll, &["E", "*"]),
+    (Action::CollapseAll, &["C"]),
+    (Action::Close, &["enter", "esc", "o"]),
+];
+
+const VIM_KEYS: &[(Action, &[&str])] = &[
+    (Action::Up, &["k", "up"]),
+    (Action::Down, &["j", "down"]),
+    (Action::Parent, &["h", "left"]),
+    (Action::Child, &["l", "right"]),
+    (Action::Open, &["enter"]),
+    (Action::AddBelow, &["o", "shift-enter"]),
+    (Action::AddChild, &["a", "alt-enter"]),
+    (Action::Edit, &["i", "e"]),
+    (Action::MoveUp, &["K", "shift-up"]),
+    (Action::MoveDown, &["J", "shift-down"]),
+    (Action::Indent, &[">", "tab"]),
+    (Action::Outdent, &["<", "backtab"]),
+    (Action::Cut, &["d", "x"]),
+    (Action::Paste, &["p"]),
+    (Action::Search, &["/"]),
+    (Action::Goto, &["g"]),
+    (Action::ShrinkTree, &["["]),
+    (Action::GrowTree, &["]"]),
+    (Action::Outline, &["O"]),
+    (Action::Quit, &["q", "esc"]),
+    (Action::ScrollUp, &["k", "up", "ctrl-y"]),
+    (Action::ScrollDown, &["j", "down", "ctrl-e"]),
+    (Action::PageUp, &["ctrl-b", "ctrl-u", "pgup"]),
+    (Action::PageDown, &["ctrl-f", "ctrl-d", "pgdn", "space"]),
+    (Action::Top, &["g", "home"]),
+    (Action::Bottom, &["G", "end"]),
+    (Action::Back, &["q", "esc", "h", "left"]),
+    (Action::Save, &["ctrl-s"]),
+    (Action::Cancel, &["esc"]),
+    (Action::Previous, &["k", "up"]),
+    (Action::Next, &["j", "down"]),
+    (Action::Collapse, &["h", "left"]),
+    (Action::Expand, &["l", "right"]),
+    (Action::Toggle, &["space"]),
+    (Action::ExpandAll, &["R", "*"]),
+    (Action::CollapseAll, &["M"]),
+    (Action::Close, &["enter", "esc", "O", "q"]),
+];
+
+const EMACS_KEYS: &[(Action, &[&str])] = &[
+    (Action::Up, &["ctrl-p", "up"]),
+    (Action::Down, &["ctrl-n", "down"]),
+    (Action::Parent, &["ctrl-b", "left"]),
+    (Action::Child, &["ctrl-f", "right"]),
+    (Action::Open, &["enter", "ctrl-m"]),
+    (Action::AddBelow, &["ctrl-o", "shift-enter"]),
+    (Action::AddChild, &["alt-enter", "alt-o"]),
+    (Action::Edit, &["ctrl-e", "e"]),
+    (Action::MoveUp, &["alt-up", "alt-p"]),
+    (Action::MoveDown, &["alt-down", "alt-n"]),
+    (Action::Indent, &["alt-right", "tab"]),
+    (Action::Outdent, &["alt-left", "backtab"]),
+    (Action::Cut, &["ctrl-w", "ctrl-k"]),
+    (Action::Paste, &["ctrl-y"]),
+    (Action::Search, &["ctrl-s", "/"]),
+    (Action::Goto, &["alt-g", "g"]),
+    (Action::ShrinkTree, &["["]),
+    (Action::GrowTree, &["]"]),
+    (Action::Outline, &["o"]),
+    (Action::Quit, &["ctrl-c", "ctrl-q", "q"]),
+    (Action::ScrollUp, &["ctrl-p", "up"]),
+    (Action::ScrollDown, &["ctrl-n", "down"]),
+    (Action::PageUp, &["alt-v", "pgup"]),
+    (Action::PageDown, &["ctrl-v", "pgdn", "space"]),
+    (Action::Top, &["alt-<", "home"]),
+    (Action::Bottom, &["alt->", "end"]),
+    (Action::Back, &["ctrl-g", "esc", "q"]),
+    (Action::Save, &["ctrl-s"]),
+    (Action::Cancel, &["ctrl-g", "esc"]),
+    (Action::Previous, &["ctrl-p", "up"]),
+    (Action::Next, &["ctrl-n", "down"]),
+    (Action::Collapse, &["ctrl-b", "left"]),
+    (Action::Expand, &["ctrl-f", "right"]),
+    (Action::Toggle, &["tab", "space"]),
+    (Action::ExpandAll, &["backtab", "*"]),
+    (Action::CollapseAll, &["alt-c"]),
+    (Action::Close, &["enter", "ctrl-g", "esc", "o"]),
+];
+
+// ClassStyle is how items of a class are shown in the tree.
+#[derive(Default, Clone)]
+pub struct ClassStyle {
+    pub icon: Option<Box<str>>,
+    pub style: Style,
+}
+
+pub struct Theme {
+    pub border: Style,
+    pub title: Style,
+    pub highlight: Style,
+    pub highlight_symbol: Box<str>,
+    pub footer: Style,
+    pub message: Style,
+    pub cut: Style,
+    pub classes: HashMap<Box<str>, ClassStyle>,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            border: Style::default(),
+            title: Style::default(),
+            highlight: Style::default().add_modifier(Modifier::ITALIC),
+            highlight_symbol: Box::from(DEFAULT_HIGHLIGHT_SYMBOL),
+            footer: Style::default(),
+            message: Style::default().fg(Color::Red),
+            cut: Style::default().add_modifier(Modifier::CROSSED_OUT | Modifier::DIM),
+            classes: HashMap::new(),
+        }
+    }
+}
+
+impl Theme {
+    // class_style is the style for an item of the class, items without a configured class get the default.
+    pub fn class_style(&self, class: Option<&str>) -> Option<&ClassStyle> {
+        class.and_then(|c| self.classes.get(c))
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub keymap: Keymap,
+    pub theme: Theme,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        // the default preset is built in and known to parse
+        Keymap::preset("default").unwrap()
+    }
+}
+
+impl Config {
+    // path is the config file named by AUI_CONFIG, or aui.toml under the user's config directory.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(p) = std::env::var_os(CONFIG_ENV) {
+            return Some(PathBuf::from(p));
+        }
+        let base = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(p) if !p.is_empty() => PathBuf::from(p),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(base.join(CONFIG_FILE))
+    }
+
+    // load reads the config file, a missing file gives the defaults.
+    pub fn load(path: &Path) -> Result<Config, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Config::parse(&s)
+                .map_err(|e| Box::new(AuError::NestedError(Box::from(path.to_string_lossy()), e)) as Box<dyn std::error::Error>),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<Config, Box<dyn std::error::Error>> {
+        let file: ConfigFile = toml::from_str(source)?;
+        let mut keymap = Keymap::preset(file.preset.as_deref().unwrap_or("default"))?;
+        // The keys table is unordered, so a chord given to two actions in the same context could end up with either.
+        let mut bound: Vec<(KeyChord, Action)> = vec![];
+        for (action, chords) in file.keys.iter() {
+            for chord in chords {
+                let chord: KeyChord = chord.parse()?;
+                if let Some((_, other)) = bound.iter().find(|(c, a)| *c == chord && a.context() == action.context()) {
+                    return Err(Box::new(AuError::InvalidField(
+                        Box::from("keys"),
+                        Box::from(format!("'{}' is bound to both {:?} and {:?}", chord, other, action)),
+                    )));
+                }
+                bound.push((chord, *action));
+            }
+        }
+        for (action, chords) in file.keys.iter() {
+            keymap.bind(*action, chords)?;
+        }
+        let mut theme = Theme::default();
+        let styles = [
+            (&file.theme.border, &mut theme.border),
+            (&file.theme.title, &mut theme.title),
+            (&file.theme.highlight, &mut theme.highlight),
+            (&file.theme.footer, &mut theme.footer),
+            (&file.theme.message, &mut theme.message),
+            (&file.theme.cut, &mut theme.cut),
+        ];
+        for (config, style) in styles {
+            if let Some(config) = config {
+                *style = config.to_style()?;
+            }
+        }
+        if let Some(symbol) = file.theme.highlight_symbol {
+            theme.highlight_symbol = Box::from(symbol);
+        }
+        for (class, config) in file.classes {
+            let class_style = ClassStyle {
+                icon: config.icon.map(Box::from),
+                style: config.style.to_style()?,
+            };
+            theme.classes.insert(Box::from(class), class_style);
+        }
+        Ok(Config { keymap, theme })
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    preset: Option<String>,
+    #[serde(default)]
+    keys: HashMap<Action, Vec<String>>,
+    #[serde(default)]
+    theme: ThemeConfig,
+    #[serde(default)]
+    classes: HashMap<String, ClassConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ThemeConfig {
+    border: Option<StyleConfig>,
+    title: Option<StyleConfig>,
+    highlight: Option<StyleConfig>,
+    highlight_symbol: Option<String>,
+    footer: Option<StyleConfig>,
+    message: Option<StyleConfig>,
+    cut: Option<StyleConfig>,
+}
+
+#[derive(Deserialize)]
+struct ClassConfig {
+    icon: Option<String>,
+    #[serde(flatten)]
+    style: StyleConfig,
+}
+
+#[derive(Deserialize, Default)]
+struct StyleConfig {
+    fg: Option<String>,
+    bg: Option<String>,
+    #[serde(default)]
+    modifiers: Vec<String>,
+}
+
+impl StyleConfig {
+    fn to_style(&self) -> Result<Style, Box<dyn std::error::Error>> {
+        let color = |field: &str, value: &str| {
+            Color::from_str(value).map_err(|_| AuError::InvalidField(Box::from(field), Box::from(format!("'{}' is not a color", value))))
+        };
+        let mut style = Style::default();
+        if let Some(fg) = &self.fg {
+            style = style.fg(color("fg", fg)?);
+        }
+        if let Some(bg) = &self.bg {
+            style = style.bg(color("bg", bg)?);
+        }
+        for m in self.modifiers.iter() {
+            style = style.add_modifier(match m.to_ascii_lowercase().as_str() {
+                "bold" => Modifier::BOLD,
+                "dim" => Modifier::DIM,
+                "italic" => Modifier::ITALIC,
+                "underlined" => Modifier::UNDERLINED,
+                "reversed" => Modifier::REVERSED,
+                "crossed_out" => Modifier::CROSSED_OUT,
+                "slow_blink" => Modifier::SLOW_BLINK,
+                "rapid_blink" => Modifier::RAPID_BLINK,
+                "hidden" => Modifier::HIDDEN,
+                _ => {
+                    return Err(Box::new(AuError::InvalidField(
+                        Box::from("modifiers"),
+                        Box::from(format!("'{}' is not a modifier", m)),
+                    )))
+                }
+            });
+        }
+        Ok(style)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn test_key_chords() {
+        let chord: KeyChord = "ctrl-s".parse().unwrap();
+        assert!(chord.matches(&key(KeyCode::Char('s'), KeyModifiers::CONTROL)));
+        assert!(!chord.matches(&key(KeyCode::Char('s'), KeyModifiers::NONE)));
+
+        // terminals disagree on whether shifted letters carry the shift modifier
+        let chord: KeyChord = "K".parse().unwrap();
+        assert!(chord.matches(&key(KeyCode::Char('K'), KeyModifiers::SHIFT)));
+        assert!(chord.matches(&key(KeyCode::Char('K'), KeyModifiers::NONE)));
+        assert_eq!("shift-k".parse::<KeyChord>().unwrap(), chord);
+
+        let chord: KeyChord = "shift-up".parse().unwrap();
+        assert!(chord.matches(&key(KeyCode::Up, KeyModifiers::SHIFT)));
+        assert!(!chord.matches(&key(KeyCode::Up, KeyModifiers::NONE)));
+
+        assert!("-"
+            .parse::<KeyChord>()
+            .unwrap()
+            .matches(&key(KeyCode::Char('-'), KeyModifiers::NONE)));
+        assert!("alt->"
+            .parse::<KeyChord>()
+            .unwrap()
+            .matches(&key(KeyCode::Char('>'), KeyModifiers::ALT)));
+        assert!("f5".parse::<KeyChord>().unwrap().matches(&key(KeyCode::F(5), KeyModifiers::NONE)));
+        assert_eq!("c-M-pagedown".parse::<KeyChord>().unwrap().to_string(), "ctrl-alt-pgdn");
+        assert_eq!("shift-backtab".parse::<KeyChord>().unwrap().to_string(), "backtab");
+        assert!("hyper-x".parse::<KeyChord>().is_err());
+        assert!("nokey".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        let up = key(KeyCode::Char('k'), KeyModifiers::NONE);
+        assert_eq!(Keymap::preset("default").unwrap().action(Context::Tree, &up), None);
+        assert_eq!(
+            Keymap::preset("default").unwrap().action(Context::Detail, &up),
+            Some(Action::ScrollUp)
+        );
+        assert_eq!(Keymap::preset("vim").unwrap().action(Context::Tree, &up), Some(Action::Up));
+        let next = key(KeyCode::Char('n'), KeyModifiers::CONTROL);
+        assert_eq!(Keymap::preset("emacs").unwrap().action(Context::Tree, &next), Some(Action::Down));
+        let all = key(KeyCode::Char('M'), KeyModifiers::SHIFT);
+        assert_eq!(
+            Keymap::preset("vim").unwrap().action(Context::Outline, &all),
+            Some(Action::CollapseAll)
+        );
+        assert!(Keymap::preset("nano").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            r##"
+preset = "vim"
+
+[keys]
+quit = ["ctrl-c", "d"]
+
+[theme]
+border = { fg = "blue" }
+highlight = { fg = "black", bg = "#ffcc00", modifiers = ["bold"] }
+highlight_symbol = "> "
+
+[classes.todo]
+icon = "[ ]"
+fg = "yellow"
+"##,
+        )
+        .unwrap();
+        let quit = key(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(config.keymap.action(Context::Tree, &quit), Some(Action::Quit));
+        // d was cut in the preset and is taken over, x still cuts
+        assert_eq!(
+            config.keymap.action(Context::Tree, &key(KeyCode::Char('d'), KeyModifiers::NONE)),
+            Some(Action::Quit)
+        );
+        assert_eq!(
+            config.keymap.action(Context::Tree, &key(KeyCode::Char('x'), KeyModifiers::NONE)),
+            Some(Action::Cut)
+        );
+        assert_eq!(
+            config.keymap.action(Context::Tree, &key(KeyCode::Char('q'), KeyModifiers::NONE)),
+            None
+        );
+        assert_eq!(
+            config.keymap.action(Context::Tree, &key(KeyCode::Char('j'), KeyModifiers::NONE)),
+            Some(Action::Down)
+        );
+        assert_eq!(config.theme.border, Style::default().fg(Color::Blue));
+        assert_eq!(
+            config.theme.highlight,
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Rgb(0xff, 0xcc, 0))
+                .add_modifier(Modifier::BOLD)
+        );
+        assert_eq!(config.theme.highlight_symbol.as_ref(), "> ");
+        let todo = config.theme.class_style(Some("todo")).unwrap();
+        assert_eq!(todo.icon.as_deref(), Some("[ ]"));
+        assert_eq!(todo.style, Style::default().fg(Color::Yellow));
+        assert!(config.theme.class_style(Some("note")).is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Config::parse("[keys]\nfly = [\"f\"]").is_err());
+        assert!(Config::parse("[keys]\nup = [\"ctrl-\"]").is_err());
+        assert!(Config::parse("[keys]\nup = [\"k\"]\ndown = [\"k\"]").is_err());
+        // the same chord can do different things in different contexts
+        assert!(Config::parse("[keys]\nup = [\"k\"]\nscroll_up = [\"k\"]").is_ok());
+        assert!(Config::parse("[theme]\nborder = { fg = \"blurple\" }").is_err());
+        assert!(Config::parse("[theme]\nborder = { modifiers = [\"sparkly\"] }").is_err());
+        assert!(Config::parse("preset = \"nano\"").is_err());
+        assert!(Config::parse("colour = \"red\"").is_err());
+    }
+}
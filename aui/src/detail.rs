@@ -0,0 +1,195 @@
+/*
+
+detail builds the lines of the detail view for an item: a block of metadata followed by the content. Text is wrapped
+to the width of the view here rather than by ratatui so that the number of lines, and so how far the view can scroll,
+is known before drawing. Markdown gets some basic line level styling and anything that is not text is shown as a hex
+dump.
+
+ */
+
+use au::item::{Item, Project};
+use au::path::item_path;
+use ratatui::style::{Modifier, Style};
+use ratatui::text::{Line, Span};
+use time::format_description::well_known::Rfc3339;
+
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+const CONTENT_TYPE_MARKDOWN: &str = "text/markdown";
+// HEX_DUMP_WIDTH is the number of bytes shown on each line of a hex dump.
+const HEX_DUMP_WIDTH: usize = 16;
+const LABEL_WIDTH: usize = 14;
+
+// metadata_lines describes the item itself, the parent is shown by path so that it can be found in the tree.
+pub fn metadata_lines(project: &Project, item: &Item) -> Vec<Line<'static>> {
+    let parent = match item.parent.as_deref() {
+        Some(p) => item_path(project, p).unwrap_or_else(|_| p.to_string()),
+        None => String::from("/"),
+    };
+    let at = item.at.format(&Rfc3339).unwrap_or_else(|_| item.at.to_string());
+    [
+        ("id", item.id.to_string()),
+        ("class", item.class.as_deref().unwrap_or("").to_string()),
+        ("content type", format!("{} ({} bytes)", item.content_type, item.content.len())),
+        ("rank", item.rank.to_string()),
+        ("at", at),
+        ("parent", parent),
+    ]
+    .into_iter()
+    .map(|(label, value)| {
+        Line::from(vec![
+            Span::styled(
+                format!("{:<width$}", label, width = LABEL_WIDTH),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(value),
+        ])
+    })
+    .collect()
+}
+
+// content_lines returns the content wrapped to the given width, or a hex dump when the content is not text.
+pub fn content_lines(item: &Item, width: usize) -> Vec<Line<'static>> {
+    let text = match std::str::from_utf8(item.content.as_ref()) {
+        Ok(s) if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) => s,
+        _ => return hex_dump(item.content.as_ref()).into_iter().map(Line::from).collect(),
+    };
+    let markdown = item.content_type.starts_with(CONTENT_TYPE_MARKDOWN);
+    let mut in_fence = false;
+    let mut out: Vec<Line<'static>> = Vec::new();
+    for line in text.lines() {
+        let style = if markdown {
+            markdown_style(line, &mut in_fence)
+        } else {
+            Style::default()
+        };
+        out.extend(wrap(line, width).into_iter().map(|l| Line::styled(l, style)));
+    }
+    out
+}
+
+// markdown_style picks a style for a whole line of markdown, tracking whether the line is inside a code fence.
+fn markdown_style(line: &str, in_fence: &mut bool) -> Style {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+        *in_fence = !*in_fence;
+        return Style::default().add_modifier(Modifier::DIM);
+    }
+    if *in_fence {
+        return Style::default().add_modifier(Modifier::DIM);
+    }
+    if trimmed.starts_with('#') {
+        Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+    } else if trimmed.starts_with('>') {
+        Style::default().add_modifier(Modifier::ITALIC)
+    } else {
+        Style::default()
+    }
+}
+
+// wrap breaks a line into pieces of at most width characters, preferring to break after whitespace. Words longer than
+// the width are split. An empty line stays as one empty line.
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut out: Vec<String> = Vec::new();
+    let mut rest = line.trim_end();
+    while rest.chars().count() > width {
+        let limit = rest.char_indices().nth(width).map_or(rest.len(), |(i, _)| i);
+        // break at the last whitespace that fits, which may be just past the limit, or mid-word when there is none
+        let window = rest.char_indices().nth(width + 1).map_or(rest.len(), |(i, _)| i);
+        let end = match rest[..window].rfind(char::is_whitespace) {
+            Some(i) if i > 0 => i,
+            _ => limit,
+        };
+        out.push(rest[..end].trim_end().to_string());
+        rest = rest[end..].trim_start();
+    }
+    out.push(rest.to_string());
+    out
+}
+
+// hex_dump shows bytes in the usual offset, hex and printable ascii columns.
+fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(HEX_DUMP_WIDTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            let ascii: String = chunk
+                .iter()
+                .map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' })
+                .collect();
+            format!(
+                "{:08x}  {:<width$}  |{}|",
+                i * HEX_DUMP_WIDTH,
+                hex.join(" "),
+                ascii,
+                width = HEX_DUMP_WIDTH * 3 - 1
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("", 10), vec![""]);
+        assert_eq!(wrap("short", 10), vec!["short"]);
+        assert_eq!(wrap("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+        assert_eq!(wrap("abcdefghijkl", 5), vec!["abcde", "fghij", "kl"]);
+        assert_eq!(wrap("héllo wörld", 6), vec!["héllo", "wörld"]);
+    }
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = hex_dump(b"hello\x00\x01world, more than sixteen");
+        assert_eq!(dump.len(), 2);
+        assert_eq!(
+            dump[0],
+            "00000000  68 65 6c 6c 6f 00 01 77 6f 72 6c 64 2c 20 6d 6f  |hello..world, mo|"
+        );
+        assert!(dump[1].starts_with("00000010  72 65 20 74"));
+        assert!(dump[1].ends_with("|re than sixteen|"));
+    }
+
+    #[test]
+    fn test_content_lines_markdown() {
+        let item = Item {
+            content_type: Rc::from("text/markdown"),
+            content: Rc::from("# Title\n\n```\n# not a heading\n```\nplain".as_bytes()),
+            ..Default::default()
+        };
+        let lines = content_lines(&item, 40);
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].style.add_modifier.contains(Modifier::BOLD));
+        assert!(lines[3].style.add_modifier.contains(Modifier::DIM));
+        assert!(!lines[3].style.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(lines[5].style, Style::default());
+    }
+
+    #[test]
+    fn test_metadata_lines() {
+        let mut project = Project::default();
+        let mut doc = automerge::AutoCommit::new();
+        let parent = Item {
+            id: Rc::from("p"),
+            content: Rc::from("Parent".as_bytes()),
+            ..Default::default()
+        };
+        let child = Item {
+            id: Rc::from("c"),
+            class: Some(Rc::from("todo")),
+            parent: Some(Rc::from("p")),
+            ..Default::default()
+        };
+        project.with_item(&parent, &mut doc).unwrap();
+        project.with_item(&child, &mut doc).unwrap();
+        let lines: Vec<String> = metadata_lines(&project, &child).iter().map(|l| l.to_string()).collect();
+        assert_eq!(lines[1], format!("{:<14}todo", "class"));
+        assert_eq!(lines[5], format!("{:<14}/Parent", "parent"));
+    }
+}
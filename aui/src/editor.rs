@@ -0,0 +1,193 @@
+/*
+
+editor is a small multi-line text editor for item content. The text is held as lines with the cursor at a line and a
+character within it, so that multibyte text never splits a character. There is no wrapping, the ui scrolls the view to
+keep the cursor visible instead.
+
+ */
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub struct Editor {
+    lines: Vec<String>,
+    // row is the line the cursor is on and col is the number of characters before the cursor on that line.
+    row: usize,
+    col: usize,
+}
+
+impl Default for Editor {
+    fn default() -> Editor {
+        Editor::new("")
+    }
+}
+
+impl Editor {
+    // new starts with the cursor at the end of the text so that adding to it is the quickest thing to do.
+    pub fn new(text: &str) -> Editor {
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        let row = lines.len() - 1;
+        let col = lines[row].chars().count();
+        Editor { lines, row, col }
+    }
+
+    pub fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.row, self.col)
+    }
+
+    // on_key applies an editing key and returns false if the key was not one the editor handles.
+    pub fn on_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+            return false;
+        }
+        match key.code {
+            KeyCode::Char(c) => self.insert(c),
+            KeyCode::Tab => self.insert('\t'),
+            KeyCode::Enter => self.newline(),
+            KeyCode::Backspace => self.backspace(),
+            KeyCode::Delete => self.delete(),
+            KeyCode::Left => self.left(),
+            KeyCode::Right => self.right(),
+            KeyCode::Up => self.up(),
+            KeyCode::Down => self.down(),
+            KeyCode::Home => self.col = 0,
+            KeyCode::End => self.col = self.line_len(),
+            _ => return false,
+        }
+        true
+    }
+
+    fn line_len(&self) -> usize {
+        self.lines[self.row].chars().count()
+    }
+
+    // byte_index is the byte offset of the cursor within its line.
+    fn byte_index(&self) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices().nth(self.col).map_or(line.len(), |(i, _)| i)
+    }
+
+    fn insert(&mut self, c: char) {
+        let i = self.byte_index();
+        self.lines[self.row].insert(i, c);
+        self.col += 1;
+    }
+
+    fn newline(&mut self) {
+        let i = self.byte_index();
+        let rest = self.lines[self.row].split_off(i);
+        self.row += 1;
+        self.col = 0;
+        self.lines.insert(self.row, rest);
+    }
+
+    // backspace deletes the character before the cursor, joining with the previous line at the start of a line.
+    fn backspace(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+            let i = self.byte_index();
+            self.lines[self.row].remove(i);
+        } else if self.row > 0 {
+            let line = self.lines.remove(self.row);
+            self.row -= 1;
+            self.col = self.line_len();
+            self.lines[self.row].push_str(&line);
+        }
+    }
+
+    // delete deletes the character after the cursor, joining with the next line at the end of a line.
+    fn delete(&mut self) {
+        if self.col < self.line_len() {
+            let i = self.byte_index();
+            self.lines[self.row].remove(i);
+        } else if self.row + 1 < self.lines.len() {
+            let line = self.lines.remove(self.row + 1);
+            self.lines[self.row].push_str(&line);
+        }
+    }
+
+    fn left(&mut self) {
+        if self.col > 0 {
+            self.col -= 1;
+        } else if self.row > 0 {
+            self.row -= 1;
+            self.col = self.line_len();
+        }
+    }
+
+    fn right(&mut self) {
+        if self.col < self.line_len() {
+            self.col += 1;
+        } else if self.row + 1 < self.lines.len() {
+            self.row += 1;
+            self.col = 0;
+        }
+    }
+
+    fn up(&mut self) {
+        if self.row > 0 {
+            self.row -= 1;
+            self.col = self.col.min(self.line_len());
+        }
+    }
+
+    fn down(&mut self) {
+        if self.row + 1 < self.lines.len() {
+            self.row += 1;
+            self.col = self.col.min(self.line_len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_keys(editor: &mut Editor, codes: &[KeyCode]) {
+        for code in codes {
+            editor.on_key(KeyEvent::new(*code, KeyModifiers::NONE));
+        }
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut editor = Editor::new("héllo\nwörld");
+        assert_eq!(editor.cursor(), (1, 5));
+        type_keys(
+            &mut editor,
+            &[
+                KeyCode::Char('!'),
+                KeyCode::Up,
+                KeyCode::Left,
+                KeyCode::Backspace,
+                KeyCode::Char('L'),
+            ],
+        );
+        assert_eq!(editor.text(), "hélLo\nwörld!");
+        type_keys(&mut editor, &[KeyCode::Home, KeyCode::Right, KeyCode::Delete, KeyCode::Char('e')]);
+        assert_eq!(editor.text(), "helLo\nwörld!");
+        type_keys(&mut editor, &[KeyCode::End, KeyCode::Delete]);
+        assert_eq!(editor.text(), "helLowörld!");
+        type_keys(&mut editor, &[KeyCode::Enter, KeyCode::Enter]);
+        assert_eq!(editor.text(), "helLo\n\nwörld!");
+        assert_eq!(editor.cursor(), (2, 0));
+        type_keys(&mut editor, &[KeyCode::Backspace, KeyCode::Backspace, KeyCode::Backspace]);
+        assert_eq!(editor.text(), "helLwörld!");
+        assert_eq!(editor.cursor(), (0, 4));
+    }
+
+    #[test]
+    fn test_modified_keys_are_not_handled() {
+        let mut editor = Editor::new("");
+        assert!(!editor.on_key(KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL)));
+        assert!(editor.on_key(KeyEvent::new(KeyCode::Char('S'), KeyModifiers::SHIFT)));
+        assert_eq!(editor.text(), "S");
+    }
+}
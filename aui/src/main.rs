@@ -1,46 +1,155 @@
 use std::io;
-use std::io::{Result, stdout};
+use std::io::stdout;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
-use crossterm::{event::{self}, ExecutableCommand, execute, terminal::{
-    disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
-    LeaveAlternateScreen,
-}};
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture, Event};
+use au::storage::fs::{FsBackend, Watcher};
+use clap::Parser;
+
+use crossterm::event::{
+    DisableMouseCapture, EnableMouseCapture, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{
+    event::{self},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen, LeaveAlternateScreen},
+};
 use ratatui::backend::Backend;
-use ratatui::prelude::{CrosstermBackend, Stylize, Terminal};
+use ratatui::prelude::{CrosstermBackend, Terminal};
+use time::UtcOffset;
 
 use crate::app::App;
+use crate::config::Config;
+use crate::outline::OutlineState;
+use crate::store::Store;
 use crate::ui::ui;
 
 mod app;
+mod config;
+mod detail;
+mod editor;
+mod ops;
+mod outline;
+mod search;
+mod store;
 mod ui;
 
-fn main() -> Result<()> {
+// WATCH_INTERVAL is how often the project directory is checked for changes saved by other processes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(name = "aui", about = "Browse and edit an au project in the terminal")]
+struct Cli {
+    /// The project directory, without one aui starts an empty project that is not saved
+    #[arg(env = "AU_PROJECT")]
+    project: Option<PathBuf>,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("aui: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    // Read the config and open the project before taking over the terminal so that mistakes are printed normally.
+    let config = match Config::path() {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    // the local offset can only be found while there is a single thread, so before the watcher starts
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    // outline is where the outline of the project is kept between sessions and the key it is kept under
+    let mut outline: Option<(PathBuf, String)> = None;
+    let (app, watcher) = match &cli.project {
+        Some(path) => {
+            let backend = FsBackend::open(path)?;
+            let watcher = backend.watch(WATCH_INTERVAL);
+            let (store, doc, project) = Store::open(Box::new(backend), path.to_string_lossy().as_ref(), offset)?;
+            let mut app = App::with_project(doc, project).with_store(store);
+            if let Some(state_path) = OutlineState::path() {
+                let key = std::fs::canonicalize(path)?.to_string_lossy().to_string();
+                // the outline is only a convenience, a broken state file should not keep the project from opening
+                let state = OutlineState::load(&state_path).unwrap_or_else(|e| {
+                    eprintln!("aui: warning: ignoring outline state: {}", e);
+                    OutlineState::default()
+                });
+                app.expanded = state.expanded(&key);
+                outline = Some((state_path, key));
+            }
+            (app, Some(watcher))
+        }
+        None => (App::new(), None),
+    };
+    let mut app = app.with_config(config);
+
     // Enter raw mode so that we no longer care about wrapping and backspaces and that sort
     // of thing.
     enable_raw_mode()?;
     // Enter a fresh new screen and start polling for mouse events.
     execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    // Ask for modifiers on keys like enter where the terminal can report them, so that shift and alt enter work.
+    let enhanced = supports_keyboard_enhancement().unwrap_or(false);
+    if enhanced {
+        execute!(
+            stdout(),
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )?;
+    }
     // Set up Ratatui with the cross term backend
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
 
-    let mut app = App::new();
-    let _ = run_app(&mut terminal, &mut app);
+    let result = run_app(&mut terminal, &mut app, watcher.as_ref());
+    // Save whatever was changed before anything else can fail, one last try if the last save failed, and keep the
+    // outline even when the app failed.
+    let saved = app.save();
+    let kept = match &outline {
+        Some((state_path, key)) => OutlineState::store(state_path, key, &app.expanded),
+        None => Ok(()),
+    };
 
-    // Restore terminal back to original modes.
+    // Restore terminal back to original modes, even when the app failed, so that the error is readable.
+    if enhanced {
+        execute!(terminal.backend_mut(), PopKeyboardEnhancementFlags)?;
+    }
     disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
 
-    // Return.
-    Ok(())
+    // Report every failure, a save that failed after the app did should not go unnoticed.
+    let mut errors: Vec<String> = Vec::new();
+    if let Err(e) = result {
+        errors.push(e.to_string());
+    }
+    if let Err(e) = saved {
+        errors.push(format!("changes not saved: {}", e));
+    }
+    if let Err(e) = kept {
+        errors.push(format!("outline not kept: {}", e));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Box::from(errors.join("\naui: ")))
+    }
 }
 
-fn run_app<B: Backend>(term: &mut Terminal<B>, app: &mut App) -> io::Result<bool> {
+// run_app draws and passes events to the app until it asks to quit. With a watcher it also wakes up now and then to
+// merge changes saved by other processes.
+fn run_app<B: Backend>(term: &mut Terminal<B>, app: &mut App, watcher: Option<&Watcher>) -> io::Result<()> {
     loop {
         term.draw(|f| ui(f, app))?;
-        if let Event::Key(key) = event::read()? {
-            continue
+        if event::poll(WATCH_INTERVAL)? && app.on_event(event::read()?, Instant::now()) {
+            return Ok(());
+        }
+        if watcher.is_some_and(|w| w.changed()) {
+            app.refresh();
         }
     }
-}
\ No newline at end of file
+}
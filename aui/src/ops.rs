@@ -0,0 +1,277 @@
+/*
+
+ops are the changes the tui makes to the project. Each one is a single transaction on the document so that a failure
+leaves both the document and the project as they were, and the error can be shown without losing anything.
+
+ */
+
+use std::rc::Rc;
+
+use au::error::AuError;
+use au::id::IdGen;
+use au::item::{Item, ItemUpdate, Project};
+use automerge::AutoCommit;
+use time::OffsetDateTime;
+
+const CONTENT_TYPE_DEFAULT: &str = "text/plain";
+// RENUMBER_STEP is the gap left between siblings when they are renumbered, so that many items can be placed between
+// them again before another renumbering.
+const RENUMBER_STEP: i64 = 1 << 20;
+
+// add_below adds a text item directly below the sibling. Returns the id of the new item.
+pub fn add_below(
+    doc: &mut AutoCommit,
+    project: &mut Project,
+    sibling: &Item,
+    content: &str,
+) -> Result<Rc<str>, Box<dyn std::error::Error>> {
+    let siblings = project.list_children(sibling.parent.as_deref());
+    let index = siblings.iter().position(|s| s.id == sibling.id).map_or(siblings.len(), |i| i + 1);
+    let (rank, moved) = placement(&siblings, index);
+    let item = new_item(sibling.parent.clone(), rank, content);
+    project.transact(doc, format!("add {}", item.id).as_str(), |p, d| {
+        p.with_item(&item, d)?;
+        for (id, rank) in moved.iter() {
+            p.with_updated_item(id, &[ItemUpdate::Rank(*rank)], d)?;
+        }
+        Ok(())
+    })?;
+    Ok(item.id)
+}
+
+// move_to moves an item, along with everything under it, to the index among the children of the parent. The index
+// counts the children without the item itself, so moving within the same parent works the same as moving elsewhere.
+// Moving an item under itself fails with a cycle error and changes nothing.
+pub fn move_to(
+    doc: &mut AutoCommit,
+    project: &mut Project,
+    id: &str,
+    parent: Option<&str>,
+    index: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let item = project.get_item(id).ok_or_else(|| AuError::NoSuchKey(Box::from(id)))?;
+    let siblings: Vec<Rc<Item>> = project.list_children(parent).into_iter().filter(|s| s.id != item.id).collect();
+    let (rank, moved) = placement(&siblings, index.min(siblings.len()));
+    let mut updates = vec![ItemUpdate::Rank(rank)];
+    if item.parent.as_deref() != parent {
+        updates.insert(0, ItemUpdate::Parent(parent.map(Box::from)));
+    }
+    project.transact(doc, format!("move {}", id).as_str(), |p, d| {
+        p.with_updated_item(id, &updates, d)?;
+        for (id, rank) in moved.iter() {
+            p.with_updated_item(id, &[ItemUpdate::Rank(*rank)], d)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// placement finds a rank that puts an item at the index among the siblings, which are in list_children order and do
+// not include the item. Where there is no room between the ranks either side the siblings that follow are moved
+// further down, and those moves are returned with the rank.
+fn placement(siblings: &[Rc<Item>], index: usize) -> (i64, Vec<(Rc<str>, i64)>) {
+    let Some(above) = index.checked_sub(1).and_then(|i| siblings.get(i)) else {
+        return match siblings.first() {
+            None => (0, vec![]),
+            Some(first) => match first.rank.checked_add(1) {
+                Some(rank) => (rank, vec![]),
+                None => renumber(siblings, index),
+            },
+        };
+    };
+    if let Some(below) = siblings.get(index) {
+        let gap = above.rank as i128 - below.rank as i128;
+        if gap >= 2 {
+            return ((above.rank as i128 - gap / 2) as i64, vec![]);
+        }
+    }
+    // an item placed at the same rank as a sibling would be ordered by time, so every rank has to be strictly lower
+    let Some(rank) = above.rank.checked_sub(1) else {
+        return renumber(siblings, index);
+    };
+    let mut moved: Vec<(Rc<str>, i64)> = Vec::new();
+    let mut next = rank;
+    for s in siblings[index..].iter() {
+        if s.rank < next {
+            break;
+        }
+        next = match next.checked_sub(1) {
+            Some(n) => n,
+            None => return renumber(siblings, index),
+        };
+        moved.push((s.id.clone(), next));
+    }
+    (rank, moved)
+}
+
+// renumber spreads the ranks of all of the siblings out evenly around zero, leaving a place for the item at the index.
+// It is only needed once the siblings have been pushed to the end of the range of ranks.
+fn renumber(siblings: &[Rc<Item>], index: usize) -> (i64, Vec<(Rc<str>, i64)>) {
+    let count = siblings.len() as i64 + 1;
+    let step = RENUMBER_STEP.min(i64::MAX / count);
+    let rank_at = |position: i64| (count / 2 - position) * step;
+    let moved = siblings
+        .iter()
+        .enumerate()
+        .filter_map(|(i, s)| {
+            let position = if i < index { i } else { i + 1 };
+            let rank = rank_at(position as i64);
+            (rank != s.rank).then(|| (s.id.clone(), rank))
+        })
+        .collect();
+    (rank_at(index as i64), moved)
+}
+
+// add_into adds a text item at the bottom of the children of the parent. Returns the id of the new item.
+pub fn add_into(
+    doc: &mut AutoCommit,
+    project: &mut Project,
+    parent: Option<&str>,
+    content: &str,
+) -> Result<Rc<str>, Box<dyn std::error::Error>> {
+    let siblings = project.list_children(parent);
+    let (rank, moved) = placement(&siblings, siblings.len());
+    let item = new_item(parent.map(Rc::from), rank, content);
+    project.transact(doc, format!("add {}", item.id).as_str(), |p, d| {
+        p.with_item(&item, d)?;
+        for (id, rank) in moved.iter() {
+            p.with_updated_item(id, &[ItemUpdate::Rank(*rank)], d)?;
+        }
+        Ok(())
+    })?;
+    Ok(item.id)
+}
+
+// set_content replaces the text content of an item, keeping its content type.
+pub fn set_content(doc: &mut AutoCommit, project: &mut Project, id: &str, content: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let content_type = project
+        .get_item(id)
+        .ok_or_else(|| AuError::NoSuchKey(Box::from(id)))?
+        .content_type
+        .clone();
+    let update = ItemUpdate::Content(Box::from(content_type.as_ref()), Box::from(content.as_bytes()));
+    project.transact(doc, format!("edit {}", id).as_str(), |p, d| {
+        p.with_updated_item(id, &[update], d).map(|_| ())
+    })?;
+    Ok(())
+}
+
+fn new_item(parent: Option<Rc<str>>, rank: i64, content: &str) -> Item {
+    Item {
+        id: Rc::from(IdGen::default().gen(rand::thread_rng())),
+        at: OffsetDateTime::now_utc(),
+        class: None,
+        content_type: Rc::from(CONTENT_TYPE_DEFAULT),
+        content: Rc::from(content.as_bytes()),
+        rank,
+        parent,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use au::item::init_project;
+
+    use super::*;
+
+    fn ids(project: &Project, parent: Option<&str>) -> Vec<String> {
+        project
+            .list_children(parent)
+            .iter()
+            .map(|i| i.summary(usize::MAX).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_add_below() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let a = add_into(&mut doc, &mut project, None, "a").unwrap();
+        let c = add_into(&mut doc, &mut project, None, "c").unwrap();
+        add_into(&mut doc, &mut project, None, "d").unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "c", "d"]);
+
+        // a and c are next to each other in rank so c and d have to move down
+        let item_a = project.get_item(&a).unwrap();
+        add_below(&mut doc, &mut project, &item_a, "b").unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "b", "c", "d"]);
+
+        let item_d = project.list_children(None).last().unwrap().clone();
+        add_below(&mut doc, &mut project, &item_d, "e").unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "b", "c", "d", "e"]);
+
+        let c1 = add_into(&mut doc, &mut project, Some(&c), "c1").unwrap();
+        assert_eq!(ids(&project, Some(&c)), vec!["c1"]);
+        set_content(&mut doc, &mut project, &c1, "c one").unwrap();
+        assert_eq!(ids(&project, Some(&c)), vec!["c one"]);
+    }
+
+    #[test]
+    fn test_rank_limits() {
+        // ranked has items a, b and c at the ranks given, with ids the same as their content
+        let ranked = |ranks: [i64; 3]| {
+            let mut doc = AutoCommit::new();
+            let mut project = init_project(&mut doc).unwrap();
+            for (content, rank) in ["a", "b", "c"].into_iter().zip(ranks) {
+                let item = Item {
+                    id: Rc::from(content),
+                    ..new_item(None, rank, content)
+                };
+                project.with_item(&item, &mut doc).unwrap();
+            }
+            (doc, project)
+        };
+        let ranks = |project: &Project| -> Vec<i64> { project.list_children(None).iter().map(|i| i.rank).collect() };
+        let distinct = |project: &Project| ranks(project).windows(2).all(|w| w[0] > w[1]);
+
+        // nothing fits above the top at i64::MAX so the siblings are renumbered
+        let (mut doc, mut project) = ranked([i64::MAX, i64::MAX - 1, 0]);
+        move_to(&mut doc, &mut project, "c", None, 0).unwrap();
+        assert_eq!(ids(&project, None), vec!["c", "a", "b"]);
+        assert!(distinct(&project));
+
+        // nor below the bottom at i64::MIN
+        let (mut doc, mut project) = ranked([0, i64::MIN + 1, i64::MIN]);
+        add_into(&mut doc, &mut project, None, "d").unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "b", "c", "d"]);
+        assert!(distinct(&project));
+
+        // and moving the siblings that follow further down runs out of room
+        let (mut doc, mut project) = ranked([0, i64::MIN + 1, i64::MIN]);
+        let item_a = project.get_item("a").unwrap();
+        add_below(&mut doc, &mut project, &item_a, "a1").unwrap();
+        let item_b = project.get_item("b").unwrap();
+        add_below(&mut doc, &mut project, &item_b, "b1").unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "a1", "b", "b1", "c"]);
+        assert!(distinct(&project));
+    }
+
+    #[test]
+    fn test_move_to() {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        let a = add_into(&mut doc, &mut project, None, "a").unwrap();
+        let b = add_into(&mut doc, &mut project, None, "b").unwrap();
+        add_into(&mut doc, &mut project, None, "c").unwrap();
+        let b1 = add_into(&mut doc, &mut project, Some(&b), "b1").unwrap();
+
+        move_to(&mut doc, &mut project, &a, None, 2).unwrap();
+        assert_eq!(ids(&project, None), vec!["b", "c", "a"]);
+        move_to(&mut doc, &mut project, &a, None, 0).unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "b", "c"]);
+        move_to(&mut doc, &mut project, &a, None, 1).unwrap();
+        assert_eq!(ids(&project, None), vec!["b", "a", "c"]);
+
+        // b moves with its child
+        move_to(&mut doc, &mut project, &b, Some(&a), 0).unwrap();
+        assert_eq!(ids(&project, None), vec!["a", "c"]);
+        assert_eq!(ids(&project, Some(&a)), vec!["b"]);
+        assert_eq!(ids(&project, Some(&b)), vec!["b1"]);
+
+        // a cannot go under its own grandchild and nothing changes when it tries
+        let heads = doc.get_heads();
+        assert!(move_to(&mut doc, &mut project, &a, Some(&b1), 0).is_err());
+        assert_eq!(doc.get_heads(), heads);
+        assert_eq!(ids(&project, None), vec!["a", "c"]);
+    }
+}
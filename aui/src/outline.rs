@@ -0,0 +1,217 @@
+/*
+
+outline shows the whole hierarchy at once as an indented list, where each item with children can be expanded to show
+them or collapsed to hide them. Which items are expanded is kept between sessions in a state file, separately for each
+project directory, so that a large project opens the way it was left:
+
+["/home/me/notes/.au"]
+expanded = ["45PNBGMN127SEK", "8H2KQ0PLX3M1"]
+
+ */
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+
+use au::error::AuError;
+use au::item::{Item, Project};
+
+// STATE_ENV names a state file to use instead of the one in the user's state directory.
+pub const STATE_ENV: &str = "AUI_STATE";
+const STATE_FILE: &str = "au/aui-outline.toml";
+
+// OutlineRow is an item as it appears in the outline.
+pub struct OutlineRow {
+    pub item: Rc<Item>,
+    // depth is how many ancestors the item has, the top level is 0.
+    pub depth: usize,
+    // children is the number of children the item has whether or not they are shown.
+    pub children: usize,
+    pub expanded: bool,
+}
+
+// outline_rows lists the items in outline order, the children of expanded items following them in list_children order.
+// Items caught in a cycle have no path up to the top level so they never appear.
+pub fn outline_rows(project: &Project, expanded: &HashSet<Rc<str>>) -> Vec<OutlineRow> {
+    let mut rows: Vec<OutlineRow> = Vec::new();
+    add_rows(project, expanded, None, 0, &mut rows);
+    rows
+}
+
+fn add_rows(project: &Project, expanded: &HashSet<Rc<str>>, parent: Option<&str>, depth: usize, rows: &mut Vec<OutlineRow>) {
+    for item in project.list_children(parent) {
+        let children = if project.has_children(Some(&item.id)) {
+            project.list_children(Some(&item.id)).len()
+        } else {
+            0
+        };
+        let is_expanded = children > 0 && expanded.contains(&item.id);
+        rows.push(OutlineRow {
+            item: item.clone(),
+            depth,
+            children,
+            expanded: is_expanded,
+        });
+        if is_expanded {
+            add_rows(project, expanded, Some(&item.id), depth + 1, rows);
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct ProjectState {
+    #[serde(default)]
+    expanded: Vec<String>,
+}
+
+// OutlineState is the expanded items of each project that has been opened, keyed by project directory.
+#[derive(Default)]
+pub struct OutlineState {
+    projects: BTreeMap<String, ProjectState>,
+}
+
+impl OutlineState {
+    // path is the state file named by AUI_STATE, or the default one in the user's state directory.
+    pub fn path() -> Option<PathBuf> {
+        if let Some(p) = std::env::var_os(STATE_ENV) {
+            return Some(PathBuf::from(p));
+        }
+        let base = match std::env::var_os("XDG_STATE_HOME") {
+            Some(p) if !p.is_empty() => PathBuf::from(p),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("state"),
+        };
+        Some(base.join(STATE_FILE))
+    }
+
+    // load reads the state file, a missing file has nothing expanded.
+    pub fn load(path: &Path) -> Result<OutlineState, Box<dyn std::error::Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => OutlineState::parse(&s)
+                .map_err(|e| Box::new(AuError::NestedError(Box::from(path.to_string_lossy()), e)) as Box<dyn std::error::Error>),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(OutlineState::default()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<OutlineState, Box<dyn std::error::Error>> {
+        Ok(OutlineState {
+            projects: toml::from_str(source)?,
+        })
+    }
+
+    // save writes the state file, creating its directory if needed.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, toml::to_string(&self.projects)?)?;
+        Ok(())
+    }
+
+    // store re-reads the state file so that other projects closed in the meantime are kept, sets the expanded items of
+    // the project and saves it. A state file that can't be read is replaced rather than left broken.
+    pub fn store(path: &Path, project: &str, expanded: &HashSet<Rc<str>>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut state = OutlineState::load(path).unwrap_or_default();
+        state.set_expanded(project, expanded);
+        state.save(path)
+    }
+
+    pub fn expanded(&self, project: &str) -> HashSet<Rc<str>> {
+        self.projects
+            .get(project)
+            .map(|p| p.expanded.iter().map(|id| Rc::from(id.as_str())).collect())
+            .unwrap_or_default()
+    }
+
+    // set_expanded replaces the expanded items of the project, forgetting the project when nothing is expanded.
+    pub fn set_expanded(&mut self, project: &str, expanded: &HashSet<Rc<str>>) {
+        if expanded.is_empty() {
+            self.projects.remove(project);
+            return;
+        }
+        let mut ids: Vec<String> = expanded.iter().map(|id| id.to_string()).collect();
+        ids.sort();
+        self.projects.insert(String::from(project), ProjectState { expanded: ids });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge::AutoCommit;
+
+    use super::*;
+
+    #[test]
+    fn test_outline_rows() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        for (id, parent, rank) in [
+            ("a", None, 2),
+            ("b", None, 1),
+            ("a1", Some("a"), 2),
+            ("a2", Some("a"), 1),
+            ("a2x", Some("a2"), 0),
+        ] {
+            let item = Item {
+                id: Rc::from(id),
+                rank,
+                parent: parent.map(Rc::from),
+                ..Default::default()
+            };
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        let rows = |expanded: &[&str]| -> Vec<(String, usize, usize)> {
+            let expanded: HashSet<Rc<str>> = expanded.iter().map(|id| Rc::from(*id)).collect();
+            outline_rows(&project, &expanded)
+                .iter()
+                .map(|r| (r.item.id.to_string(), r.depth, r.children))
+                .collect()
+        };
+        assert_eq!(rows(&[]), vec![(String::from("a"), 0, 2), (String::from("b"), 0, 0)]);
+        // a2 is only shown expanded when a is as well, and b has nothing to expand
+        assert_eq!(rows(&["a2", "b"]), rows(&[]));
+        assert_eq!(
+            rows(&["a", "a2"]),
+            vec![
+                (String::from("a"), 0, 2),
+                (String::from("a1"), 1, 0),
+                (String::from("a2"), 1, 1),
+                (String::from("a2x"), 2, 0),
+                (String::from("b"), 0, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let mut state = OutlineState::default();
+        let expanded: HashSet<Rc<str>> = [Rc::from("b"), Rc::from("a")].into_iter().collect();
+        state.set_expanded("/p/.au", &expanded);
+        state.set_expanded("/q/.au", &HashSet::new());
+        let source = toml::to_string(&state.projects).unwrap();
+        assert_eq!(source, "[\"/p/.au\"]\nexpanded = [\"a\", \"b\"]\n");
+        let state = OutlineState::parse(&source).unwrap();
+        assert_eq!(state.expanded("/p/.au"), expanded);
+        assert!(state.expanded("/q/.au").is_empty());
+        assert!(OutlineState::parse("expanded = 1").is_err());
+    }
+
+    #[test]
+    fn test_store_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.toml");
+        let expanded: HashSet<Rc<str>> = [Rc::from("a")].into_iter().collect();
+        OutlineState::store(&path, "/p/.au", &expanded).unwrap();
+        OutlineState::store(&path, "/q/.au", &expanded).unwrap();
+        assert_eq!(OutlineState::load(&path).unwrap().expanded("/p/.au"), expanded);
+
+        // a corrupt state file is overwritten
+        std::fs::write(&path, "expanded = 1").unwrap();
+        OutlineState::store(&path, "/q/.au", &expanded).unwrap();
+        let state = OutlineState::load(&path).unwrap();
+        assert!(state.expanded("/p/.au").is_empty());
+        assert_eq!(state.expanded("/q/.au"), expanded);
+    }
+}
@@ -0,0 +1,123 @@
+/*
+
+search finds items across the whole project by fuzzy matching the query against their summaries and each line of
+their text content. A query matches text when all of its characters appear in order, ignoring case, and matches score
+higher the more of them are consecutive or start a word. Summary matches are preferred over content matches so that
+items named by the query come first.
+
+ */
+
+use std::cmp::Reverse;
+use std::rc::Rc;
+
+use au::item::{Item, Project};
+
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+// MAX_RESULTS bounds the result list, a query that matches more than this needs refining anyway.
+pub const MAX_RESULTS: usize = 100;
+const SCORE_MATCH: i64 = 1;
+const SCORE_CONSECUTIVE: i64 = 4;
+const SCORE_WORD_START: i64 = 3;
+const SCORE_SUMMARY: i64 = 10;
+
+// fuzzy_score scores how well the query matches the text, or None if it does not match at all. Each query character
+// takes the earliest match so the score is quick to find rather than the best possible.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut text_chars = text.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        if q.is_whitespace() {
+            continue;
+        }
+        loop {
+            let c = text_chars.next()?;
+            let word_start = previous.is_none_or(|p| !p.is_alphanumeric());
+            previous = Some(c);
+            if c == q {
+                score += SCORE_MATCH;
+                if consecutive {
+                    score += SCORE_CONSECUTIVE;
+                }
+                if word_start {
+                    score += SCORE_WORD_START;
+                }
+                consecutive = true;
+                break;
+            }
+            consecutive = false;
+        }
+    }
+    Some(score)
+}
+
+// search returns the items that match the query, best first. An empty query matches nothing.
+pub fn search(project: &Project, query: &str) -> Vec<Rc<Item>> {
+    if query.trim().is_empty() {
+        return vec![];
+    }
+    let mut scored: Vec<(i64, Rc<Item>)> = project
+        .list_items()
+        .into_iter()
+        .filter_map(|item| {
+            // items without text content have a made up summary like "(binary image/png file of 10 bytes)", which
+            // would match queries such as "file" for every attachment
+            let text = match std::str::from_utf8(item.content.as_ref()) {
+                Ok(text) if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) && !text.trim().is_empty() => text,
+                _ => return None,
+            };
+            let summary = fuzzy_score(query, &item.summary(usize::MAX)).map(|s| s + SCORE_SUMMARY);
+            let content = text.lines().filter_map(|line| fuzzy_score(query, line)).max();
+            summary.max(content).map(|score| (score, item))
+        })
+        .collect();
+    // list_items is ordered by id so equal scores stay in a stable order
+    scored.sort_by_key(|s| Reverse(s.0));
+    scored.into_iter().take(MAX_RESULTS).map(|(_, item)| item).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge::AutoCommit;
+
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert!(fuzzy_score("abc", "xaxbxc").is_some());
+        assert!(fuzzy_score("abc", "acb").is_none());
+        assert!(fuzzy_score("ÉTÉ", "un été chaud").is_some());
+        // consecutive and word start matches beat scattered ones
+        assert!(fuzzy_score("ql", "Q3 Launch").unwrap() > fuzzy_score("ql", "quietly").unwrap());
+        assert!(fuzzy_score("lau", "launch").unwrap() > fuzzy_score("lau", "l a u").unwrap());
+        assert_eq!(fuzzy_score("q l", "Q3 Launch"), fuzzy_score("ql", "Q3 Launch"));
+    }
+
+    #[test]
+    fn test_search() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        for (id, content, content_type) in [
+            ("a", "Groceries\nmilk and eggs", "text/plain"),
+            ("b", "Launch plan\nbuy milk for the party", "text/plain"),
+            ("c", "Milk", "text/plain"),
+            ("d", "milk", "application/octet-stream"),
+            ("e", "", "text/plain"),
+        ] {
+            let item = Item {
+                id: Rc::from(id),
+                content: Rc::from(content.as_bytes()),
+                content_type: Rc::from(content_type),
+                ..Default::default()
+            };
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        let ids: Vec<String> = search(&project, "milk").iter().map(|i| i.id.to_string()).collect();
+        assert_eq!(ids, vec!["c", "a", "b"]);
+        assert!(search(&project, "  ").is_empty());
+        assert!(search(&project, "zebra").is_empty());
+        // the summaries made up for binary and empty items are not searched
+        assert!(search(&project, "file").is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+/*
+
+store keeps an opened project in step with the backend it was loaded from. Every change made in the tui is saved as soon
+as it is made, and changes other processes save to the backend are merged in whenever the watcher notices them, so
+nothing is ever overwritten in either direction. The store also keeps what the footer shows about both.
+
+ */
+
+use automerge::{AutoCommit, ChangeHash};
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+
+use au::item::Project;
+use au::storage::{load_project, refresh_project, save_project, Backend};
+
+pub enum SaveState {
+    Saved,
+    // Failed holds the error from the last save, the next change or quitting tries again.
+    Failed(Box<str>),
+}
+
+pub struct Store {
+    backend: Box<dyn Backend>,
+    // name is how the project is shown in the footer, usually its path.
+    name: Box<str>,
+    // offset is the local time zone for showing times, it has to be found before any threads are started.
+    offset: UtcOffset,
+    state: SaveState,
+    // saved is the heads of the document as of the last save, a save is only needed when they move on.
+    saved: Vec<ChangeHash>,
+    // synced is when the document and the backend were last known to hold the same changes.
+    synced: OffsetDateTime,
+    // remote is when changes saved by another process were last merged in.
+    remote: Option<OffsetDateTime>,
+}
+
+impl Store {
+    // open loads the project from the backend, which must already have been initialised.
+    pub fn open(
+        backend: Box<dyn Backend>,
+        name: &str,
+        offset: UtcOffset,
+    ) -> Result<(Store, AutoCommit, Project), Box<dyn std::error::Error>> {
+        let (mut doc, project) = load_project(backend.as_ref())?;
+        if doc.get_heads().is_empty() {
+            return Err(Box::from(format!("{}: project is not initialised", name)));
+        }
+        let store = Store {
+            backend,
+            name: Box::from(name),
+            offset,
+            state: SaveState::Saved,
+            saved: doc.get_heads(),
+            synced: OffsetDateTime::now_utc(),
+            remote: None,
+        };
+        Ok((store, doc, project))
+    }
+
+    // save saves any changes made to the document since the last save. A failure is kept to be shown as well as
+    // returned.
+    pub fn save(&mut self, doc: &mut AutoCommit) -> Result<(), Box<dyn std::error::Error>> {
+        let heads = doc.get_heads();
+        if heads == self.saved {
+            return Ok(());
+        }
+        match save_project(self.backend.as_mut(), doc) {
+            Ok(_) => {
+                self.saved = heads;
+                self.synced = OffsetDateTime::now_utc();
+                self.state = SaveState::Saved;
+                Ok(())
+            }
+            Err(e) => {
+                self.state = SaveState::Failed(Box::from(e.to_string()));
+                Err(e)
+            }
+        }
+    }
+
+    // refresh merges changes saved to the backend by other processes and returns whether there were any.
+    pub fn refresh(&mut self, doc: &mut AutoCommit, project: &mut Project) -> Result<bool, Box<dyn std::error::Error>> {
+        let merged = refresh_project(self.backend.as_ref(), doc, project)?;
+        let now = OffsetDateTime::now_utc();
+        if merged {
+            self.remote = Some(now);
+            // merging is a change to the document that the backend already has
+            if matches!(self.state, SaveState::Saved) {
+                self.saved = doc.get_heads();
+            }
+        }
+        self.synced = now;
+        Ok(merged)
+    }
+
+    // status is a one line summary of the save state, when the project was last synced with the backend and when
+    // changes from elsewhere last arrived.
+    pub fn status(&self) -> String {
+        let time = |t: OffsetDateTime| {
+            t.to_offset(self.offset)
+                .format(format_description!("[hour]:[minute]:[second]"))
+                .unwrap_or_default()
+        };
+        let state = match &self.state {
+            SaveState::Saved => String::from("saved"),
+            SaveState::Failed(e) => format!("not saved: {}", e),
+        };
+        let mut status = format!("{}  {}  synced {}", self.name, state, time(self.synced));
+        if let Some(remote) = self.remote {
+            status.push_str(format!("  remote changes {}", time(remote)).as_str());
+        }
+        status
+    }
+
+    pub fn state(&self) -> &SaveState {
+        &self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use au::item::Item;
+    use au::storage::fs::FsBackend;
+    use au::storage::init_project;
+
+    use super::*;
+
+    #[test]
+    fn test_save_and_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut other = FsBackend::init(dir.path()).unwrap();
+        let (mut other_doc, mut other_project) = init_project(&mut other).unwrap();
+
+        let (mut store, mut doc, mut project) = Store::open(Box::new(FsBackend::open(dir.path()).unwrap()), "p", UtcOffset::UTC).unwrap();
+        assert!(matches!(store.state(), SaveState::Saved));
+        assert!(!store.refresh(&mut doc, &mut project).unwrap());
+        assert!(!store.status().contains("remote"));
+
+        let item = Item {
+            id: Rc::from("a"),
+            ..Default::default()
+        };
+        project.with_item(&item, &mut doc).unwrap();
+        store.save(&mut doc).unwrap();
+        assert!(load_project(&other).unwrap().1.get_item("a").is_some());
+
+        let item = Item {
+            id: Rc::from("b"),
+            ..Default::default()
+        };
+        other_project.with_item(&item, &mut other_doc).unwrap();
+        save_project(&mut other, &mut other_doc).unwrap();
+        assert!(store.refresh(&mut doc, &mut project).unwrap());
+        assert!(project.get_item("b").is_some());
+        assert!(store.status().starts_with("p  saved  synced "));
+        assert!(store.status().contains("remote changes"));
+    }
+}
@@ -1,55 +1,549 @@
-use ratatui::Frame;
-use ratatui::layout::{Constraint, Layout};
+use std::rc::Rc;
+
+use au::item::{Item, Project};
+use au::path::{item_path, resolve};
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
 use ratatui::prelude::Direction;
-use ratatui::style::{Modifier, Style};
-use ratatui::text::Text;
-use ratatui::widgets::{Block, Borders, List, ListDirection, Paragraph};
-use crate::app::{App, Mode};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::block::Title;
+use ratatui::widgets::{Block, Borders, List, ListDirection, ListItem, Paragraph};
+use ratatui::Frame;
+
+use crate::app::{App, EditTarget, Mode, Panes};
+use crate::config::{Action, Keymap, Theme};
+use crate::detail::{content_lines, metadata_lines};
+use crate::store::SaveState;
 
-pub fn ui(f: &mut Frame, app: &App) {
+// the footer shows the first key bound to each of these actions
+const TREE_HELP: &[(Action, &str)] = &[
+    (Action::Open, "open"),
+    (Action::AddBelow, "add"),
+    (Action::AddChild, "add child"),
+    (Action::Edit, "edit"),
+    (Action::MoveUp, "move up"),
+    (Action::MoveDown, "move down"),
+    (Action::Indent, "indent"),
+    (Action::Outdent, "outdent"),
+    (Action::Cut, "cut"),
+    (Action::Paste, "paste"),
+    (Action::ShrinkTree, "narrower"),
+    (Action::GrowTree, "wider"),
+    (Action::Outline, "outline"),
+    (Action::Quit, "quit"),
+];
+const DETAIL_HELP: &[(Action, &str)] = &[
+    (Action::ScrollUp, "up"),
+    (Action::ScrollDown, "down"),
+    (Action::PageUp, "page up"),
+    (Action::PageDown, "page down"),
+    (Action::Top, "top"),
+    (Action::Bottom, "bottom"),
+    (Action::Back, "back"),
+];
+const OUTLINE_HELP: &[(Action, &str)] = &[
+    (Action::Toggle, "expand"),
+    (Action::ExpandAll, "expand all"),
+    (Action::CollapseAll, "collapse all"),
+    (Action::Close, "back"),
+];
+const EDIT_HELP: &[(Action, &str)] = &[(Action::Save, "save"), (Action::Cancel, "cancel")];
+const SEARCH_HELP: &str = "type to search  up/down: choose  enter: go  esc: cancel";
+const GOTO_HELP: &str = "id, id prefix or path  enter: go  esc: cancel";
+// PREVIEW_MIN_WIDTH is the narrowest the body can be with the preview beside the tree, below it the tree is shown alone.
+const PREVIEW_MIN_WIDTH: u16 = 80;
+// each level of the outline is indented further, with a marker for whether an item is expanded
+const OUTLINE_INDENT: &str = "  ";
+const OUTLINE_LEAF: &str = "  ";
+const OUTLINE_EXPANDED: &str = "▾ ";
+const OUTLINE_COLLAPSED: &str = "▸ ";
 
+pub fn ui(f: &mut Frame, app: &mut App) {
+    let theme = &app.config.theme;
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3),
-            Constraint::Min(1),
-            Constraint::Min(1),
-            Constraint::Length(3),
-        ])
+        .constraints([Constraint::Length(3), Constraint::Min(1), Constraint::Length(3)])
         .split(f.size());
 
-    match app.mode {
+    match &mut app.mode {
         Mode::Tree(ctx) => {
+            // the title shows the path down to the items being listed, the inner width excludes the borders
+            let title_width = chunks[0].width.saturating_sub(2) as usize;
+            let path: Vec<String> = ctx.parents.iter().map(|p| p.summary(title_width).to_string()).collect();
+            let title = if path.is_empty() { String::from("Items") } else { path.join(" / ") };
+            f.render_widget(Paragraph::new(Text::styled(title, theme.title)).block(block(theme)), chunks[0]);
 
-            let title = Paragraph::new(Text::styled("Items", Style::default()))
-                .block(Block::default().borders(Borders::ALL).style(Style::default()));
-            f.render_widget(title, chunks[0]);
-            
-            let core = List::new([
-                "one", "two", "three",
-                "one", "two", "three",
-                "one", "two", "three",
-                "one", "two", "three",
-                "one", "two", "three",
-                "one", "two", "three",
-            ])
-                .block(Block::default().borders(Borders::ALL).style(Style::default()))
-                .highlight_style(Style::default().add_modifier(Modifier::ITALIC))
-                .highlight_symbol(">>")
+            let panes = panes(chunks[1], app.split);
+            app.panes = panes;
+
+            // each summary fits in what is left of the row after the borders and the highlight symbol
+            let width = panes.list.width.saturating_sub(2 + theme.highlight_symbol.chars().count() as u16) as usize;
+            let items = ctx.children.iter().map(|c| list_item(theme, app.cut.as_ref(), c, "", "", width));
+            let core = List::new(items)
+                .block(block(theme))
+                .highlight_style(theme.highlight)
+                .highlight_symbol(theme.highlight_symbol.as_ref())
                 .repeat_highlight_symbol(true)
                 .direction(ListDirection::TopToBottom);
+            f.render_stateful_widget(core, panes.list, &mut ctx.list_state);
 
-            f.render_stateful_widget(core, chunks[1]);
+            if let Some(area) = panes.preview {
+                let selected = ctx.selected().map(|s| s.as_ref());
+                preview_selected(f, area, theme, &app.project, selected, &mut app.preview_id, &mut app.preview_scroll);
+            }
+        }
+        Mode::Outline(ctx) => {
+            f.render_widget(Paragraph::new(Text::styled("Outline", theme.title)).block(block(theme)), chunks[0]);
+            let panes = panes(chunks[1], app.split);
+            app.panes = panes;
 
-            let footer = Paragraph::new(Text::styled("Example Footer", Style::default()))
-                .block(Block::default().borders(Borders::ALL).style(Style::default()));
-            f.render_widget(footer, chunks[2]);
+            let width = panes.list.width.saturating_sub(2 + theme.highlight_symbol.chars().count() as u16) as usize;
+            let items = ctx.rows.iter().map(|r| {
+                let marker = match (r.children, r.expanded) {
+                    (0, _) => OUTLINE_LEAF,
+                    (_, true) => OUTLINE_EXPANDED,
+                    (_, false) => OUTLINE_COLLAPSED,
+                };
+                let prefix = format!("{}{}", OUTLINE_INDENT.repeat(r.depth), marker);
+                // the badge counts the children whether they are shown or not
+                let badge = if r.children > 0 {
+                    format!(" ({})", r.children)
+                } else {
+                    String::new()
+                };
+                list_item(theme, app.cut.as_ref(), &r.item, &prefix, &badge, width)
+            });
+            let outline = List::new(items)
+                .block(block(theme).title(format!(" {} shown ", ctx.rows.len())))
+                .highlight_style(theme.highlight)
+                .highlight_symbol(theme.highlight_symbol.as_ref())
+                .repeat_highlight_symbol(true);
+            f.render_stateful_widget(outline, panes.list, &mut ctx.list_state);
 
+            if let Some(area) = panes.preview {
+                let selected = ctx.selected().map(|s| s.as_ref());
+                preview_selected(f, area, theme, &app.project, selected, &mut app.preview_id, &mut app.preview_scroll);
+            }
         }
-        Mode::Detail(_) => {
+        Mode::Detail(ctx) => match app.project.get_item(&ctx.id) {
+            Some(item) => {
+                let title_width = chunks[0].width.saturating_sub(2) as usize;
+                let title = Paragraph::new(Text::styled(item.summary(title_width).to_string(), theme.title)).block(block(theme));
+                f.render_widget(title, chunks[0]);
+
+                let metadata = metadata_lines(&app.project, &item);
+                let body = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints([Constraint::Length(metadata.len() as u16 + 2), Constraint::Min(1)])
+                    .split(chunks[1]);
+                f.render_widget(Paragraph::new(metadata).block(block(theme)), body[0]);
 
+                // clamp the scroll now that the wrapped length is known so that scrolling back up takes effect straight away
+                let lines = content_lines(&item, body[1].width.saturating_sub(2) as usize);
+                ctx.page = body[1].height.saturating_sub(2) as usize;
+                ctx.scroll = ctx.scroll.min(lines.len().saturating_sub(ctx.page));
+                let position = format!(
+                    " {}-{} of {} ",
+                    ctx.scroll + 1,
+                    (ctx.scroll + ctx.page).min(lines.len()),
+                    lines.len()
+                );
+                let content = Paragraph::new(lines.into_iter().skip(ctx.scroll).take(ctx.page).collect::<Vec<_>>())
+                    .block(block(theme).title(position));
+                f.render_widget(content, body[1]);
+            }
+            None => {
+                let gone = Paragraph::new(format!("item {} no longer exists", ctx.id)).block(block(theme));
+                f.render_widget(gone, chunks[1]);
+            }
+        },
+        Mode::Edit(ctx) => {
+            let title = match &ctx.target {
+                EditTarget::Content(id) => match app.project.get_item(id) {
+                    Some(item) => format!("Editing {}", item.summary(chunks[0].width.saturating_sub(10) as usize)),
+                    None => format!("Editing {}", id),
+                },
+                _ => String::from("New item"),
+            };
+            f.render_widget(Paragraph::new(Text::styled(title, theme.title)).block(block(theme)), chunks[0]);
 
+            // the editor does not wrap, so scroll both ways just enough to keep the cursor inside the borders
+            let (row, col) = ctx.editor.cursor();
+            let (height, width) = (
+                chunks[1].height.saturating_sub(2) as usize,
+                chunks[1].width.saturating_sub(2) as usize,
+            );
+            let (top, left) = ((row + 1).saturating_sub(height), (col + 1).saturating_sub(width));
+            let lines: Vec<String> = ctx
+                .editor
+                .lines()
+                .iter()
+                .skip(top)
+                .take(height)
+                .map(|l| l.chars().skip(left).take(width).collect())
+                .collect();
+            f.render_widget(Paragraph::new(lines.join("\n")).block(block(theme)), chunks[1]);
+            f.set_cursor(chunks[1].x + 1 + (col - left) as u16, chunks[1].y + 1 + (row - top) as u16);
         }
+        Mode::Search(ctx) => {
+            prompt(f, chunks[0], theme, "/", &ctx.query);
+            let panes = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(chunks[1]);
+            let width = panes[0].width.saturating_sub(2 + theme.highlight_symbol.chars().count() as u16) as usize;
+            let results = List::new(ctx.results.iter().map(|r| r.summary(width).to_string()))
+                .block(block(theme).title(format!(" {} found ", ctx.results.len())))
+                .highlight_style(theme.highlight)
+                .highlight_symbol(theme.highlight_symbol.as_ref());
+            f.render_stateful_widget(results, panes[0], &mut ctx.list_state);
+            let selected = ctx.list_state.selected().and_then(|i| ctx.results.get(i));
+            preview(f, panes[1], theme, &app.project, selected.map(|i| i.as_ref()), &mut 0);
+        }
+        Mode::Goto(ctx) => {
+            prompt(f, chunks[0], theme, "go to: ", &ctx.input);
+            // show where the input leads as it is typed
+            let input = ctx.input.trim();
+            match resolve(&app.project, ctx.tree.parent_id(), input) {
+                _ if input.is_empty() => preview(f, chunks[1], theme, &app.project, None, &mut 0),
+                Ok(Some(item)) => preview(f, chunks[1], theme, &app.project, Some(&item), &mut 0),
+                Ok(None) => f.render_widget(Paragraph::new("the top level").block(block(theme)), chunks[1]),
+                Err(e) => f.render_widget(
+                    Paragraph::new(Text::styled(e.to_string(), theme.message)).block(block(theme)),
+                    chunks[1],
+                ),
+            }
+        }
+    }
+
+    // a message replaces the help until the next key press
+    let footer = match (&app.message, &app.mode) {
+        (Some(message), _) => Text::styled(message.as_ref(), theme.message),
+        (None, Mode::Tree(_)) => Text::styled(help(&app.config.keymap, TREE_HELP), theme.footer),
+        (None, Mode::Detail(_)) => Text::styled(help(&app.config.keymap, DETAIL_HELP), theme.footer),
+        (None, Mode::Edit(_)) => Text::styled(help(&app.config.keymap, EDIT_HELP), theme.footer),
+        (None, Mode::Search(_)) => Text::styled(SEARCH_HELP, theme.footer),
+        (None, Mode::Goto(_)) => Text::styled(GOTO_HELP, theme.footer),
+        (None, Mode::Outline(_)) => Text::styled(help(&app.config.keymap, OUTLINE_HELP), theme.footer),
+    };
+    // the state of a saved project sits on the border above the help
+    let mut footer_block = block(theme);
+    if let Some(store) = &app.store {
+        let style = match store.state() {
+            SaveState::Saved => theme.footer,
+            SaveState::Failed(_) => theme.message,
+        };
+        footer_block = footer_block.title(Title::from(Span::styled(format!(" {} ", store.status()), style)).alignment(Alignment::Right));
+    }
+    f.render_widget(Paragraph::new(footer).block(footer_block), chunks[2]);
+}
+
+// panes puts the preview beside the list when there is room for both.
+fn panes(area: Rect, split: u16) -> Panes {
+    if area.width < PREVIEW_MIN_WIDTH {
+        return Panes { list: area, preview: None };
     }
+    let split = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(split), Constraint::Min(1)])
+        .split(area);
+    Panes {
+        list: split[0],
+        preview: Some(split[1]),
+    }
+}
+
+// list_item is a row of the tree or outline: the class icon and summary between the prefix and suffix, styled by the
+// class, and fitted to the width.
+fn list_item(theme: &Theme, cut: Option<&Rc<str>>, item: &Item, prefix: &str, suffix: &str, width: usize) -> ListItem<'static> {
+    let class = theme.class_style(item.class.as_deref());
+    let icon = class.and_then(|c| c.icon.as_deref()).map(|i| format!("{} ", i)).unwrap_or_default();
+    let mut style = class.map(|c| c.style).unwrap_or_default();
+    // the item waiting to be pasted stands out until it is
+    if cut == Some(&item.id) {
+        style = style.patch(theme.cut);
+    }
+    let width = width.saturating_sub(prefix.chars().count() + icon.chars().count() + suffix.chars().count());
+    // summaries of content that is not text are not fitted to the width, cut them so that the suffix is still seen
+    let summary: String = item.summary(width).chars().take(width).collect();
+    ListItem::new(format!("{}{}{}{}", prefix, icon, summary, suffix)).style(style)
+}
+
+// preview_selected previews the selected item of the tree or outline. The scroll is for the item preview_id and starts
+// again from the top for a different item.
+fn preview_selected(
+    f: &mut Frame,
+    area: Rect,
+    theme: &Theme,
+    project: &Project,
+    selected: Option<&Item>,
+    preview_id: &mut Option<Rc<str>>,
+    scroll: &mut usize,
+) {
+    if preview_id.as_ref() != selected.map(|s| &s.id) {
+        *preview_id = selected.map(|s| s.id.clone());
+        *scroll = 0;
+    }
+    preview(f, area, theme, project, selected, scroll);
+}
+
+// prompt shows typed text in the title area with the cursor after it.
+fn prompt(f: &mut Frame, area: Rect, theme: &Theme, label: &str, input: &str) {
+    let text = format!("{}{}", label, input);
+    // keep the end of long input in view
+    let width = area.width.saturating_sub(3) as usize;
+    let shown: String = text.chars().skip(text.chars().count().saturating_sub(width)).collect();
+    let cursor = shown.chars().count() as u16;
+    f.render_widget(Paragraph::new(Text::styled(shown, theme.title)).block(block(theme)), area);
+    f.set_cursor(area.x + 1 + cursor, area.y + 1);
+}
+
+// preview shows an item's path and content from the scroll line on, or an empty pane when there is no item. The scroll
+// is clamped to the content.
+fn preview(f: &mut Frame, area: Rect, theme: &Theme, project: &Project, item: Option<&Item>, scroll: &mut usize) {
+    let Some(item) = item else {
+        f.render_widget(block(theme), area);
+        return;
+    };
+    let path = item_path(project, &item.id).unwrap_or_else(|_| item.id.to_string());
+    let lines = content_lines(item, area.width.saturating_sub(2) as usize);
+    let page = area.height.saturating_sub(2) as usize;
+    *scroll = (*scroll).min(lines.len().saturating_sub(page));
+    let lines: Vec<Line> = lines.into_iter().skip(*scroll).take(page).collect();
+    f.render_widget(Paragraph::new(lines).block(block(theme).title(format!(" {} ", path))), area);
+}
+
+fn block(theme: &Theme) -> Block<'static> {
+    Block::default().borders(Borders::ALL).border_style(theme.border)
+}
 
+// help lists the keys for the actions, leaving out actions that have been unbound.
+fn help(keymap: &Keymap, actions: &[(Action, &str)]) -> String {
+    let keys: Vec<String> = actions
+        .iter()
+        .filter_map(|(action, label)| keymap.chord(*action).map(|chord| format!("{}: {}", chord, label)))
+        .collect();
+    keys.join("  ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use au::item::init_project;
+    use automerge::AutoCommit;
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use super::*;
+
+    // mock_app has the same mock items on every run so that what is drawn can be compared exactly.
+    fn mock_app() -> App {
+        let mut doc = AutoCommit::new();
+        let mut project = init_project(&mut doc).unwrap();
+        for item in aumock::mock_items(StdRng::seed_from_u64(42), 30) {
+            project.with_item(&item, &mut doc).unwrap();
+        }
+        App::with_project(doc, project)
+    }
+
+    // replay passes the keys to the app as the event loop would.
+    fn replay(app: &mut App, keys: &[KeyEvent]) {
+        for key in keys {
+            assert!(!app.on_event(Event::Key(*key), Instant::now()), "the app quit");
+        }
+    }
+
+    fn keys(codes: &[KeyCode]) -> Vec<KeyEvent> {
+        codes.iter().map(|c| KeyEvent::new(*c, KeyModifiers::NONE)).collect()
+    }
+
+    // draw renders the app on a terminal of the size and returns the text of each row, ignoring styles.
+    fn draw(app: &mut App, width: u16, height: u16) -> Vec<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal.draw(|f| ui(f, app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        (0..height)
+            .map(|y| {
+                (0..width)
+                    .map(|x| buffer.get(x, y).symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    // assert_drawn compares what was drawn row by row, so that a failure shows which rows differ.
+    fn assert_drawn(drawn: Vec<String>, expected: &[&str]) {
+        assert_eq!(drawn, expected.iter().map(|r| r.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_draw_tree() {
+        let mut app = mock_app();
+        assert_drawn(
+            draw(&mut app, 100, 12),
+            &[
+                "┌──────────────────────────────────────────────────────────────────────────────────────────────────┐",
+                "│Items                                                                                             │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+                "┌────────────────────────────────────────────────┐┌ /Fieri, quanta ad augendas, cum conscientia fac┐",
+                "│>>Fieri, quanta ad augendas, cum conscientia ...││Fieri, quanta ad augendas, cum conscientia      │",
+                "│  (binary application/x-octet-stream file of 0 b││factorum, tum poena legum odioque civium? Et    │",
+                "│  (binary application/x-octet-stream file of 0 b││tamen ego a.                                    │",
+                "│  Ut Autem a Facillimis Ordiamur Prima Veniat   ││                                                │",
+                "└────────────────────────────────────────────────┘└────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────────────────────────────────────────────┐",
+                "│enter: open  shift-enter: add  alt-enter: add child  e: edit  shift-up: move up  shift-down: move │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+            ],
+        );
+
+        // on a narrow terminal the tree has the whole width
+        replay(&mut app, &keys(&[KeyCode::Down, KeyCode::Down, KeyCode::Down]));
+        assert_drawn(
+            draw(&mut app, 60, 10),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Items                                                     │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│  (binary application/x-octet-stream file of 0 bytes)     │",
+                "│>>Ut Autem a Facillimis Ordiamur Prima Veniat             │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│enter: open  shift-enter: add  alt-enter: add child  e: ed│",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_draw_detail() {
+        let mut app = mock_app();
+        replay(&mut app, &keys(&[KeyCode::Down, KeyCode::Down, KeyCode::Down, KeyCode::Enter]));
+        assert_drawn(
+            draw(&mut app, 60, 20),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Ut Autem a Facillimis Ordiamur Prima Veniat               │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│id            45PNBGMN127SEK                              │",
+                "│class         Aut.                                        │",
+                "│content type  text/plain (298 bytes)                      │",
+                "│rank          5882606602895383545                         │",
+                "│at            1970-01-01T00:00:00Z                        │",
+                "│parent        /                                           │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ 1-4 of 7 ────────────────────────────────────────────────┐",
+                "│Ut Autem a Facillimis Ordiamur Prima Veniat               │",
+                "│                                                          │",
+                "│Potest? Quodsi vita doloribus referta maxime fugienda est,│",
+                "│summum profecto malum est vivere cum dolore, cui          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│up: up  down: down  pgup: page up  pgdn: page down  home: │",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+
+        replay(&mut app, &keys(&[KeyCode::PageDown]));
+        assert_drawn(
+            draw(&mut app, 60, 20),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Ut Autem a Facillimis Ordiamur Prima Veniat               │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│id            45PNBGMN127SEK                              │",
+                "│class         Aut.                                        │",
+                "│content type  text/plain (298 bytes)                      │",
+                "│rank          5882606602895383545                         │",
+                "│at            1970-01-01T00:00:00Z                        │",
+                "│parent        /                                           │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ 4-7 of 7 ────────────────────────────────────────────────┐",
+                "│summum profecto malum est vivere cum dolore, cui          │",
+                "│sententiae consentaneum est ultimum esse bonorum eum      │",
+                "│voluptate vivere. Nec enim satis est iudicare quid        │",
+                "│faciendum non faciendumve sit, sed stare.                 │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│up: up  down: down  pgup: page up  pgdn: page down  home: │",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_draw_edit() {
+        let mut app = mock_app();
+        let mut typed = keys(&[KeyCode::Char('a')]);
+        typed.extend(keys(&"Hello".chars().map(KeyCode::Char).collect::<Vec<_>>()));
+        typed.extend(keys(&[KeyCode::Enter, KeyCode::Char('w'), KeyCode::Char('o')]));
+        replay(&mut app, &typed);
+        assert_drawn(
+            draw(&mut app, 60, 10),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│New item                                                  │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Hello                                                     │",
+                "│wo                                                        │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│ctrl-s: save  esc: cancel                                 │",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+
+        // saving adds the item below the one that was selected and selects it
+        replay(&mut app, &[KeyEvent::new(KeyCode::Char('s'), KeyModifiers::CONTROL)]);
+        assert_drawn(
+            draw(&mut app, 60, 10),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Items                                                     │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│  Fieri, quanta ad augendas, cum conscientia factorum, ...│",
+                "│>>Hello                                                   │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│enter: open  shift-enter: add  alt-enter: add child  e: ed│",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_draw_outline() {
+        let mut app = mock_app();
+        // everything expanded, with each item that has children showing how many
+        replay(&mut app, &keys(&[KeyCode::Char('o'), KeyCode::Char('E')]));
+        assert_drawn(
+            draw(&mut app, 60, 14),
+            &[
+                "┌──────────────────────────────────────────────────────────┐",
+                "│Outline                                                   │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ 30 shown ────────────────────────────────────────────────┐",
+                "│>>  Fieri, quanta ad augendas, cum conscientia factorum...│",
+                "│  ▾ (binary application/x-octet-stream file of 0 bytes (1)│",
+                "│      (binary application/x-octet-stream file of 0 bytes) │",
+                "│    (binary application/x-octet-stream file of 0 bytes)   │",
+                "│    Ut Autem a Facillimis Ordiamur Prima Veniat           │",
+                "│    Philosophis Compluribus Permulta Dicantur cur nec V...│",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌──────────────────────────────────────────────────────────┐",
+                "│space: expand  E: expand all  C: collapse all  enter: back│",
+                "└──────────────────────────────────────────────────────────┘",
+            ],
+        );
+    }
 }
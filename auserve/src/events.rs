@@ -0,0 +1,495 @@
+/*
+
+Events streams item changes to WebSocket clients so that they can stay live without polling, and carries automerge sync
+messages for clients that keep their own copy of the document. Text messages are json objects with a type:
+
+    client: {"type": "subscribe"}      start receiving item events, answered with {"type": "subscribed", "etag": ...}
+    client: {"type": "unsubscribe"}    stop receiving item events
+    server: {"type": "added", "id": ..., "item": {...}, "etag": ...}
+    server: {"type": "updated", "id": ..., "item": {...}, "etag": ...}
+    server: {"type": "moved", "id": ..., "item": {...}, "etag": ...}
+    server: {"type": "deleted", "id": ..., "etag": ...}
+    server: {"type": "error", "error": ...}
+
+The item is the same record as GET /items/{id} and the etag is the one the http api gives for the state after the
+change. An item that was both moved and modified gets both events. Changes that happen close together may be reported
+together, so clients should rely on the final state rather than on seeing every intermediate one.
+
+Binary messages are automerge sync messages. A client that sends one is answered with sync messages until both sides
+have the same changes, and from then on is sent new changes as they happen. Changes received this way are saved like
+any other write and produce item events for the subscribers.
+
+Sockets are non-blocking and are polled from the same thread as the http api, so the project is never shared. That
+includes the WebSocket handshake, which is carried on over as many polls as it takes so that a slow client can't hold up
+the others, and dropped if it is not done within HANDSHAKE_TIMEOUT.
+
+ */
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+use automerge::sync;
+use serde::{Deserialize, Serialize};
+use tungstenite::handshake::server::{NoCallback, ServerHandshake};
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::{Message, WebSocket};
+
+use au::history::{diff_projects, is_modified, is_moved, ItemDiff};
+use au::item::{Item, Project};
+use au::record::ItemRecord;
+
+use crate::Api;
+
+// HANDSHAKE_TIMEOUT bounds how long a new client may take to finish the WebSocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Handshake is how far a WebSocket handshake got in one go.
+type Handshake = Result<WebSocket<TcpStream>, HandshakeError<ServerHandshake<TcpStream, NoCallback>>>;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum ClientMessage {
+    Subscribe,
+    Unsubscribe,
+}
+
+// Event is a single item change sent to subscribers.
+#[derive(Serialize)]
+struct Event<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    item: Option<ItemRecord>,
+    etag: &'a str,
+}
+
+// Pending is a client that has not finished the WebSocket handshake yet.
+struct Pending {
+    handshake: MidHandshake<ServerHandshake<TcpStream, NoCallback>>,
+    started: Instant,
+}
+
+struct Client {
+    socket: WebSocket<TcpStream>,
+    subscribed: bool,
+    // sync is the automerge sync state, present once the client has sent a sync message
+    sync: Option<sync::State>,
+    closed: bool,
+}
+
+// Events accepts WebSocket clients and keeps them up to date with the project of an Api.
+pub struct Events {
+    listener: TcpListener,
+    pending: Vec<Pending>,
+    clients: Vec<Client>,
+    // seen is the project as of the last events sent, with its etag
+    seen: Project,
+    seen_etag: String,
+}
+
+impl Events {
+    pub fn bind(addr: SocketAddr, api: &mut Api) -> Result<Events, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Events {
+            listener,
+            pending: Vec::new(),
+            clients: Vec::new(),
+            seen: api.project().clone(),
+            seen_etag: api.etag(),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    // poll accepts new clients and handles whatever the clients sent, then sends them events and sync messages for
+    // anything that changed since the last poll. It never blocks, a client that fails is dropped.
+    pub fn poll(&mut self, api: &mut Api) -> Result<(), Box<dyn std::error::Error>> {
+        // changes made through the http api go out before any subscription is acknowledged with the current etag
+        self.send_events(api)?;
+        self.accept();
+        for client in self.clients.iter_mut() {
+            client.receive(api);
+        }
+        self.send_events(api)?;
+        for client in self.clients.iter_mut() {
+            client.send_sync(api);
+        }
+        self.clients.retain(|c| !c.closed);
+        Ok(())
+    }
+
+    // accept takes new connections and carries on the handshakes of earlier ones as far as they go without blocking.
+    fn accept(&mut self) {
+        let now = Instant::now();
+        let mut handshakes: Vec<(Handshake, Instant)> = self.pending.drain(..).map(|p| (p.handshake.handshake(), p.started)).collect();
+        loop {
+            match self.listener.accept() {
+                // accepted streams don't inherit non-blocking from the listener everywhere
+                Ok((stream, _)) => {
+                    if stream.set_nonblocking(true).is_ok() {
+                        handshakes.push((tungstenite::accept(stream), now));
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
+        }
+        for (handshake, started) in handshakes {
+            match handshake {
+                Ok(socket) => self.clients.push(Client {
+                    socket,
+                    subscribed: false,
+                    sync: None,
+                    closed: false,
+                }),
+                Err(HandshakeError::Interrupted(handshake)) if now.duration_since(started) < HANDSHAKE_TIMEOUT => {
+                    self.pending.push(Pending { handshake, started })
+                }
+                // a client that fails the handshake or takes too long is simply not added
+                Err(_) => (),
+            }
+        }
+    }
+
+    fn send_events(&mut self, api: &mut Api) -> Result<(), Box<dyn std::error::Error>> {
+        let etag = api.etag();
+        if etag == self.seen_etag {
+            return Ok(());
+        }
+        let mut messages: Vec<String> = Vec::new();
+        for diff in diff_projects(&self.seen, api.project()) {
+            match &diff {
+                ItemDiff::Added(item) => messages.push(event("added", item, true, etag.as_str())?),
+                ItemDiff::Deleted(item) => messages.push(event("deleted", item, false, etag.as_str())?),
+                ItemDiff::Updated(before, after) => {
+                    if is_moved(before, after) {
+                        messages.push(event("moved", after, true, etag.as_str())?);
+                    }
+                    if is_modified(before, after) {
+                        messages.push(event("updated", after, true, etag.as_str())?);
+                    }
+                }
+            }
+        }
+        for client in self.clients.iter_mut().filter(|c| c.subscribed) {
+            for message in messages.iter() {
+                client.send(Message::text(message.as_str()));
+            }
+            client.flush();
+        }
+        self.seen = api.project().clone();
+        self.seen_etag = etag;
+        Ok(())
+    }
+}
+
+impl Client {
+    fn receive(&mut self, api: &mut Api) {
+        while !self.closed {
+            match self.socket.read() {
+                Ok(Message::Text(text)) => self.on_text(text.as_str(), api),
+                Ok(Message::Binary(data)) => self.on_sync(data.as_ref(), api),
+                // pings and closes are answered by tungstenite itself on the next write or flush
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        self.flush();
+    }
+
+    fn on_text(&mut self, text: &str, api: &mut Api) {
+        match serde_json::from_str(text) {
+            Ok(ClientMessage::Subscribe) => {
+                self.subscribed = true;
+                let reply = serde_json::json!({ "type": "subscribed", "etag": api.etag() });
+                self.send(Message::text(reply.to_string()));
+            }
+            Ok(ClientMessage::Unsubscribe) => self.subscribed = false,
+            Err(e) => self.send_error(e.to_string().as_str()),
+        }
+    }
+
+    fn on_sync(&mut self, data: &[u8], api: &mut Api) {
+        let state = self.sync.get_or_insert_with(sync::State::new);
+        // a peer whose changes are rejected would only send them again, so it is closed after the error
+        if let Err(e) = api.receive_sync(state, data) {
+            self.send_error(e.to_string().as_str());
+            let _ = self.socket.close(None);
+            self.flush();
+            self.closed = true;
+        }
+    }
+
+    fn send_sync(&mut self, api: &mut Api) {
+        if let Some(message) = self.sync.as_mut().and_then(|s| api.generate_sync(s)) {
+            self.send(Message::binary(message));
+            self.flush();
+        }
+    }
+
+    fn send_error(&mut self, error: &str) {
+        let reply = serde_json::json!({ "type": "error", "error": error });
+        self.send(Message::text(reply.to_string()));
+    }
+
+    // send queues the message, a socket that can't take it right now gets it on a later flush.
+    fn send(&mut self, message: Message) {
+        match self.socket.write(message) {
+            Ok(()) => (),
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(_) => self.closed = true,
+        }
+    }
+
+    fn flush(&mut self) {
+        match self.socket.flush() {
+            Ok(()) => (),
+            Err(tungstenite::Error::Io(e)) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(_) => self.closed = true,
+        }
+    }
+}
+
+fn event(kind: &'static str, item: &Item, with_item: bool, etag: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let event = Event {
+        kind,
+        id: item.id.as_ref(),
+        item: if with_item { Some(ItemRecord::show(item)?) } else { None },
+        etag,
+    };
+    Ok(serde_json::to_string(&event)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::rc::Rc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use automerge::sync::{self, SyncDoc};
+    use automerge::transaction::Transactable;
+    use automerge::AutoCommit;
+    use serde_json::Value;
+    use tungstenite::stream::MaybeTlsStream;
+    use tungstenite::{Message, WebSocket};
+
+    use au::item::{decode_project, Item};
+    use au::storage::init_project;
+    use au::storage::memory::MemoryBackend;
+
+    use crate::events::HANDSHAKE_TIMEOUT;
+    use crate::server::Server;
+    use crate::Api;
+
+    type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    const READ_TIMEOUT: Duration = Duration::from_millis(300);
+    const WAIT_READS: usize = 20;
+
+    // serve runs a server over a fresh project on its own thread and returns its http and events addresses.
+    fn serve() -> (SocketAddr, SocketAddr) {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let mut backend = MemoryBackend::default();
+            init_project(&mut backend).unwrap();
+            let local = "127.0.0.1:0".parse().unwrap();
+            let mut server = Server::bind(Api::open(Box::new(backend)).unwrap(), local, local).unwrap();
+            tx.send((server.http_addr().unwrap(), server.events_addr().unwrap())).unwrap();
+            server.run().unwrap();
+        });
+        rx.recv().unwrap()
+    }
+
+    fn connect(addr: SocketAddr) -> Socket {
+        let (socket, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+        }
+        socket
+    }
+
+    fn http(addr: SocketAddr, method: &str, path: &str, body: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    // next_event reads text messages until one has the given type.
+    fn next_event(socket: &mut Socket, kind: &str) -> Value {
+        for _ in 0..WAIT_READS {
+            match socket.read() {
+                Ok(Message::Text(text)) => {
+                    let value: Value = serde_json::from_str(text.as_str()).unwrap();
+                    if value["type"] == kind {
+                        return value;
+                    }
+                }
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(e) => panic!("{}", e),
+            }
+        }
+        panic!("no {} event", kind);
+    }
+
+    // sync exchanges sync messages with the server until it has nothing more to send.
+    fn sync(socket: &mut Socket, doc: &mut AutoCommit, state: &mut sync::State) {
+        loop {
+            if let Some(message) = doc.sync().generate_sync_message(state) {
+                socket.send(Message::binary(message.encode())).unwrap();
+            }
+            match socket.read() {
+                Ok(Message::Binary(data)) => {
+                    let message = sync::Message::decode(data.as_ref()).unwrap();
+                    doc.sync().receive_sync_message(state, message).unwrap();
+                }
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return,
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_events_and_sync() {
+        let (http_addr, events_addr) = serve();
+        let mut subscriber = connect(events_addr);
+        subscriber.send(Message::text(r#"{"type": "subscribe"}"#)).unwrap();
+        let subscribed = next_event(&mut subscriber, "subscribed");
+
+        // a change over http is streamed to the subscriber with the etag the http api gives
+        let response = http(http_addr, "POST", "/items", r#"{"content": "Inbox"}"#);
+        assert!(response.starts_with("HTTP/1.1 201"), "{}", response);
+        let added = next_event(&mut subscriber, "added");
+        assert_eq!(added["item"]["content"], "Inbox");
+        assert_ne!(added["etag"], subscribed["etag"]);
+        assert!(response.contains(format!("ETag: {}", added["etag"].as_str().unwrap()).as_str()));
+        let inbox = added["id"].as_str().unwrap().to_string();
+
+        // a sync client gets the whole project and its own changes reach the subscriber and the http api
+        let mut peer = connect(events_addr);
+        let mut doc = AutoCommit::new();
+        let mut state = sync::State::new();
+        sync(&mut peer, &mut doc, &mut state);
+        let mut project = decode_project(doc.document()).unwrap();
+        assert!(project.get_item(inbox.as_str()).is_some());
+        let item = Item {
+            id: Rc::from("SYNCED"),
+            parent: Some(Rc::from(inbox.as_str())),
+            content: Rc::from("from a peer".as_bytes()),
+            ..Default::default()
+        };
+        project.with_item(&item, &mut doc).unwrap();
+        doc.commit();
+        sync(&mut peer, &mut doc, &mut state);
+
+        let added = next_event(&mut subscriber, "added");
+        assert_eq!(added["id"], "SYNCED");
+        assert_eq!(added["item"]["parent"].as_str(), Some(inbox.as_str()));
+        assert!(http(http_addr, "GET", "/items/SYNCED", "").starts_with("HTTP/1.1 200"));
+
+        http(http_addr, "DELETE", "/items/SYNCED", "");
+        assert_eq!(next_event(&mut subscriber, "deleted")["id"], "SYNCED");
+
+        subscriber.send(Message::text(r#"{"type": "nonsense"}"#)).unwrap();
+        assert!(next_event(&mut subscriber, "error")["error"].is_string());
+    }
+
+    // rejected sends the peer's sync messages until the server answers with an error, then expects it to close.
+    fn rejected(socket: &mut Socket, doc: &mut AutoCommit, state: &mut sync::State) {
+        if let Some(message) = doc.sync().generate_sync_message(state) {
+            socket.send(Message::binary(message.encode())).unwrap();
+        }
+        let mut error = false;
+        for _ in 0..WAIT_READS {
+            match socket.read() {
+                Ok(Message::Binary(data)) => {
+                    doc.sync()
+                        .receive_sync_message(state, sync::Message::decode(data.as_ref()).unwrap())
+                        .unwrap();
+                    if let Some(message) = doc.sync().generate_sync_message(state) {
+                        socket.send(Message::binary(message.encode())).unwrap();
+                    }
+                }
+                Ok(Message::Text(text)) => error |= serde_json::from_str::<Value>(text.as_str()).unwrap()["type"] == "error",
+                Ok(_) => (),
+                Err(tungstenite::Error::Io(e)) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(_) => {
+                    assert!(error);
+                    return;
+                }
+            }
+        }
+        panic!("not closed");
+    }
+
+    #[test]
+    fn test_rejected_sync() {
+        let (http_addr, events_addr) = serve();
+        let mut peer = connect(events_addr);
+        let mut doc = AutoCommit::new();
+        let mut state = sync::State::new();
+        sync(&mut peer, &mut doc, &mut state);
+
+        // a peer with a change that breaks the project gets an error and is closed, and again when it reconnects
+        doc.put(automerge::ROOT, "items", "broken").unwrap();
+        doc.commit();
+        rejected(&mut peer, &mut doc, &mut state);
+        let mut peer = connect(events_addr);
+        let mut state = sync::State::new();
+        rejected(&mut peer, &mut doc, &mut state);
+        assert!(http(http_addr, "GET", "/items", "").starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_stalled_handshake() {
+        let (http_addr, events_addr) = serve();
+        // a client that connects and never sends its handshake holds up neither http nor other clients
+        let _stalled = TcpStream::connect(events_addr).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        assert!(http(http_addr, "GET", "/items", "").starts_with("HTTP/1.1 200"));
+        let mut subscriber = connect(events_addr);
+        subscriber.send(Message::text(r#"{"type": "subscribe"}"#)).unwrap();
+        next_event(&mut subscriber, "subscribed");
+        assert!(started.elapsed() < HANDSHAKE_TIMEOUT / 2);
+    }
+
+    #[test]
+    fn test_stalled_body() {
+        let (http_addr, _) = serve();
+        // a request whose body never arrives holds up neither the project nor other requests
+        let mut stalled = TcpStream::connect(http_addr).unwrap();
+        write!(
+            stalled,
+            "POST /items HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100000\r\n\r\n{{"
+        )
+        .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        assert!(http(http_addr, "POST", "/items", r#"{"content": "A"}"#).starts_with("HTTP/1.1 201"));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
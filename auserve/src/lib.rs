@@ -0,0 +1,597 @@
+/*
+
+auserve exposes a project over a small HTTP/JSON API so that tools can read and write items without linking against
+au:
+
+    GET    /items                  the top level items
+    GET    /items/{id}             the item including its content
+    GET    /items/{id}/children    the children of the item
+    GET    /items/{id}/content     the raw content, served with the content type of the item
+    POST   /items                  create an item, answered with 201 and the Location of the new item
+    PATCH  /items/{id}             update the class, content_type, content, parent or rank of the item
+    POST   /items/{id}/move        move the item to another parent or rank
+    DELETE /items/{id}             delete an item that has no children
+
+Items are written with the same fields as the json output of the CLI:
+
+    id, parent, rank, class, content_type, at, content, content_encoding
+
+Request bodies take the fields of the matching au::batch operation, so content is utf-8 text unless content_encoding
+is "base64", fields that are left out are unchanged and a null class or parent clears it. Every write is a single
+change going through the same Project mutators as any other edit. The {id} and parent can be anything the command line
+takes for an item, such as a unique id prefix or a path, resolved with au::path::resolve. A path in {id} is sent with
+its slashes escaped as %2F.
+
+Every response carries an ETag made from the heads of the project document. A write that sends it back as If-Match
+only applies if the project has not changed since, otherwise it is answered with 412. A read that sends it back as
+If-None-Match is answered with 304 if nothing changed. Errors are {"error": "..."} with 404 for missing items, 409 for
+cycles and items that still have children, and 400 for invalid requests.
+
+The project is refreshed from the backend before every request so that changes made by other processes are seen, and
+writes are saved to the backend before they are answered. Live updates are streamed to WebSocket clients by the
+events module, and server runs both from a single thread.
+
+ */
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use automerge::sync::{self, SyncDoc};
+use automerge::{AutoCommit, ChangeHash};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use au::batch::{apply_edit, operation, resolve_parent, update_operations, Operation};
+use au::error::AuError;
+use au::id::IdGen;
+use au::item::{decode_project, Item, Project};
+use au::path::resolve_item;
+use au::record::ItemRecord;
+use au::storage::{load_project, refresh_project, save_project, Backend};
+
+pub mod events;
+pub mod server;
+
+const ROUTE_ITEMS: &str = "items";
+const ROUTE_CHILDREN: &str = "children";
+const ROUTE_CONTENT: &str = "content";
+const ROUTE_MOVE: &str = "move";
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+// Request is the part of an http request that the api looks at.
+pub struct Request<'a> {
+    pub method: &'a str,
+    // url is the path with an optional query string, which is ignored
+    pub url: &'a str,
+    pub if_match: Option<&'a str>,
+    pub if_none_match: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+// Response is the http response to write back. The headers hold everything apart from the content length.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    fn json(status: u16, value: &impl Serialize) -> Result<Response, Box<dyn std::error::Error>> {
+        let mut response = Response::new(status).with_header("Content-Type", CONTENT_TYPE_JSON);
+        response.body = serde_json::to_vec(value)?;
+        Ok(response)
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        let mut response = Response::new(status).with_header("Content-Type", CONTENT_TYPE_JSON);
+        response.body = serde_json::json!({ "error": message }).to_string().into_bytes();
+        response
+    }
+
+    fn with_header(mut self, name: &'static str, value: &str) -> Response {
+        self.headers.push((name, value.to_string()));
+        self
+    }
+
+    // header returns the value of the named header, names are compared case insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+// Api serves requests against a project held in a backend.
+pub struct Api {
+    backend: Box<dyn Backend>,
+    doc: AutoCommit,
+    project: Project,
+    id_gen: IdGen,
+    // rejected are the heads of changes that were dropped because they broke the project
+    rejected: HashSet<ChangeHash>,
+}
+
+impl Api {
+    // open loads the project from the backend, which must already have been initialised.
+    pub fn open(backend: Box<dyn Backend>) -> Result<Api, Box<dyn std::error::Error>> {
+        let (mut doc, project) = load_project(backend.as_ref())?;
+        if doc.get_heads().is_empty() {
+            return Err(Box::from("project is not initialised"));
+        }
+        Ok(Api {
+            backend,
+            doc,
+            project,
+            id_gen: IdGen::default(),
+            rejected: HashSet::new(),
+        })
+    }
+
+    // project is the project as of the last request, refresh or sync.
+    pub fn project(&self) -> &Project {
+        &self.project
+    }
+
+    // refresh merges any changes other processes saved to the backend and returns whether there were any.
+    pub fn refresh(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        refresh_project(self.backend.as_ref(), &mut self.doc, &mut self.project)
+    }
+
+    // receive_sync applies an automerge sync message from a peer and saves any changes it carried. Changes that don't
+    // decode as a valid project are dropped again so that a misbehaving peer can't break the project, and the sync
+    // state starts over since it counts them as shared. Their heads are remembered so that a peer that still has them
+    // is turned away without applying them again.
+    pub fn receive_sync(&mut self, state: &mut sync::State, message: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let message = sync::Message::decode(message)?;
+        if let Some(head) = message.heads.iter().find(|h| self.rejected.contains(h)) {
+            return Err(Box::new(AuError::InvalidOperation(
+                Box::from(head.to_string()),
+                Box::from("change was rejected"),
+            )));
+        }
+        let heads = self.doc.get_heads();
+        self.doc.sync().receive_sync_message(state, message)?;
+        if self.doc.get_heads() == heads {
+            return Ok(());
+        }
+        match decode_project(self.doc.document()) {
+            Ok(project) => self.project = project,
+            Err(e) => {
+                self.rejected
+                    .extend(self.doc.get_heads().into_iter().filter(|h| !heads.contains(h)));
+                let mut restored = self.doc.fork_at(&heads)?;
+                restored.set_actor(self.doc.get_actor().clone());
+                self.doc = restored;
+                *state = sync::State::new();
+                return Err(e);
+            }
+        }
+        save_project(self.backend.as_mut(), &mut self.doc)?;
+        Ok(())
+    }
+
+    // generate_sync returns the next sync message for a peer, or None if there is nothing to send until it answers.
+    pub fn generate_sync(&mut self, state: &mut sync::State) -> Option<Vec<u8>> {
+        self.doc.sync().generate_sync_message(state).map(|m| m.encode())
+    }
+
+    // handle answers a single request, any failure is turned into an error response.
+    pub fn handle(&mut self, request: &Request) -> Response {
+        let response = self
+            .refresh()
+            .and_then(|_| self.route(request))
+            .unwrap_or_else(|e| Response::error(status_of(e.as_ref()), e.to_string().as_str()));
+        let etag = self.etag();
+        response.with_header("ETag", etag.as_str())
+    }
+
+    fn route(&mut self, request: &Request) -> Result<Response, Box<dyn std::error::Error>> {
+        let path = request.url.split('?').next().unwrap_or_default();
+        let segments = path
+            .trim_matches('/')
+            .split('/')
+            .map(percent_decode)
+            .collect::<Result<Vec<String>, _>>()?;
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        let etag = self.etag();
+        if request.method == "GET" {
+            if request.if_none_match.is_some_and(|m| etag_matches(m, etag.as_str())) {
+                return Ok(Response::new(304));
+            }
+        } else if request.if_match.is_some_and(|m| !etag_matches(m, etag.as_str())) {
+            return Ok(Response::error(412, "the project has changed"));
+        }
+        match (request.method, segments.as_slice()) {
+            ("GET", [ROUTE_ITEMS]) => self.list(None),
+            ("GET", [ROUTE_ITEMS, id]) => self.get(id),
+            ("GET", [ROUTE_ITEMS, id, ROUTE_CHILDREN]) => self.list(Some(id)),
+            ("GET", [ROUTE_ITEMS, id, ROUTE_CONTENT]) => self.content(id),
+            ("POST", [ROUTE_ITEMS]) => self.create(request.body),
+            ("PATCH", [ROUTE_ITEMS, id]) => self.update(id, request.body),
+            ("POST", [ROUTE_ITEMS, id, ROUTE_MOVE]) => self.move_item(id, request.body),
+            ("DELETE", [ROUTE_ITEMS, id]) => self.delete(id),
+            (_, [ROUTE_ITEMS] | [ROUTE_ITEMS, _] | [ROUTE_ITEMS, _, ROUTE_CHILDREN | ROUTE_CONTENT | ROUTE_MOVE]) => {
+                Ok(Response::error(405, "method not allowed"))
+            }
+            _ => Ok(Response::error(404, "no such route")),
+        }
+    }
+
+    fn list(&self, parent: Option<&str>) -> Result<Response, Box<dyn std::error::Error>> {
+        let parent = parent.map(|p| self.item(p)).transpose()?;
+        let records = self
+            .project
+            .list_children(parent.as_ref().map(|p| p.id.as_ref()))
+            .iter()
+            .map(|i| ItemRecord::new(i))
+            .collect::<Result<Vec<ItemRecord>, _>>()?;
+        Response::json(200, &records)
+    }
+
+    fn get(&self, id: &str) -> Result<Response, Box<dyn std::error::Error>> {
+        Response::json(200, &ItemRecord::show(self.item(id)?.as_ref())?)
+    }
+
+    fn content(&self, id: &str) -> Result<Response, Box<dyn std::error::Error>> {
+        let item = self.item(id)?;
+        let mut response = Response::new(200).with_header("Content-Type", item.content_type.as_ref());
+        response.body = item.content.to_vec();
+        Ok(response)
+    }
+
+    fn create(&mut self, body: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
+        let id = self.id_gen.gen(rand::thread_rng());
+        let mut fields = parse_fields(body)?;
+        resolve_parent(&self.project, &mut fields)?;
+        let op = operation("add", None, fields)?;
+        self.apply(&[op], format!("add {}", id).as_str(), || id.clone())?;
+        let response = Response::json(201, &ItemRecord::show(self.item(id.as_str())?.as_ref())?)?;
+        Ok(response.with_header("Location", format!("/{}/{}", ROUTE_ITEMS, id).as_str()))
+    }
+
+    fn update(&mut self, reference: &str, body: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
+        let id = self.item(reference)?.id.clone();
+        let mut fields = parse_fields(body)?;
+        resolve_parent(&self.project, &mut fields)?;
+        let ops = update_operations(&id, fields)?;
+        self.apply(&ops, format!("edit {}", id).as_str(), String::new)?;
+        self.get(&id)
+    }
+
+    fn move_item(&mut self, reference: &str, body: &[u8]) -> Result<Response, Box<dyn std::error::Error>> {
+        let id = self.item(reference)?.id.clone();
+        let mut fields = parse_fields(body)?;
+        resolve_parent(&self.project, &mut fields)?;
+        let message = match fields.get("parent") {
+            Some(Value::String(p)) => format!("move {} under {}", id, p),
+            Some(Value::Null) => format!("move {} to the top level", id),
+            _ => format!("move {}", id),
+        };
+        let op = operation("move", Some(&id), fields)?;
+        self.apply(&[op], message.as_str(), String::new)?;
+        self.get(&id)
+    }
+
+    fn delete(&mut self, reference: &str) -> Result<Response, Box<dyn std::error::Error>> {
+        let id = self.item(reference)?.id.clone();
+        let op = operation("delete", Some(&id), Map::new())?;
+        self.apply(&[op], format!("remove {}", id).as_str(), String::new)?;
+        Ok(Response::new(204))
+    }
+
+    // apply runs the operations of a request as a single change and saves it.
+    fn apply(&mut self, ops: &[Operation], message: &str, new_id: impl FnMut() -> String) -> Result<(), Box<dyn std::error::Error>> {
+        apply_edit(&mut self.project, &mut self.doc, ops, message, new_id)?;
+        save_project(self.backend.as_mut(), &mut self.doc)?;
+        Ok(())
+    }
+
+    // item finds the item a request names by its id, a unique prefix of it, or its path.
+    fn item(&self, reference: &str) -> Result<Rc<Item>, Box<dyn std::error::Error>> {
+        resolve_item(&self.project, None, reference)
+    }
+
+    // etag is made from the sorted heads of the document, so it changes with every change to the project.
+    pub fn etag(&mut self) -> String {
+        let mut heads: Vec<String> = self.doc.get_heads().iter().map(|h| h.to_string()).collect();
+        heads.sort();
+        format!("\"{}\"", heads.join("+"))
+    }
+}
+
+// etag_matches checks an If-Match or If-None-Match header, which may list several tags or be "*".
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+// percent_decode decodes the %XX escapes of a url path segment, which is how an item path with a `/` in it is sent.
+fn percent_decode(segment: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let bytes = segment.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| u8::from_str_radix(std::str::from_utf8(h).ok()?, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(std::str::from_utf8(&out)?.to_string())
+}
+
+// parse_fields reads a request body as a json object, an empty body is an empty object.
+fn parse_fields(body: &[u8]) -> Result<Map<String, Value>, Box<dyn std::error::Error>> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(Map::new());
+    }
+    match serde_json::from_slice(body)? {
+        Value::Object(fields) => Ok(fields),
+        _ => Err(Box::new(AuError::InvalidField(
+            Box::from("body"),
+            Box::from("expected a json object"),
+        ))),
+    }
+}
+
+// status_of picks the http status for an error, looking through nested errors to their cause.
+fn status_of(e: &(dyn std::error::Error + 'static)) -> u16 {
+    if let Some(err) = e.downcast_ref::<AuError>() {
+        return match err {
+            AuError::NoSuchKey(_) => 404,
+            AuError::Cycle(_) | AuError::Ambiguous(_, _) | AuError::InvalidOperation(_, _) => 409,
+            AuError::InvalidField(_, _) => 400,
+            AuError::IncorrectType(_, _) => 500,
+            AuError::NestedError(_, inner) => status_of(inner.as_ref()),
+        };
+    }
+    if e.is::<serde_json::Error>() || e.is::<base64::DecodeError>() || e.is::<std::str::Utf8Error>() {
+        400
+    } else {
+        500
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use automerge::sync::{self, SyncDoc};
+    use automerge::transaction::Transactable;
+    use automerge::AutoCommit;
+    use serde_json::Value;
+
+    use au::storage::init_project;
+    use au::storage::memory::MemoryBackend;
+
+    use crate::{Api, Request, Response};
+
+    fn api() -> Api {
+        let mut backend = MemoryBackend::default();
+        init_project(&mut backend).unwrap();
+        Api::open(Box::new(backend)).unwrap()
+    }
+
+    fn call(api: &mut Api, method: &str, url: &str, body: &str) -> Response {
+        api.handle(&Request {
+            method,
+            url,
+            if_match: None,
+            if_none_match: None,
+            body: body.as_bytes(),
+        })
+    }
+
+    fn json(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    fn create(api: &mut Api, body: &str) -> String {
+        let response = call(api, "POST", "/items", body);
+        assert_eq!(response.status, 201, "{}", String::from_utf8_lossy(&response.body));
+        json(&response)["id"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_create_and_read() {
+        let mut api = api();
+        let inbox = create(&mut api, r#"{"content": "Inbox"}"#);
+        let image = create(
+            &mut api,
+            format!(
+                r#"{{"parent": "{}", "content": "aGk=", "content_encoding": "base64", "content_type": "image/png"}}"#,
+                inbox
+            )
+            .as_str(),
+        );
+
+        let response = call(&mut api, "GET", "/items", "");
+        assert_eq!(response.status, 200);
+        assert_eq!(json(&response)[0]["id"].as_str(), Some(inbox.as_str()));
+        assert!(json(&response)[0].get("content").is_none());
+
+        let response = call(&mut api, "GET", format!("/items/{}/children", inbox).as_str(), "");
+        assert_eq!(json(&response)[0]["id"].as_str(), Some(image.as_str()));
+
+        let response = call(&mut api, "GET", format!("/items/{}", image).as_str(), "");
+        assert_eq!(json(&response)["content"], "aGk=");
+        assert_eq!(json(&response)["content_encoding"], "base64");
+
+        let response = call(&mut api, "GET", format!("/items/{}/content", image).as_str(), "");
+        assert_eq!(response.header("content-type"), Some("image/png"));
+        assert_eq!(response.body, b"hi");
+
+        assert_eq!(call(&mut api, "GET", "/items/NOPE", "").status, 404);
+        assert_eq!(call(&mut api, "GET", "/nothing", "").status, 404);
+        assert_eq!(call(&mut api, "PUT", "/items", "").status, 405);
+        assert_eq!(call(&mut api, "POST", "/items", "not json").status, 400);
+        assert_eq!(call(&mut api, "POST", "/items", r#"{"ref": "$x"}"#).status, 400);
+    }
+
+    #[test]
+    fn test_update_move_delete() {
+        let mut api = api();
+        let a = create(&mut api, r#"{"content": "A"}"#);
+        let b = create(&mut api, r#"{"content": "B"}"#);
+
+        let response = call(
+            &mut api,
+            "PATCH",
+            format!("/items/{}", b).as_str(),
+            format!(r#"{{"content": "B2", "class": "task", "parent": "{}"}}"#, a).as_str(),
+        );
+        assert_eq!(response.status, 200);
+        let record = json(&response);
+        assert_eq!((record["content"].as_str(), record["class"].as_str()), (Some("B2"), Some("task")));
+        assert_eq!(record["parent"].as_str(), Some(a.as_str()));
+
+        let response = call(
+            &mut api,
+            "POST",
+            format!("/items/{}/move", a).as_str(),
+            format!(r#"{{"parent": "{}"}}"#, b).as_str(),
+        );
+        assert_eq!(response.status, 409);
+        assert_eq!(json(&response)["error"].as_str(), Some(format!("'{}': has a cycle", b).as_str()));
+
+        let response = call(
+            &mut api,
+            "POST",
+            format!("/items/{}/move", b).as_str(),
+            r#"{"parent": null, "rank": 5}"#,
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(json(&response)["parent"], Value::Null);
+        assert_eq!(json(&call(&mut api, "GET", "/items", ""))[0]["id"].as_str(), Some(b.as_str()));
+
+        call(
+            &mut api,
+            "POST",
+            format!("/items/{}/move", b).as_str(),
+            format!(r#"{{"parent": "{}"}}"#, a).as_str(),
+        );
+        assert_eq!(call(&mut api, "DELETE", format!("/items/{}", a).as_str(), "").status, 409);
+        assert_eq!(call(&mut api, "DELETE", format!("/items/{}", b).as_str(), "").status, 204);
+        assert_eq!(call(&mut api, "GET", format!("/items/{}", b).as_str(), "").status, 404);
+        assert_eq!(
+            call(&mut api, "PATCH", format!("/items/{}", b).as_str(), r#"{"content": "x"}"#).status,
+            404
+        );
+    }
+
+    #[test]
+    fn test_etags() {
+        let mut api = api();
+        let a = create(&mut api, r#"{"content": "A"}"#);
+        let url = format!("/items/{}", a);
+        let etag = call(&mut api, "GET", url.as_str(), "").header("ETag").unwrap().to_string();
+
+        let mut request = Request {
+            method: "GET",
+            url: url.as_str(),
+            if_match: None,
+            if_none_match: Some(etag.as_str()),
+            body: b"",
+        };
+        assert_eq!(api.handle(&request).status, 304);
+
+        request.method = "PATCH";
+        request.if_none_match = None;
+        request.if_match = Some(etag.as_str());
+        request.body = br#"{"content": "A2"}"#;
+        let response = api.handle(&request);
+        assert_eq!(response.status, 200);
+        assert_ne!(response.header("ETag"), Some(etag.as_str()));
+
+        // the second write was based on a project that has changed since
+        request.body = br#"{"content": "A3"}"#;
+        assert_eq!(api.handle(&request).status, 412);
+        assert_eq!(json(&call(&mut api, "GET", url.as_str(), ""))["content"], "A2");
+    }
+
+    // exchange passes sync messages between the api and a peer until neither has anything more to send.
+    fn exchange(
+        api: &mut Api,
+        ours: &mut sync::State,
+        peer: &mut AutoCommit,
+        theirs: &mut sync::State,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let mut sent = false;
+            if let Some(message) = api.generate_sync(ours) {
+                peer.sync().receive_sync_message(theirs, sync::Message::decode(&message)?)?;
+                sent = true;
+            }
+            if let Some(message) = peer.sync().generate_sync_message(theirs) {
+                api.receive_sync(ours, &message.encode())?;
+                sent = true;
+            }
+            if !sent {
+                return Ok(());
+            }
+        }
+    }
+
+    #[test]
+    fn test_references() {
+        let mut api = api();
+        // items and parents can be named the way the command line names them, here by their summary
+        let inbox = create(&mut api, r#"{"content": "Inbox"}"#);
+        let todo = create(&mut api, r#"{"content": "Todo", "parent": "/inbox"}"#);
+        let record = json(&call(&mut api, "GET", "/items/inbox/children", ""));
+        assert_eq!(record[0]["id"].as_str(), Some(todo.as_str()));
+        let response = call(&mut api, "POST", "/items/inbox%2Ftodo/move", r#"{"parent": "/"}"#);
+        assert_eq!(response.status, 200);
+        assert_eq!(json(&response)["parent"], Value::Null);
+        assert_eq!(call(&mut api, "PATCH", "/items/todo", r#"{"parent": "inbox"}"#).status, 200);
+        assert_eq!(
+            api.project().get_item(todo.as_str()).unwrap().parent.as_deref(),
+            Some(inbox.as_str())
+        );
+        assert_eq!(call(&mut api, "DELETE", "/items/missing", "").status, 404);
+        assert_eq!(call(&mut api, "GET", "/items/%FF", "").status, 400);
+    }
+
+    #[test]
+    fn test_bad_sync() {
+        let mut api = api();
+        let a = create(&mut api, r#"{"content": "A"}"#);
+        let mut peer = AutoCommit::new();
+        let (mut ours, mut theirs) = (sync::State::new(), sync::State::new());
+        exchange(&mut api, &mut ours, &mut peer, &mut theirs).unwrap();
+        assert!(!ours.shared_heads.is_empty());
+
+        // a change that breaks the project is dropped and the sync starts over without it
+        peer.put(automerge::ROOT, "items", "broken").unwrap();
+        peer.commit();
+        assert!(exchange(&mut api, &mut ours, &mut peer, &mut theirs).is_err());
+        assert!(ours.shared_heads.is_empty());
+        assert!(api.project().get_item(a.as_str()).is_some());
+        assert_eq!(call(&mut api, "GET", format!("/items/{}", a).as_str(), "").status, 200);
+
+        // the same peer coming back with a fresh sync state is turned away before its changes are applied again
+        let heads = api.doc.get_heads();
+        let (mut ours, mut theirs) = (sync::State::new(), sync::State::new());
+        assert!(exchange(&mut api, &mut ours, &mut peer, &mut theirs).is_err());
+        assert_eq!(api.doc.get_heads(), heads);
+        assert!(api.project().get_item(a.as_str()).is_some());
+    }
+}
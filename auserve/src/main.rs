@@ -0,0 +1,52 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::Parser;
+
+use au::storage::fs::FsBackend;
+use auserve::server::Server;
+use auserve::Api;
+
+// WATCH_INTERVAL is how often the project directory is checked for changes saved by other processes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Parser)]
+#[command(name = "auserve", about = "Serve an au project over a local HTTP/JSON API")]
+struct Cli {
+    /// The project directory
+    #[arg(short, long, env = "AU_PROJECT", default_value = ".au")]
+    project: PathBuf,
+    /// The address to listen on, only the local machine can connect by default
+    #[arg(short, long, default_value = "127.0.0.1:7373")]
+    listen: SocketAddr,
+    /// The address to accept WebSocket clients for live item events and automerge sync on
+    #[arg(short, long, default_value = "127.0.0.1:7374")]
+    events: SocketAddr,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("auserve: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let backend = FsBackend::open(&cli.project)?;
+    let watcher = backend.watch(WATCH_INTERVAL);
+    let api = Api::open(Box::new(backend))?;
+    let mut server = Server::bind(api, cli.listen, cli.events)?.with_watcher(watcher);
+    eprintln!(
+        "serving {} on http://{} with events on ws://{}",
+        cli.project.display(),
+        server.http_addr()?,
+        server.events_addr()?
+    );
+    server.run()
+}
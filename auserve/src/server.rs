@@ -0,0 +1,156 @@
+/*
+
+Server runs the http api and the WebSocket events from a single thread so that only one place ever touches the
+project. Each poll waits briefly for an http request, answers it, and then lets the event clients catch up, so changes
+made over http reach subscribers straight away. An optional Watcher brings in changes saved by other processes.
+Request bodies are read on a thread of their own and only complete requests reach the poll, so a client that is slow
+to send its body holds up nobody else.
+
+ */
+
+use std::io::{Cursor, Read};
+use std::net::SocketAddr;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use au::storage::fs::Watcher;
+
+use crate::events::Events;
+use crate::{Api, Request, Response};
+
+// MAX_BODY_BYTES bounds how much of a request body is read, larger requests are refused.
+const MAX_BODY_BYTES: u64 = 16 * 1024 * 1024;
+// POLL_INTERVAL is the longest the event clients wait while there are no http requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// Received is an http request with its body read, or the error response if the body couldn't be.
+struct Received {
+    request: tiny_http::Request,
+    body: Result<Vec<u8>, Response>,
+}
+
+pub struct Server {
+    api: Api,
+    http: Arc<tiny_http::Server>,
+    received: Receiver<Received>,
+    events: Events,
+    watcher: Option<Watcher>,
+}
+
+impl Server {
+    // bind listens for http requests on one address and WebSocket clients on another.
+    pub fn bind(mut api: Api, http: SocketAddr, events: SocketAddr) -> Result<Server, Box<dyn std::error::Error>> {
+        let events = Events::bind(events, &mut api)?;
+        let http = Arc::new(tiny_http::Server::http(http).map_err(|e| e as Box<dyn std::error::Error>)?);
+        let (tx, received) = channel();
+        let incoming = http.clone();
+        thread::spawn(move || receive(incoming.as_ref(), tx));
+        Ok(Server {
+            api,
+            http,
+            received,
+            events,
+            watcher: None,
+        })
+    }
+
+    // with_watcher refreshes the project whenever the watcher sees the backend change.
+    pub fn with_watcher(mut self, watcher: Watcher) -> Server {
+        self.watcher = Some(watcher);
+        self
+    }
+
+    pub fn http_addr(&self) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+        self.http
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| Box::from("not listening on an ip address"))
+    }
+
+    pub fn events_addr(&self) -> Result<SocketAddr, Box<dyn std::error::Error>> {
+        self.events.local_addr()
+    }
+
+    // run serves until listening fails.
+    pub fn run(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            self.poll(POLL_INTERVAL)?;
+        }
+    }
+
+    // poll answers at most one http request, waiting up to the timeout for it, and then polls the event clients.
+    pub fn poll(&mut self, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+        match self.received.recv_timeout(timeout) {
+            Ok(received) => {
+                let response = respond(&mut self.api, &received.request, received.body);
+                // a client going away only affects that client
+                let _ = received.request.respond(response);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Err(Box::from("http server stopped listening")),
+        }
+        // a failed refresh is not fatal, the next http request refreshes again and reports the error
+        if self.watcher.as_ref().is_some_and(|w| w.changed()) {
+            let _ = self.api.refresh();
+        }
+        self.events.poll(&mut self.api)
+    }
+}
+
+// receive hands each incoming request to a thread that reads its body and passes it on to the poll.
+fn receive(http: &tiny_http::Server, tx: Sender<Received>) {
+    for mut request in http.incoming_requests() {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            let body = read_body(&mut request);
+            let _ = tx.send(Received { request, body });
+        });
+    }
+}
+
+fn read_body(request: &mut tiny_http::Request) -> Result<Vec<u8>, Response> {
+    let mut body: Vec<u8> = Vec::new();
+    if let Err(e) = request.as_reader().take(MAX_BODY_BYTES + 1).read_to_end(&mut body) {
+        return Err(Response::error(400, e.to_string().as_str()));
+    }
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(Response::error(413, "request body too large"));
+    }
+    Ok(body)
+}
+
+fn respond(api: &mut Api, request: &tiny_http::Request, body: Result<Vec<u8>, Response>) -> tiny_http::Response<Cursor<Vec<u8>>> {
+    let body = match body {
+        Ok(body) => body,
+        Err(response) => return to_http(response),
+    };
+    let header = |name: &'static str| {
+        request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.as_str().to_string())
+    };
+    let (if_match, if_none_match) = (header("If-Match"), header("If-None-Match"));
+    let response = api.handle(&Request {
+        method: request.method().as_str(),
+        url: request.url(),
+        if_match: if_match.as_deref(),
+        if_none_match: if_none_match.as_deref(),
+        body: body.as_slice(),
+    });
+    to_http(response)
+}
+
+fn to_http(response: Response) -> tiny_http::Response<Cursor<Vec<u8>>> {
+    let mut out = tiny_http::Response::from_data(response.body).with_status_code(response.status);
+    for (name, value) in response.headers {
+        // header values come from item content types, which are not guaranteed to be valid header values
+        if let Ok(header) = tiny_http::Header::from_bytes(name.as_bytes(), value.as_bytes()) {
+            out.add_header(header);
+        }
+    }
+    out
+}
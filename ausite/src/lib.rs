@@ -0,0 +1,406 @@
+/*
+
+ausite renders a read-only static html view of a project.
+
+- every item reachable from the roots gets its own page named after its id
+- markdown items are rendered with any raw html in them escaped, other text items are shown preformatted
+- binary items are written out as downloadable attachments under files/
+- each page gets breadcrumbs built from the parent chain
+- search-index.js holds the data for the client side search box, it is loaded as a script rather than fetched so
+  that the output works straight from the filesystem
+
+ */
+
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use au::item::{Item, Project};
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use serde::Serialize;
+
+const SUMMARY_WIDTH: usize = 80;
+const FILES_DIR: &str = "files";
+const INDEX_PAGE: &str = "index.html";
+const STYLE_FILE: &str = "style.css";
+const SEARCH_FILE: &str = "search.js";
+const SEARCH_INDEX_FILE: &str = "search-index.js";
+const CONTENT_TYPE_TEXT_PREFIX: &str = "text/";
+const CONTENT_TYPE_MARKDOWN: &str = "text/markdown";
+const SAFE_URL_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+const STYLE: &str = include_str!("style.css");
+const SEARCH: &str = include_str!("search.js");
+
+// SearchEntry is a single record in the client side search index.
+#[derive(Serialize)]
+struct SearchEntry {
+    id: Rc<str>,
+    title: Box<str>,
+    page: Box<str>,
+    text: Box<str>,
+}
+
+// generate writes the site for the project into the output directory and returns the number of item pages written.
+// The output directory is created if it does not exist and existing files with the same names are overwritten.
+pub fn generate(project: &Project, out_dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    fs::create_dir_all(out_dir.join(FILES_DIR))?;
+
+    let roots = project.list_children(None);
+    let mut search_index: Vec<SearchEntry> = Vec::new();
+    let mut written = 0;
+
+    // walk depth first from the roots so that the search index follows the display order
+    let mut stack: Vec<Rc<Item>> = roots.iter().rev().cloned().collect();
+    while let Some(item) = stack.pop() {
+        let children = project.list_children(Some(item.id.as_ref()));
+        let body = render_item(project, &item, &children, out_dir)?;
+        fs::write(out_dir.join(&*page_name(&item.id)), page(&item.summary(SUMMARY_WIDTH), &body))?;
+        search_index.push(SearchEntry {
+            id: item.id.clone(),
+            title: item.summary(SUMMARY_WIDTH),
+            page: page_name(&item.id),
+            text: text_content(&item).map(Box::from).unwrap_or_default(),
+        });
+        written += 1;
+        stack.extend(children.into_iter().rev());
+    }
+
+    let mut body = String::new();
+    body.push_str("<h1>Items</h1>\n");
+    body.push_str(&render_children(&roots));
+    fs::write(out_dir.join(INDEX_PAGE), page("Items", &body))?;
+    fs::write(out_dir.join(STYLE_FILE), STYLE)?;
+    fs::write(out_dir.join(SEARCH_FILE), SEARCH)?;
+    fs::write(
+        out_dir.join(SEARCH_INDEX_FILE),
+        format!("window.AU_SEARCH_INDEX = {};\n", serde_json::to_string(&search_index)?),
+    )?;
+    Ok(written)
+}
+
+fn render_item(project: &Project, item: &Item, children: &[Rc<Item>], out_dir: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let mut body = String::new();
+    body.push_str(&render_breadcrumbs(project, item));
+    writeln!(body, "<h1>{}</h1>", escape_html(&item.summary(SUMMARY_WIDTH)))?;
+
+    body.push_str("<dl class=\"meta\">\n");
+    writeln!(body, "<dt>id</dt><dd>{}</dd>", escape_html(&item.id))?;
+    if let Some(ref class) = item.class {
+        writeln!(body, "<dt>class</dt><dd>{}</dd>", escape_html(class))?;
+    }
+    writeln!(body, "<dt>content type</dt><dd>{}</dd>", escape_html(&item.content_type))?;
+    writeln!(body, "<dt>at</dt><dd>{}</dd>", item.at)?;
+    body.push_str("</dl>\n");
+
+    match text_content(item) {
+        Some(text) if item.content_type.starts_with(CONTENT_TYPE_MARKDOWN) => {
+            body.push_str("<article class=\"content\">\n");
+            push_markdown(&mut body, text);
+            body.push_str("</article>\n");
+        }
+        Some(text) => {
+            writeln!(body, "<pre class=\"content\">{}</pre>", escape_html(text))?;
+        }
+        None => {
+            let file_name = file_stem(&item.id);
+            fs::write(out_dir.join(FILES_DIR).join(&file_name), item.content.as_ref())?;
+            writeln!(
+                body,
+                "<p class=\"attachment\"><a href=\"{}/{}\" download=\"{}\">Download</a> ({} bytes)</p>",
+                FILES_DIR,
+                escape_html(&file_name),
+                escape_html(&file_name),
+                item.content.len()
+            )?;
+        }
+    }
+
+    if !children.is_empty() {
+        body.push_str("<h2>Children</h2>\n");
+        body.push_str(&render_children(children));
+    }
+    Ok(body)
+}
+
+fn render_breadcrumbs(project: &Project, item: &Item) -> String {
+    let mut ancestors: Vec<Rc<Item>> = Vec::new();
+    let mut seen: HashSet<Rc<str>> = HashSet::new();
+    let mut next = item.parent.clone();
+    // merged documents are not guaranteed to be acyclic so stop if we ever revisit an item
+    while let Some(parent_id) = next {
+        if !seen.insert(parent_id.clone()) {
+            break;
+        }
+        match project.get_item(parent_id.as_ref()) {
+            Some(parent) => {
+                next = parent.parent.clone();
+                ancestors.push(parent);
+            }
+            None => break,
+        }
+    }
+
+    let mut out = String::from("<nav class=\"breadcrumbs\"><a href=\"index.html\">Items</a>");
+    for ancestor in ancestors.iter().rev() {
+        let _ = write!(
+            out,
+            " / <a href=\"{}\">{}</a>",
+            escape_html(&page_name(&ancestor.id)),
+            escape_html(&ancestor.summary(SUMMARY_WIDTH))
+        );
+    }
+    out.push_str("</nav>\n");
+    out
+}
+
+fn render_children(children: &[Rc<Item>]) -> String {
+    let mut out = String::from("<ul class=\"children\">\n");
+    for child in children {
+        let _ = writeln!(
+            out,
+            "<li><a href=\"{}\">{}</a></li>",
+            escape_html(&page_name(&child.id)),
+            escape_html(&child.summary(SUMMARY_WIDTH))
+        );
+    }
+    out.push_str("</ul>\n");
+    out
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<link rel="stylesheet" href="{}">
+</head>
+<body>
+<form class="search" onsubmit="return false"><input id="search" type="search" placeholder="Search" autocomplete="off"></form>
+<ul id="search-results"></ul>
+<main>
+{}</main>
+<script src="{}"></script>
+<script src="{}"></script>
+</body>
+</html>
+"#,
+        escape_html(title),
+        STYLE_FILE,
+        body,
+        SEARCH_INDEX_FILE,
+        SEARCH_FILE
+    )
+}
+
+// push_markdown renders markdown with any html in it shown as text and links and images only to safe urls, since item
+// content is not trusted to run in the page.
+fn push_markdown(body: &mut String, text: &str) {
+    let events = Parser::new_ext(text, Options::all()).map(|event| match event {
+        Event::Html(s) | Event::InlineHtml(s) => Event::Text(s),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) if !is_safe_url(&dest_url) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: CowStr::Borrowed(""),
+            title,
+            id,
+        }),
+        event => event,
+    });
+    html::push_html(body, events);
+}
+
+// is_safe_url accepts relative urls and those with one of the SAFE_URL_SCHEMES. Anything with a colon before the path
+// is taken to have a scheme, so that oddly spelt ones such as "JavaScript:" or " javascript:" are refused too.
+fn is_safe_url(url: &str) -> bool {
+    let end = url.find(['/', '?', '#']).unwrap_or(url.len());
+    match url[..end].split_once(':') {
+        Some((scheme, _)) => SAFE_URL_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()),
+        None => true,
+    }
+}
+
+// text_content returns the content as a string if the item is a text type and the content is valid utf-8.
+fn text_content(item: &Item) -> Option<&str> {
+    if item.content_type.starts_with(CONTENT_TYPE_TEXT_PREFIX) {
+        std::str::from_utf8(item.content.as_ref()).ok()
+    } else {
+        None
+    }
+}
+
+fn page_name(id: &str) -> Box<str> {
+    Box::from(file_stem(id) + ".html")
+}
+
+// file_stem converts an item id into a file name that is safe to use on any filesystem and in a url.
+fn file_stem(id: &str) -> String {
+    let mut out = String::with_capacity(id.len());
+    for b in id.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            out.push(b as char);
+        } else {
+            let _ = write!(out, "~{:02X}", b);
+        }
+    }
+    out
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::rc::Rc;
+
+    use automerge::AutoCommit;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use au::item::{Item, Project};
+
+    use crate::{escape_html, generate, page_name, push_markdown};
+
+    #[test]
+    fn test_page_name() {
+        assert_eq!(page_name("7KQ2M9XA").as_ref(), "7KQ2M9XA.html");
+        assert_eq!(page_name("a/b c").as_ref(), "a~2Fb~20c.html");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            escape_html("<a href=\"x\">&'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_push_markdown() {
+        let mut body = String::new();
+        push_markdown(&mut body, "<script>alert(1)</script>\n\nhi <img src=x onerror=alert(1)> *there*");
+        assert!(!body.contains("<script"));
+        assert!(!body.contains("<img"));
+        assert!(body.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(body.contains("hi &lt;img src=x onerror=alert(1)&gt; <em>there</em>"));
+
+        let mut body = String::new();
+        push_markdown(
+            &mut body,
+            "[a](javascript:alert(1)) [b](JavaScript:alert(1)) ![c](javascript:alert(1)) [d](<java\tscript:alert(1)>)",
+        );
+        assert!(!body.to_ascii_lowercase().contains("script:"), "{}", body);
+        assert!(body.contains("<a href=\"\">a</a>"));
+        assert!(body.contains("<img src=\"\" alt=\"c\" />"));
+
+        let mut body = String::new();
+        push_markdown(
+            &mut body,
+            "[a](https://example.com/x) [b](mailto:a@example.com) [c](other.html#top) [d](/x:y)",
+        );
+        for url in ["https://example.com/x", "mailto:a@example.com", "other.html#top", "/x:y"] {
+            assert!(body.contains(format!("href=\"{}\"", url).as_str()), "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_generate_tree() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        let item_a = Item {
+            id: Rc::from("item-a"),
+            content_type: Rc::from("text/markdown"),
+            content: Rc::from("# Launch\n\nSome *notes* <here>".as_bytes()),
+            ..Default::default()
+        };
+        let item_b = Item {
+            id: Rc::from("item-b"),
+            parent: Some(Rc::from("item-a")),
+            content_type: Rc::from("application/octet-stream"),
+            content: Rc::from(vec![0u8, 1, 2, 3]),
+            ..Default::default()
+        };
+        let item_c = Item {
+            id: Rc::from("item-c"),
+            parent: Some(Rc::from("item-a")),
+            content: Rc::from("plain <text>".as_bytes()),
+            ..Default::default()
+        };
+        project
+            .with_item(&item_a, &mut doc)
+            .unwrap()
+            .with_item(&item_b, &mut doc)
+            .unwrap()
+            .with_item(&item_c, &mut doc)
+            .unwrap();
+
+        let out = tempfile::tempdir().unwrap();
+        assert_eq!(generate(&project, out.path()).unwrap(), 3);
+
+        let page_a = fs::read_to_string(out.path().join("item-a.html")).unwrap();
+        assert!(page_a.contains("<h1>Launch</h1>"));
+        assert!(page_a.contains("<em>notes</em>"));
+        assert!(page_a.contains("href=\"item-b.html\""));
+        assert!(page_a.contains("href=\"item-c.html\""));
+
+        let page_b = fs::read_to_string(out.path().join("item-b.html")).unwrap();
+        assert!(page_b.contains("<a href=\"item-a.html\"># Launch</a>"));
+        assert!(page_b.contains("download=\"item-b\""));
+        assert_eq!(fs::read(out.path().join("files").join("item-b")).unwrap(), vec![0u8, 1, 2, 3]);
+
+        let page_c = fs::read_to_string(out.path().join("item-c.html")).unwrap();
+        assert!(page_c.contains("<pre class=\"content\">plain &lt;text&gt;</pre>"));
+
+        let index = fs::read_to_string(out.path().join("index.html")).unwrap();
+        assert!(index.contains("href=\"item-a.html\""));
+        assert!(!index.contains("href=\"item-b.html\""));
+
+        let search_index = fs::read_to_string(out.path().join("search-index.js")).unwrap();
+        assert!(search_index.starts_with("window.AU_SEARCH_INDEX = [{\"id\":\"item-a\""));
+        assert!(out.path().join("search.js").exists());
+        assert!(out.path().join("style.css").exists());
+    }
+
+    #[test]
+    fn test_generate_mock() {
+        let mut doc = AutoCommit::new();
+        let mut project = Project::default();
+        for x in aumock::mock_items(StdRng::seed_from_u64(42), 50) {
+            project.with_item(&x, &mut doc).unwrap();
+        }
+        let out = tempfile::tempdir().unwrap();
+        assert_eq!(generate(&project, out.path()).unwrap(), 50);
+    }
+}
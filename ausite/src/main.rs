@@ -0,0 +1,31 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use automerge::Automerge;
+
+use au::item::decode_project;
+
+// Usage: ausite <project.automerge> <output-dir>
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <project.automerge> <output-dir>", args[0]);
+        return ExitCode::from(2);
+    }
+    match run(Path::new(&args[1]), Path::new(&args[2])) {
+        Ok(n) => {
+            println!("wrote {} item pages to {}", n, args[2]);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(source: &Path, out_dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+    let doc = Automerge::load(&std::fs::read(source)?)?;
+    let project = decode_project(&doc)?;
+    ausite::generate(&project, out_dir)
+}
@@ -0,0 +1,30 @@
+// Client side search over the generated search index. Every term in the query must appear in either the title or the
+// text of an item for it to match.
+(function () {
+    var input = document.getElementById("search");
+    var results = document.getElementById("search-results");
+    var index = window.AU_SEARCH_INDEX || [];
+    var limit = 50;
+
+    input.addEventListener("input", function () {
+        var terms = input.value.toLowerCase().split(/\s+/).filter(function (t) { return t.length > 0; });
+        results.innerHTML = "";
+        if (terms.length === 0) {
+            return;
+        }
+        var found = 0;
+        for (var i = 0; i < index.length && found < limit; i++) {
+            var entry = index[i];
+            var haystack = (entry.title + "\n" + entry.text).toLowerCase();
+            if (terms.every(function (t) { return haystack.indexOf(t) >= 0; })) {
+                var li = document.createElement("li");
+                var a = document.createElement("a");
+                a.href = entry.page;
+                a.textContent = entry.title;
+                li.appendChild(a);
+                results.appendChild(li);
+                found++;
+            }
+        }
+    });
+})();